@@ -0,0 +1,64 @@
+use bitcoin::secp256k1::{rand, Keypair, Secp256k1};
+use bitcoin::taproot::{LeafVersion, TaprootSpendInfo};
+use bitcoin::ScriptBuf;
+use criterion::{criterion_group, criterion_main, Criterion};
+use toy_bitvm::circuit::wire::HashCommitment;
+use toy_bitvm::circuit::{chunk::ChunkLimits, Circuit};
+use toy_bitvm::transactions::{
+    generate_2_of_2_script, generate_anti_contradiction_script,
+    generate_compact_equivocation_address_and_info, generate_equivoation_address_and_info,
+};
+
+fn control_block_size(tree_info: &TaprootSpendInfo, script: &ScriptBuf) -> usize {
+    tree_info
+        .control_block(&(script.clone(), LeafVersion::TapScript))
+        .unwrap()
+        .size()
+}
+
+fn equivocation_trees(c: &mut Criterion) {
+    let secp = Secp256k1::new();
+    let prover_pk = Keypair::new(&secp, &mut rand::thread_rng())
+        .x_only_public_key()
+        .0;
+    let verifier_pk = Keypair::new(&secp, &mut rand::thread_rng())
+        .x_only_public_key()
+        .0;
+    let circuit: Circuit =
+        Circuit::from_bristol("bristol/add.txt", None).into_chunks(&ChunkLimits::default());
+
+    // Control block sizes are what a spend of each leaf pays for, so print them next to the timings
+    let (_, full) = generate_equivoation_address_and_info(&secp, &circuit, prover_pk, verifier_pk);
+    let (_, compact) =
+        generate_compact_equivocation_address_and_info(&secp, &circuit, prover_pk, verifier_pk);
+    let revealed = circuit.get_revealed_wires();
+    let wire_script = generate_anti_contradiction_script::<HashCommitment>(
+        &revealed[revealed.len() - 1].lock().unwrap().commitment,
+        verifier_pk,
+    );
+    let musig_script = generate_2_of_2_script(prover_pk, verifier_pk);
+    println!(
+        "{} wires, {} revealed. Anti-contradiction control block: {} bytes full, {} compact. 2-of-2 control block: {} bytes full, {} compact.",
+        circuit.wires.len(),
+        revealed.len(),
+        control_block_size(&full, &wire_script),
+        control_block_size(&compact, &wire_script),
+        control_block_size(&full, &musig_script),
+        control_block_size(&compact, &musig_script),
+    );
+
+    let mut group = c.benchmark_group("equivocation address");
+    group.sample_size(10);
+    group.bench_function("all wires", |b| {
+        b.iter(|| generate_equivoation_address_and_info(&secp, &circuit, prover_pk, verifier_pk))
+    });
+    group.bench_function("revealed wires", |b| {
+        b.iter(|| {
+            generate_compact_equivocation_address_and_info(&secp, &circuit, prover_pk, verifier_pk)
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, equivocation_trees);
+criterion_main!(benches);
@@ -1,23 +1,43 @@
+use std::sync::Arc;
+
+use bitcoin::bip32::Xpriv;
 use bitcoin::hashes::sha256;
 use bitcoin::TapNodeHash;
 use bitcoin::{
     hashes::Hash,
-    secp256k1::{
-        rand, schnorr::Signature, All, Keypair, Message, Secp256k1, SecretKey, XOnlyPublicKey,
-    },
-    Address, TapSighash, TapTweakHash,
+    secp256k1::{rand, schnorr::Signature, Secp256k1, XOnlyPublicKey},
+    Address, TapSighash,
 };
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 
 use crate::circuit::wire::{HashValue, PreimageValue};
+use crate::musig::{PartialSignature, SecretNonce, SigningSession};
+use crate::secret::Secret;
+use crate::signer::derivation::{derive_signer, master_key_from_env, KeyRole};
+use crate::signer::{signer_from_env, InMemorySigner};
+use crate::traits::signer::{Signer, SignerError};
+
+// What an actor tells the other side about its keys at the start of a game
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ActorPublicKeys {
+    pub funding: XOnlyPublicKey,
+    pub presign: XOnlyPublicKey,
+    pub response: XOnlyPublicKey,
+}
 
 pub struct Actor {
-    secp: Secp256k1<All>,
-    keypair: Keypair,
-    pub secret_key: SecretKey,
+    funding_signer: Arc<dyn Signer>,
+    presign_signer: Arc<dyn Signer>,
+    response_signer: Arc<dyn Signer>,
+    // Key of the 2-of-2 leaves, the cooperative close and the actor's other leaves
     pub public_key: XOnlyPublicKey,
+    pub response_public_key: XOnlyPublicKey,
+    pub funding_public_key: XOnlyPublicKey,
+    // Key path address of the funding key
     pub address: Address,
-    challenge_preimages: Vec<Vec<PreimageValue>>,
+    // Revealing one lets the verifier challenge, so they are kept until the actor is dropped
+    challenge_preimages: Vec<Secret<Vec<PreimageValue>>>,
     challenge_hashes: Vec<Vec<HashValue>>,
     signatures: Vec<Signature>,
 }
@@ -29,19 +49,65 @@ impl Default for Actor {
 }
 
 impl Actor {
+    // Actor with fresh keys that only live in memory
     pub fn new() -> Self {
-        let secp: Secp256k1<All> = Secp256k1::new();
-        let mut rng = rand::thread_rng();
-        let (sk, _pk) = secp.generate_keypair(&mut rng);
-        let keypair = Keypair::from_secret_key(&secp, &sk);
-        let (xonly, _parity) = XOnlyPublicKey::from_keypair(&keypair);
-        let address = Address::p2tr(&secp, xonly, None, bitcoin::Network::Regtest);
+        Self::with_signers(
+            Arc::new(InMemorySigner::random()),
+            Arc::new(InMemorySigner::random()),
+            Arc::new(InMemorySigner::random()),
+        )
+    }
+
+    // Actor using a single key for every role
+    pub fn with_signer(signer: Box<dyn Signer>) -> Self {
+        let signer: Arc<dyn Signer> = Arc::from(signer);
+        Self::with_signers(signer.clone(), signer.clone(), signer)
+    }
+
+    // Actor with the keys of the given session and game derived from the master key, so that all
+    // of them can be recovered from a single seed
+    pub fn from_master_key(master: &Xpriv, session: u32, game: u32) -> Self {
+        let secp = Secp256k1::new();
+        let [funding, presign, response] = KeyRole::ALL.map(|role| {
+            Arc::new(derive_signer(&secp, master, session, role, game)) as Arc<dyn Signer>
+        });
+        Self::with_signers(funding, presign, response)
+    }
+
+    // Keys derived from the seed in the environment if there is one, otherwise the signer
+    // configured there
+    pub fn from_env() -> Self {
+        Self::from_env_for_game(0)
+    }
+
+    // Same, with keys of their own for every game played in the session, e.g. one per verifier
+    pub fn from_env_for_game(game: u32) -> Self {
+        match master_key_from_env() {
+            Some((master, session)) => Self::from_master_key(&master, session, game),
+            None => Self::with_signer(signer_from_env()),
+        }
+    }
+
+    pub fn with_signers(
+        funding_signer: Arc<dyn Signer>,
+        presign_signer: Arc<dyn Signer>,
+        response_signer: Arc<dyn Signer>,
+    ) -> Self {
+        let funding_public_key = funding_signer.public_key();
+        let address = Address::p2tr(
+            &Secp256k1::verification_only(),
+            funding_public_key,
+            None,
+            bitcoin::Network::Regtest,
+        );
 
         Actor {
-            secp,
-            keypair,
-            secret_key: keypair.secret_key(),
-            public_key: xonly,
+            public_key: presign_signer.public_key(),
+            response_public_key: response_signer.public_key(),
+            funding_public_key,
+            funding_signer,
+            presign_signer,
+            response_signer,
             address,
             challenge_preimages: Vec::new(),
             challenge_hashes: Vec::new(),
@@ -49,39 +115,60 @@ impl Actor {
         }
     }
 
+    pub fn public_keys(&self) -> ActorPublicKeys {
+        ActorPublicKeys {
+            funding: self.funding_public_key,
+            presign: self.public_key,
+            response: self.response_public_key,
+        }
+    }
+
+    // Signer of the funding key, for whatever spends the actor's anchors in the background
+    pub fn funding_signer(&self) -> Arc<dyn Signer> {
+        self.funding_signer.clone()
+    }
+
+    // Key path spend of an output of the funding key, like the funding output or an anchor
     pub fn sign_with_tweak(
         &self,
         sighash: TapSighash,
         merkle_root: Option<TapNodeHash>,
-    ) -> Signature {
-        self.secp.sign_schnorr_with_rng(
-            &Message::from_digest_slice(sighash.as_byte_array()).expect("should be hash"),
-            &self
-                .keypair
-                .add_xonly_tweak(
-                    &self.secp,
-                    &TapTweakHash::from_key_and_tweak(self.public_key, merkle_root).to_scalar(),
-                )
-                .unwrap(),
-            &mut rand::thread_rng(),
-        )
+    ) -> Result<Signature, SignerError> {
+        self.funding_signer.sign_with_tweak(sighash, merkle_root)
     }
 
-    pub fn sign(&self, sighash: TapSighash) -> Signature {
-        self.secp.sign_schnorr_with_rng(
-            &Message::from_digest_slice(sighash.as_byte_array()).expect("should be hash"),
-            &self.keypair,
-            &mut rand::thread_rng(),
-        )
+    pub fn sign(&self, sighash: TapSighash) -> Result<Signature, SignerError> {
+        self.presign_signer.sign(sighash)
+    }
+
+    pub fn sign_response(&self, sighash: TapSighash) -> Result<Signature, SignerError> {
+        self.response_signer.sign(sighash)
+    }
+
+    // Whether the actor can co-sign the cooperative close, which is decided before the game
+    // starts so that a close is never asked of an actor that cannot sign it
+    pub fn can_close(&self) -> bool {
+        self.presign_signer.supports_musig()
+    }
+
+    pub fn musig_partial_sign(
+        &self,
+        session: &SigningSession,
+        secret_nonce: SecretNonce,
+    ) -> PartialSignature {
+        self.presign_signer
+            .musig_partial_sign(session, secret_nonce)
+            .expect("Signer cannot take part in a MuSig session")
     }
 
     pub fn generate_challenge_hashes(&mut self, num_gates: usize) -> Vec<HashValue> {
         let mut challenge_hashes: Vec<HashValue> = Vec::new();
         let mut rng = rand::thread_rng();
-        let mut preimages = Vec::new();
+        // Allocated up front so that growing it leaves no copies of the preimages behind
+        let mut preimages = Secret::new(Vec::with_capacity(num_gates));
         for _ in 0..num_gates {
             let preimage: PreimageValue = rng.gen();
-            preimages.push(preimage);
+            preimages.expose_secret_mut().push(preimage);
             challenge_hashes.push(sha256::Hash::hash(&preimage).to_byte_array());
         }
         self.challenge_preimages.push(preimages);
@@ -98,7 +185,7 @@ impl Actor {
     }
 
     pub fn get_challenge_preimage(&self, index: usize, gate_num: usize) -> PreimageValue {
-        self.challenge_preimages[index][gate_num]
+        self.challenge_preimages[index].expose_secret()[gate_num]
     }
 
     pub fn add_signature(&mut self, signature: Signature) {
@@ -114,10 +201,30 @@ impl Actor {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_actor_from_master_key() {
+        let master = crate::signer::derivation::master_key(&[7; 32]);
+        let actor = Actor::from_master_key(&master, 3, 0);
+        let restarted = Actor::from_master_key(&master, 3, 0);
+        assert_eq!(actor.public_keys(), restarted.public_keys());
+        assert_eq!(actor.address, restarted.address);
+
+        let keys = actor.public_keys();
+        assert!(keys.funding != keys.presign && keys.presign != keys.response);
+        assert_ne!(
+            Actor::from_master_key(&master, 4, 0).public_keys(),
+            actor.public_keys()
+        );
+        // Every game against another verifier too
+        assert_ne!(
+            Actor::from_master_key(&master, 3, 1).public_keys(),
+            actor.public_keys()
+        );
+    }
+
     #[test]
     fn test_prover() {
         let prover = Actor::new();
-        println!("secret key: {:?}", prover.secret_key);
         println!("public key: {:?}", prover.public_key);
     }
 }
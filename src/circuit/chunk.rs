@@ -0,0 +1,340 @@
+use std::collections::{HashMap, HashSet};
+
+use bitcoin::opcodes::all::{
+    OP_2DROP, OP_DROP, OP_EQUALVERIFY, OP_FROMALTSTACK, OP_PICK, OP_ROLL, OP_SHA256, OP_TOALTSTACK,
+};
+use bitcoin::script::Builder;
+use bitcoin::ScriptBuf;
+
+use super::wire::{HashCommitment, HashValue};
+use crate::traits::bit_commitment::BitCommitment;
+use crate::traits::gate::{wire_accessors, GateTrait, Wires};
+
+// Limits a chunk has to respect so that its response leaf stays spendable. The stack limit is the
+// consensus one, counting the altstack too. Tapscript has no script size limit of its own, so the
+// default keeps response transactions about as large as the legacy 10,000 byte limit allows.
+#[derive(Clone, Copy, Debug)]
+pub struct ChunkLimits {
+    pub max_script_size: usize,
+    pub max_stack_size: usize,
+}
+
+impl Default for ChunkLimits {
+    fn default() -> Self {
+        ChunkLimits {
+            max_script_size: 10_000,
+            max_stack_size: 1000,
+        }
+    }
+}
+
+// A contiguous run of gates proven by a single response script. Only the wires crossing the chunk
+// boundary are committed to, the intermediate ones are recomputed on the stack. Every wire gets a
+// slot, and the script keeps a model of which slot sits where on the stack to pick gate inputs.
+pub struct ChunkGate<C: BitCommitment = HashCommitment> {
+    pub gates: Vec<Box<dyn GateTrait<C> + Send + Sync>>,
+    pub input_wires: Wires<C>,
+    pub output_wires: Wires<C>,
+    input_slots: Vec<usize>,
+    output_slots: Vec<usize>,
+    gate_slots: Vec<(Vec<usize>, Vec<usize>)>,
+    num_slots: usize,
+}
+
+impl<C: BitCommitment> ChunkGate<C> {
+    // Wires written by the chunk become its outputs if is_output holds for their index.
+    pub fn new<F>(mut gates: Vec<Box<dyn GateTrait<C> + Send + Sync>>, is_output: F) -> Self
+    where
+        F: Fn(usize) -> bool,
+    {
+        let mut slots = HashMap::<usize, usize>::new();
+        let mut input_wires = Vec::new();
+        let mut input_slots = Vec::new();
+        let mut output_wires = Vec::new();
+        let mut output_slots = Vec::new();
+        let mut gate_slots = Vec::new();
+        for gate in gates.iter_mut() {
+            let mut gate_input_slots = Vec::new();
+            for wire_arcm in gate.get_input_wires().iter() {
+                let index = wire_arcm.lock().unwrap().index.unwrap();
+                let next_slot = slots.len();
+                let slot = *slots.entry(index).or_insert_with(|| {
+                    input_wires.push(wire_arcm.clone());
+                    input_slots.push(next_slot);
+                    next_slot
+                });
+                gate_input_slots.push(slot);
+            }
+            let mut gate_output_slots = Vec::new();
+            for wire_arcm in gate.get_output_wires().iter() {
+                let index = wire_arcm.lock().unwrap().index.unwrap();
+                let slot = slots.len();
+                assert!(
+                    slots.insert(index, slot).is_none(),
+                    "wire {} is written after being used",
+                    index
+                );
+                if is_output(index) {
+                    output_wires.push(wire_arcm.clone());
+                    output_slots.push(slot);
+                }
+                gate_output_slots.push(slot);
+            }
+            gate_slots.push((gate_input_slots, gate_output_slots));
+        }
+        ChunkGate {
+            gates,
+            input_wires,
+            output_wires,
+            input_slots,
+            output_slots,
+            gate_slots,
+            num_slots: slots.len(),
+        }
+    }
+
+    // Evaluates the gates on the stack starting from the input slots, returning the stack model
+    // (the slot held by each stack position, bottom first) and the peak number of elements used.
+    fn add_gates_script(&self, builder: Builder) -> (Builder, Vec<usize>, usize) {
+        let mut builder = builder;
+        let mut model = self.input_slots.clone();
+        let mut peak = model.len();
+        for (gate, (input_slots, output_slots)) in self.gates.iter().zip(self.gate_slots.iter()) {
+            for (i, slot) in input_slots.iter().enumerate() {
+                let position = model.iter().position(|s| s == slot).unwrap();
+                builder = builder
+                    .push_int((model.len() - 1 - position + i) as i64)
+                    .push_opcode(OP_PICK);
+            }
+            peak = peak.max(model.len() + input_slots.len() + gate.compute_stack_overhead());
+            builder = gate.add_compute_script(builder);
+            model.extend(output_slots);
+        }
+        (builder, model, peak)
+    }
+
+    fn add_drop_script(builder: Builder, count: usize) -> Builder {
+        let mut builder = builder;
+        for _ in 0..count / 2 {
+            builder = builder.push_opcode(OP_2DROP);
+        }
+        if count % 2 == 1 {
+            builder = builder.push_opcode(OP_DROP);
+        }
+        builder
+    }
+
+    // Upper bound on the stack and altstack elements used while executing the response leaf,
+    // including the signature checked at its end.
+    pub fn max_stack_size(&self) -> usize {
+        let witness_len =
+            (self.input_wires.len() + self.output_wires.len()) * C::REVEAL_WITNESS_LEN + 2;
+        let (_, _, peak) = self.add_gates_script(Builder::new());
+        // Both the committed and the computed outputs end up in the altstack at some point
+        (witness_len + C::SCRIPT_STACK_OVERHEAD).max(1 + 2 * self.output_wires.len() + peak + 1)
+    }
+
+    pub fn fits(&self, limits: &ChunkLimits) -> bool {
+        self.create_response_script([0; 32]).len() <= limits.max_script_size
+            && self.max_stack_size() <= limits.max_stack_size
+    }
+}
+
+impl<C: BitCommitment> GateTrait<C> for ChunkGate<C> {
+    fn get_input_size(&self) -> usize {
+        self.input_wires.len()
+    }
+
+    fn get_output_size(&self) -> usize {
+        self.output_wires.len()
+    }
+
+    wire_accessors!();
+
+    fn evaluate(&mut self) {
+        for gate in self.gates.iter_mut() {
+            gate.evaluate();
+        }
+    }
+
+    fn create_response_script(&self, lock_hash: HashValue) -> ScriptBuf {
+        let mut builder = Builder::new()
+            .push_opcode(OP_SHA256)
+            .push_slice(lock_hash)
+            .push_opcode(OP_EQUALVERIFY);
+        for wire in self.output_wires.iter().rev() {
+            builder = C::add_commitment_script(&wire.lock().unwrap().commitment, builder)
+                .push_opcode(OP_TOALTSTACK);
+        }
+        for wire in self.input_wires.iter().rev() {
+            builder = C::add_commitment_script(&wire.lock().unwrap().commitment, builder)
+                .push_opcode(OP_TOALTSTACK);
+        }
+        for _ in 0..self.input_wires.len() {
+            builder = builder.push_opcode(OP_FROMALTSTACK);
+        }
+        builder = self.add_compute_script(builder);
+        // The computed outputs have the last one on top while the committed ones come out of the
+        // altstack first one first
+        let num_outputs = self.output_wires.len();
+        for i in 0..num_outputs {
+            let depth = num_outputs - 1 - i;
+            if depth > 0 {
+                builder = builder.push_int(depth as i64).push_opcode(OP_ROLL);
+            }
+            builder = builder
+                .push_opcode(OP_FROMALTSTACK)
+                .push_opcode(OP_EQUALVERIFY);
+        }
+        builder.into_script()
+    }
+
+    fn add_compute_script(&self, builder: Builder) -> Builder {
+        let (mut builder, model, _) = self.add_gates_script(builder);
+        for slot in self.output_slots.iter().rev() {
+            let position = model.iter().position(|s| s == slot).unwrap();
+            builder = builder
+                .push_int((model.len() - 1 - position) as i64)
+                .push_opcode(OP_PICK)
+                .push_opcode(OP_TOALTSTACK);
+        }
+        builder = Self::add_drop_script(builder, model.len());
+        for _ in 0..self.output_slots.len() {
+            builder = builder.push_opcode(OP_FROMALTSTACK);
+        }
+        builder
+    }
+
+    fn compute_stack_overhead(&self) -> usize {
+        let (_, _, peak) = self.add_gates_script(Builder::new());
+        (peak + self.output_slots.len()).saturating_sub(self.input_slots.len())
+    }
+
+    fn run_gate_on_inputs(&self, inputs: Vec<bool>) -> Vec<bool> {
+        assert!(inputs.len() == self.input_slots.len());
+        let mut values = vec![None; self.num_slots];
+        for (slot, value) in self.input_slots.iter().zip(inputs) {
+            values[*slot] = Some(value);
+        }
+        for (gate, (input_slots, output_slots)) in self.gates.iter().zip(self.gate_slots.iter()) {
+            let gate_inputs = input_slots
+                .iter()
+                .map(|slot| values[*slot].unwrap())
+                .collect();
+            for (slot, value) in output_slots
+                .iter()
+                .zip(gate.run_gate_on_inputs(gate_inputs))
+            {
+                values[*slot] = Some(value);
+            }
+        }
+        self.output_slots
+            .iter()
+            .map(|slot| values[*slot].unwrap())
+            .collect()
+    }
+}
+
+// Greedily groups consecutive gates into the largest chunks that fit the limits. A wire written by
+// a chunk is one of its outputs if a later gate reads it or it is one of the given circuit outputs.
+pub fn partition_into_chunks<C: BitCommitment>(
+    mut gates: Vec<Box<dyn GateTrait<C> + Send + Sync>>,
+    circuit_outputs: &HashSet<usize>,
+    limits: &ChunkLimits,
+) -> Vec<ChunkGate<C>> {
+    let mut last_read = HashMap::<usize, usize>::new();
+    for (i, gate) in gates.iter_mut().enumerate() {
+        for wire_arcm in gate.get_input_wires().iter() {
+            last_read.insert(wire_arcm.lock().unwrap().index.unwrap(), i);
+        }
+    }
+    let build_chunk = |gates, end: usize| {
+        ChunkGate::new(gates, |index| {
+            circuit_outputs.contains(&index) || last_read.get(&index).is_some_and(|i| *i >= end)
+        })
+    };
+
+    let mut chunks = Vec::new();
+    let mut current = Vec::new();
+    for (i, gate) in gates.into_iter().enumerate() {
+        current.push(gate);
+        let chunk = build_chunk(current, i + 1);
+        if chunk.fits(limits) {
+            current = chunk.gates;
+            continue;
+        }
+        let mut gates = chunk.gates;
+        assert!(gates.len() > 1, "gate {} does not fit in a chunk", i);
+        let gate = gates.pop().unwrap();
+        chunks.push(build_chunk(gates, i));
+        current = vec![gate];
+    }
+    if !current.is_empty() {
+        let end = chunks.iter().map(|c| c.gates.len()).sum::<usize>() + current.len();
+        chunks.push(build_chunk(current, end));
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::winternitz::WinternitzCommitment;
+    use super::super::Circuit;
+    use super::*;
+    use crate::test_utils::run_script;
+    use crate::utils::{bool_array_to_number, number_to_bool_array};
+    use bitcoin::hashes::{sha256, Hash};
+
+    #[test]
+    fn test_chunked_circuit_evaluates_the_same() {
+        let mut circuit: Circuit = Circuit::from_bristol("bristol/add.txt", None);
+        let num_gates = circuit.num_gates();
+        let mut chunked: Circuit =
+            Circuit::from_bristol("bristol/add.txt", None).into_chunks(&ChunkLimits::default());
+        assert!(chunked.num_gates() < num_gates);
+        for gate in chunked.gates.iter() {
+            assert!(gate.get_output_size() > 0);
+        }
+
+        let (a, b) = (12345, 67890);
+        let inputs = vec![number_to_bool_array(a, 64), number_to_bool_array(b, 64)];
+        let output = chunked.evaluate(inputs.clone());
+        assert_eq!(output, circuit.evaluate(inputs));
+        assert_eq!(bool_array_to_number(output[0].clone()), a + b);
+    }
+
+    fn test_chunk_scripts<C: BitCommitment>() {
+        let limits = ChunkLimits {
+            max_script_size: 3000,
+            max_stack_size: 1000,
+        };
+        let mut circuit: Circuit<C> =
+            Circuit::from_bristol("bristol/add.txt", None).into_chunks(&limits);
+        circuit.evaluate(vec![
+            number_to_bool_array(1000, 64),
+            number_to_bool_array(2345, 64),
+        ]);
+        let lock_preimage = [7; 32];
+        let lock_hash = sha256::Hash::hash(&lock_preimage).to_byte_array();
+        for chunk in circuit.gates.iter_mut().take(4) {
+            let script = chunk.create_response_script(lock_hash);
+            assert!(script.len() <= limits.max_script_size);
+            let res = run_script(&script, chunk.create_response_witness(lock_preimage));
+            assert_eq!(res.error, None);
+
+            // Claiming a wrong value for one of the outputs makes the script fail
+            let output = chunk.get_output_wires()[0].clone();
+            let value = output.lock().unwrap().selector.unwrap();
+            output.lock().unwrap().selector = Some(!value);
+            let res = run_script(&script, chunk.create_response_witness(lock_preimage));
+            assert!(res.error.is_some());
+            output.lock().unwrap().selector = Some(value);
+        }
+    }
+
+    #[test]
+    fn test_chunk_response_scripts() {
+        test_chunk_scripts::<HashCommitment>();
+        test_chunk_scripts::<WinternitzCommitment>();
+    }
+}
@@ -1,24 +1,25 @@
 use bitcoin::opcodes::all::{
-    OP_BOOLAND, OP_EQUALVERIFY, OP_FROMALTSTACK, OP_NOT, OP_NUMEQUAL, OP_SHA256, OP_TOALTSTACK,
+    OP_2DROP, OP_ADD, OP_BOOLAND, OP_BOOLOR, OP_DROP, OP_DUP, OP_ELSE, OP_ENDIF, OP_EQUALVERIFY,
+    OP_FROMALTSTACK, OP_IF, OP_NIP, OP_NOT, OP_NUMEQUAL, OP_PICK, OP_SHA256, OP_TOALTSTACK,
 };
 use bitcoin::script::Builder;
 use bitcoin::ScriptBuf;
 
-use super::wire::{HashValue, Wire};
-use crate::traits::gate::{GateTrait, Wires};
-use crate::transactions::add_bit_commitment_script;
+use super::wire::{HashCommitment, HashValue, Wire};
+use crate::traits::bit_commitment::BitCommitment;
+use crate::traits::gate::{wire_accessors, GateTrait, Wires};
 
 use std::sync::{Arc, Mutex};
 
-// Every gate has a type parameter COM, which is a bit commitment scheme which can be hash based or schnorr based.
+// Every gate has a type parameter C, which is a bit commitment scheme which can be hash based or winternitz based.
 // Every gate has an array of input wire pointers.
-pub struct NotGate {
-    pub input_wires: Vec<Arc<Mutex<Wire>>>,
-    pub output_wires: Vec<Arc<Mutex<Wire>>>,
+pub struct NotGate<C: BitCommitment = HashCommitment> {
+    pub input_wires: Wires<C>,
+    pub output_wires: Wires<C>,
 }
 
-impl NotGate {
-    pub fn new(input_wires: Vec<Arc<Mutex<Wire>>>, output_wires: Vec<Arc<Mutex<Wire>>>) -> Self {
+impl<C: BitCommitment> NotGate<C> {
+    pub fn new(input_wires: Wires<C>, output_wires: Wires<C>) -> Self {
         NotGate {
             input_wires,
             output_wires,
@@ -26,7 +27,7 @@ impl NotGate {
     }
 }
 
-impl GateTrait for NotGate {
+impl<C: BitCommitment> GateTrait<C> for NotGate<C> {
     fn get_input_size(&self) -> usize {
         1
     }
@@ -35,26 +36,18 @@ impl GateTrait for NotGate {
         1
     }
 
-    fn get_input_wires(&mut self) -> &mut Wires {
-        &mut self.input_wires
-    }
-
-    fn get_output_wires(&mut self) -> &mut Wires {
-        &mut self.output_wires
-    }
+    wire_accessors!();
 
     fn create_response_script(&self, lock_hash: HashValue) -> ScriptBuf {
         let builder = Builder::new()
             .push_opcode(OP_SHA256)
             .push_slice(lock_hash)
             .push_opcode(OP_EQUALVERIFY);
-        let builder = add_bit_commitment_script(
-            self.output_wires[0].lock().unwrap().get_hash_pair(),
-            builder,
-        )
-        .push_opcode(OP_TOALTSTACK);
         let builder =
-            add_bit_commitment_script(self.input_wires[0].lock().unwrap().get_hash_pair(), builder);
+            C::add_commitment_script(&self.output_wires[0].lock().unwrap().commitment, builder)
+                .push_opcode(OP_TOALTSTACK);
+        let builder =
+            C::add_commitment_script(&self.input_wires[0].lock().unwrap().commitment, builder);
         builder
             .push_opcode(OP_NOT)
             .push_opcode(OP_FROMALTSTACK)
@@ -62,19 +55,23 @@ impl GateTrait for NotGate {
             .into_script()
     }
 
+    fn add_compute_script(&self, builder: Builder) -> Builder {
+        builder.push_opcode(OP_NOT)
+    }
+
     fn run_gate_on_inputs(&self, inputs: Vec<bool>) -> Vec<bool> {
         assert!(inputs.len() == 1);
         vec![!inputs[0]]
     }
 }
 
-pub struct AndGate {
-    pub input_wires: Vec<Arc<Mutex<Wire>>>,
-    pub output_wires: Vec<Arc<Mutex<Wire>>>,
+pub struct AndGate<C: BitCommitment = HashCommitment> {
+    pub input_wires: Wires<C>,
+    pub output_wires: Wires<C>,
 }
 
-impl AndGate {
-    pub fn new(input_wires: Vec<Arc<Mutex<Wire>>>, output_wires: Vec<Arc<Mutex<Wire>>>) -> Self {
+impl<C: BitCommitment> AndGate<C> {
+    pub fn new(input_wires: Wires<C>, output_wires: Wires<C>) -> Self {
         AndGate {
             input_wires,
             output_wires,
@@ -82,7 +79,7 @@ impl AndGate {
     }
 }
 
-impl GateTrait for AndGate {
+impl<C: BitCommitment> GateTrait<C> for AndGate<C> {
     fn get_input_size(&self) -> usize {
         2
     }
@@ -91,58 +88,224 @@ impl GateTrait for AndGate {
         1
     }
 
-    fn get_input_wires(&mut self) -> &mut Wires {
-        &mut self.input_wires
+    wire_accessors!();
+
+    fn create_response_script(&self, lock_hash: HashValue) -> ScriptBuf {
+        let builder = Builder::new()
+            .push_opcode(OP_SHA256)
+            .push_slice(lock_hash)
+            .push_opcode(OP_EQUALVERIFY);
+        let builder =
+            C::add_commitment_script(&self.output_wires[0].lock().unwrap().commitment, builder)
+                .push_opcode(OP_TOALTSTACK);
+        let builder =
+            C::add_commitment_script(&self.input_wires[1].lock().unwrap().commitment, builder)
+                .push_opcode(OP_TOALTSTACK);
+        let builder =
+            C::add_commitment_script(&self.input_wires[0].lock().unwrap().commitment, builder);
+        builder
+            .push_opcode(OP_FROMALTSTACK)
+            .push_opcode(OP_BOOLAND)
+            .push_opcode(OP_FROMALTSTACK)
+            .push_opcode(OP_EQUALVERIFY)
+            .into_script()
+    }
+
+    fn add_compute_script(&self, builder: Builder) -> Builder {
+        builder.push_opcode(OP_BOOLAND)
+    }
+
+    fn run_gate_on_inputs(&self, inputs: Vec<bool>) -> Vec<bool> {
+        assert!(inputs.len() == 2);
+        vec![inputs[0] && inputs[1]]
+    }
+}
+
+pub struct XorGate<C: BitCommitment = HashCommitment> {
+    pub input_wires: Wires<C>,
+    pub output_wires: Wires<C>,
+}
+
+impl<C: BitCommitment> XorGate<C> {
+    pub fn new(input_wires: Wires<C>, output_wires: Wires<C>) -> Self {
+        XorGate {
+            input_wires,
+            output_wires,
+        }
+    }
+}
+
+impl<C: BitCommitment> GateTrait<C> for XorGate<C> {
+    fn get_input_size(&self) -> usize {
+        2
     }
 
-    fn get_output_wires(&mut self) -> &mut Wires {
-        &mut self.output_wires
+    fn get_output_size(&self) -> usize {
+        1
     }
 
+    wire_accessors!();
+
     fn create_response_script(&self, lock_hash: HashValue) -> ScriptBuf {
         let builder = Builder::new()
             .push_opcode(OP_SHA256)
             .push_slice(lock_hash)
             .push_opcode(OP_EQUALVERIFY);
-        let builder = add_bit_commitment_script(
-            self.output_wires[0].lock().unwrap().get_hash_pair(),
-            builder,
-        )
-        .push_opcode(OP_TOALTSTACK);
         let builder =
-            add_bit_commitment_script(self.input_wires[1].lock().unwrap().get_hash_pair(), builder)
+            C::add_commitment_script(&self.output_wires[0].lock().unwrap().commitment, builder)
                 .push_opcode(OP_TOALTSTACK);
         let builder =
-            add_bit_commitment_script(self.input_wires[0].lock().unwrap().get_hash_pair(), builder);
+            C::add_commitment_script(&self.input_wires[1].lock().unwrap().commitment, builder)
+                .push_opcode(OP_TOALTSTACK);
+        let builder =
+            C::add_commitment_script(&self.input_wires[0].lock().unwrap().commitment, builder);
+        builder
+            .push_opcode(OP_FROMALTSTACK)
+            .push_opcode(OP_NUMEQUAL)
+            .push_opcode(OP_NOT)
+            .push_opcode(OP_FROMALTSTACK)
+            .push_opcode(OP_EQUALVERIFY)
+            .into_script()
+    }
+
+    fn add_compute_script(&self, builder: Builder) -> Builder {
+        builder.push_opcode(OP_NUMEQUAL).push_opcode(OP_NOT)
+    }
+
+    fn run_gate_on_inputs(&self, inputs: Vec<bool>) -> Vec<bool> {
+        assert!(inputs.len() == 2);
+        vec![inputs[0] ^ inputs[1]]
+    }
+}
+
+pub struct OrGate<C: BitCommitment = HashCommitment> {
+    pub input_wires: Wires<C>,
+    pub output_wires: Wires<C>,
+}
+
+impl<C: BitCommitment> OrGate<C> {
+    pub fn new(input_wires: Wires<C>, output_wires: Wires<C>) -> Self {
+        OrGate {
+            input_wires,
+            output_wires,
+        }
+    }
+}
+
+impl<C: BitCommitment> GateTrait<C> for OrGate<C> {
+    fn get_input_size(&self) -> usize {
+        2
+    }
+
+    fn get_output_size(&self) -> usize {
+        1
+    }
+
+    wire_accessors!();
+
+    fn create_response_script(&self, lock_hash: HashValue) -> ScriptBuf {
+        let builder = Builder::new()
+            .push_opcode(OP_SHA256)
+            .push_slice(lock_hash)
+            .push_opcode(OP_EQUALVERIFY);
+        let builder =
+            C::add_commitment_script(&self.output_wires[0].lock().unwrap().commitment, builder)
+                .push_opcode(OP_TOALTSTACK);
+        let builder =
+            C::add_commitment_script(&self.input_wires[1].lock().unwrap().commitment, builder)
+                .push_opcode(OP_TOALTSTACK);
+        let builder =
+            C::add_commitment_script(&self.input_wires[0].lock().unwrap().commitment, builder);
+        builder
+            .push_opcode(OP_FROMALTSTACK)
+            .push_opcode(OP_BOOLOR)
+            .push_opcode(OP_FROMALTSTACK)
+            .push_opcode(OP_EQUALVERIFY)
+            .into_script()
+    }
+
+    fn add_compute_script(&self, builder: Builder) -> Builder {
+        builder.push_opcode(OP_BOOLOR)
+    }
+
+    fn run_gate_on_inputs(&self, inputs: Vec<bool>) -> Vec<bool> {
+        assert!(inputs.len() == 2);
+        vec![inputs[0] || inputs[1]]
+    }
+}
+
+pub struct NandGate<C: BitCommitment = HashCommitment> {
+    pub input_wires: Wires<C>,
+    pub output_wires: Wires<C>,
+}
+
+impl<C: BitCommitment> NandGate<C> {
+    pub fn new(input_wires: Wires<C>, output_wires: Wires<C>) -> Self {
+        NandGate {
+            input_wires,
+            output_wires,
+        }
+    }
+}
+
+impl<C: BitCommitment> GateTrait<C> for NandGate<C> {
+    fn get_input_size(&self) -> usize {
+        2
+    }
+
+    fn get_output_size(&self) -> usize {
+        1
+    }
+
+    wire_accessors!();
+
+    fn create_response_script(&self, lock_hash: HashValue) -> ScriptBuf {
+        let builder = Builder::new()
+            .push_opcode(OP_SHA256)
+            .push_slice(lock_hash)
+            .push_opcode(OP_EQUALVERIFY);
+        let builder =
+            C::add_commitment_script(&self.output_wires[0].lock().unwrap().commitment, builder)
+                .push_opcode(OP_TOALTSTACK);
+        let builder =
+            C::add_commitment_script(&self.input_wires[1].lock().unwrap().commitment, builder)
+                .push_opcode(OP_TOALTSTACK);
+        let builder =
+            C::add_commitment_script(&self.input_wires[0].lock().unwrap().commitment, builder);
         builder
             .push_opcode(OP_FROMALTSTACK)
             .push_opcode(OP_BOOLAND)
+            .push_opcode(OP_NOT)
             .push_opcode(OP_FROMALTSTACK)
             .push_opcode(OP_EQUALVERIFY)
             .into_script()
     }
 
+    fn add_compute_script(&self, builder: Builder) -> Builder {
+        builder.push_opcode(OP_BOOLAND).push_opcode(OP_NOT)
+    }
+
     fn run_gate_on_inputs(&self, inputs: Vec<bool>) -> Vec<bool> {
         assert!(inputs.len() == 2);
-        vec![inputs[0] && inputs[1]]
+        vec![!(inputs[0] && inputs[1])]
     }
 }
 
-pub struct XorGate {
-    pub input_wires: Vec<Arc<Mutex<Wire>>>,
-    pub output_wires: Vec<Arc<Mutex<Wire>>>,
+pub struct NorGate<C: BitCommitment = HashCommitment> {
+    pub input_wires: Wires<C>,
+    pub output_wires: Wires<C>,
 }
 
-impl XorGate {
-    pub fn new(input_wires: Vec<Arc<Mutex<Wire>>>, output_wires: Vec<Arc<Mutex<Wire>>>) -> Self {
-        XorGate {
+impl<C: BitCommitment> NorGate<C> {
+    pub fn new(input_wires: Wires<C>, output_wires: Wires<C>) -> Self {
+        NorGate {
             input_wires,
             output_wires,
         }
     }
 }
 
-impl GateTrait for XorGate {
+impl<C: BitCommitment> GateTrait<C> for NorGate<C> {
     fn get_input_size(&self) -> usize {
         2
     }
@@ -151,41 +314,331 @@ impl GateTrait for XorGate {
         1
     }
 
-    fn get_input_wires(&mut self) -> &mut Wires {
-        &mut self.input_wires
+    wire_accessors!();
+
+    fn create_response_script(&self, lock_hash: HashValue) -> ScriptBuf {
+        let builder = Builder::new()
+            .push_opcode(OP_SHA256)
+            .push_slice(lock_hash)
+            .push_opcode(OP_EQUALVERIFY);
+        let builder =
+            C::add_commitment_script(&self.output_wires[0].lock().unwrap().commitment, builder)
+                .push_opcode(OP_TOALTSTACK);
+        let builder =
+            C::add_commitment_script(&self.input_wires[1].lock().unwrap().commitment, builder)
+                .push_opcode(OP_TOALTSTACK);
+        let builder =
+            C::add_commitment_script(&self.input_wires[0].lock().unwrap().commitment, builder);
+        builder
+            .push_opcode(OP_FROMALTSTACK)
+            .push_opcode(OP_BOOLOR)
+            .push_opcode(OP_NOT)
+            .push_opcode(OP_FROMALTSTACK)
+            .push_opcode(OP_EQUALVERIFY)
+            .into_script()
+    }
+
+    fn add_compute_script(&self, builder: Builder) -> Builder {
+        builder.push_opcode(OP_BOOLOR).push_opcode(OP_NOT)
+    }
+
+    fn run_gate_on_inputs(&self, inputs: Vec<bool>) -> Vec<bool> {
+        assert!(inputs.len() == 2);
+        vec![!(inputs[0] || inputs[1])]
+    }
+}
+
+// Both committed bits are 0 or 1, so comparing them as numbers is enough
+pub struct XnorGate<C: BitCommitment = HashCommitment> {
+    pub input_wires: Wires<C>,
+    pub output_wires: Wires<C>,
+}
+
+impl<C: BitCommitment> XnorGate<C> {
+    pub fn new(input_wires: Wires<C>, output_wires: Wires<C>) -> Self {
+        XnorGate {
+            input_wires,
+            output_wires,
+        }
+    }
+}
+
+impl<C: BitCommitment> GateTrait<C> for XnorGate<C> {
+    fn get_input_size(&self) -> usize {
+        2
     }
 
-    fn get_output_wires(&mut self) -> &mut Wires {
-        &mut self.output_wires
+    fn get_output_size(&self) -> usize {
+        1
     }
 
+    wire_accessors!();
+
     fn create_response_script(&self, lock_hash: HashValue) -> ScriptBuf {
         let builder = Builder::new()
             .push_opcode(OP_SHA256)
             .push_slice(lock_hash)
             .push_opcode(OP_EQUALVERIFY);
-        let builder = add_bit_commitment_script(
-            self.output_wires[0].lock().unwrap().get_hash_pair(),
-            builder,
-        )
-        .push_opcode(OP_TOALTSTACK);
         let builder =
-            add_bit_commitment_script(self.input_wires[1].lock().unwrap().get_hash_pair(), builder)
+            C::add_commitment_script(&self.output_wires[0].lock().unwrap().commitment, builder)
+                .push_opcode(OP_TOALTSTACK);
+        let builder =
+            C::add_commitment_script(&self.input_wires[1].lock().unwrap().commitment, builder)
                 .push_opcode(OP_TOALTSTACK);
         let builder =
-            add_bit_commitment_script(self.input_wires[0].lock().unwrap().get_hash_pair(), builder);
+            C::add_commitment_script(&self.input_wires[0].lock().unwrap().commitment, builder);
         builder
             .push_opcode(OP_FROMALTSTACK)
             .push_opcode(OP_NUMEQUAL)
-            .push_opcode(OP_NOT)
             .push_opcode(OP_FROMALTSTACK)
             .push_opcode(OP_EQUALVERIFY)
             .into_script()
     }
 
+    fn add_compute_script(&self, builder: Builder) -> Builder {
+        builder.push_opcode(OP_NUMEQUAL)
+    }
+
     fn run_gate_on_inputs(&self, inputs: Vec<bool>) -> Vec<bool> {
         assert!(inputs.len() == 2);
-        vec![inputs[0] ^ inputs[1]]
+        vec![inputs[0] == inputs[1]]
+    }
+}
+
+// A 2:1 multiplexer with inputs (a, b, s), whose output is b when s is set and a otherwise.
+pub struct MuxGate<C: BitCommitment = HashCommitment> {
+    pub input_wires: Wires<C>,
+    pub output_wires: Wires<C>,
+}
+
+impl<C: BitCommitment> MuxGate<C> {
+    pub fn new(input_wires: Wires<C>, output_wires: Wires<C>) -> Self {
+        MuxGate {
+            input_wires,
+            output_wires,
+        }
+    }
+}
+
+impl<C: BitCommitment> GateTrait<C> for MuxGate<C> {
+    fn get_input_size(&self) -> usize {
+        3
+    }
+
+    fn get_output_size(&self) -> usize {
+        1
+    }
+
+    wire_accessors!();
+
+    fn create_response_script(&self, lock_hash: HashValue) -> ScriptBuf {
+        let builder = Builder::new()
+            .push_opcode(OP_SHA256)
+            .push_slice(lock_hash)
+            .push_opcode(OP_EQUALVERIFY);
+        let builder =
+            C::add_commitment_script(&self.output_wires[0].lock().unwrap().commitment, builder)
+                .push_opcode(OP_TOALTSTACK);
+        let builder =
+            C::add_commitment_script(&self.input_wires[2].lock().unwrap().commitment, builder)
+                .push_opcode(OP_TOALTSTACK);
+        let builder =
+            C::add_commitment_script(&self.input_wires[1].lock().unwrap().commitment, builder)
+                .push_opcode(OP_TOALTSTACK);
+        let builder =
+            C::add_commitment_script(&self.input_wires[0].lock().unwrap().commitment, builder);
+        // The committed selector is either empty or 1, which satisfies the minimal if rule
+        builder
+            .push_opcode(OP_FROMALTSTACK)
+            .push_opcode(OP_FROMALTSTACK)
+            .push_opcode(OP_IF)
+            .push_opcode(OP_NIP)
+            .push_opcode(OP_ELSE)
+            .push_opcode(OP_DROP)
+            .push_opcode(OP_ENDIF)
+            .push_opcode(OP_FROMALTSTACK)
+            .push_opcode(OP_EQUALVERIFY)
+            .into_script()
+    }
+
+    fn add_compute_script(&self, builder: Builder) -> Builder {
+        builder
+            .push_opcode(OP_IF)
+            .push_opcode(OP_NIP)
+            .push_opcode(OP_ELSE)
+            .push_opcode(OP_DROP)
+            .push_opcode(OP_ENDIF)
+    }
+
+    fn run_gate_on_inputs(&self, inputs: Vec<bool>) -> Vec<bool> {
+        assert!(inputs.len() == 3);
+        vec![if inputs[2] { inputs[1] } else { inputs[0] }]
+    }
+}
+
+// Larger tables would not fit in the stack together with the rest of the response script
+pub const MAX_LOOKUP_INPUTS: usize = 8;
+
+// A gate defined by its truth table, where row i holds the outputs for the inputs whose bits, least
+// significant first, make up i. The response script computes the row index from the committed
+// inputs and looks up every output with OP_PICK, so any small boolean function can be used
+// without writing its script by hand.
+pub struct LookupGate<C: BitCommitment = HashCommitment> {
+    pub truth_table: Vec<Vec<bool>>,
+    pub input_wires: Wires<C>,
+    pub output_wires: Wires<C>,
+}
+
+impl<C: BitCommitment> LookupGate<C> {
+    pub fn new(truth_table: Vec<Vec<bool>>, input_wires: Wires<C>, output_wires: Wires<C>) -> Self {
+        assert!(
+            truth_table.len() >= 2 && truth_table.len().is_power_of_two(),
+            "truth table should have 2^k rows"
+        );
+        assert!(
+            truth_table.len().ilog2() as usize <= MAX_LOOKUP_INPUTS,
+            "too many inputs for a lookup gate"
+        );
+        assert!(
+            !truth_table[0].is_empty()
+                && truth_table
+                    .iter()
+                    .all(|row| row.len() == truth_table[0].len()),
+            "every row should have the same number of outputs"
+        );
+        assert_eq!(
+            input_wires.len(),
+            truth_table.len().ilog2() as usize,
+            "wrong number of input wires for the truth table"
+        );
+        assert_eq!(
+            output_wires.len(),
+            truth_table[0].len(),
+            "wrong number of output wires for the truth table"
+        );
+        LookupGate {
+            truth_table,
+            input_wires,
+            output_wires,
+        }
+    }
+
+    // Parses tables written as "lut:" followed by the rows separated by commas, each row being the
+    // output bits as 0s and 1s, e.g. "lut:0,1,1,0" for xor.
+    pub fn parse_truth_table(gate_name: &str) -> Option<Vec<Vec<bool>>> {
+        gate_name
+            .strip_prefix("lut:")?
+            .split(',')
+            .map(|row| {
+                row.chars()
+                    .map(|c| match c {
+                        '0' => Some(false),
+                        '1' => Some(true),
+                        _ => None,
+                    })
+                    .collect::<Option<Vec<bool>>>()
+            })
+            .collect()
+    }
+}
+
+impl<C: BitCommitment> GateTrait<C> for LookupGate<C> {
+    fn get_input_size(&self) -> usize {
+        self.truth_table.len().ilog2() as usize
+    }
+
+    fn get_output_size(&self) -> usize {
+        self.truth_table[0].len()
+    }
+
+    wire_accessors!();
+
+    fn create_response_script(&self, lock_hash: HashValue) -> ScriptBuf {
+        let mut builder = Builder::new()
+            .push_opcode(OP_SHA256)
+            .push_slice(lock_hash)
+            .push_opcode(OP_EQUALVERIFY);
+        // Outputs go to the altstack so that the first one comes back first
+        for wire in self.output_wires.iter().rev() {
+            builder = C::add_commitment_script(&wire.lock().unwrap().commitment, builder)
+                .push_opcode(OP_TOALTSTACK);
+        }
+        // Row index, accumulated from the most significant input down
+        let last_input = self.input_wires.len() - 1;
+        builder = C::add_commitment_script(
+            &self.input_wires[last_input].lock().unwrap().commitment,
+            builder,
+        );
+        for wire in self.input_wires[..last_input].iter().rev() {
+            builder = builder.push_opcode(OP_TOALTSTACK);
+            builder = C::add_commitment_script(&wire.lock().unwrap().commitment, builder)
+                .push_opcode(OP_FROMALTSTACK)
+                .push_opcode(OP_DUP)
+                .push_opcode(OP_ADD)
+                .push_opcode(OP_ADD);
+        }
+        let rows = self.truth_table.len();
+        for output in 0..self.get_output_size() {
+            // Push the column with the first row on top, so that the index picks its own row
+            for row in self.truth_table.iter().rev() {
+                builder = builder.push_int(row[output] as i64);
+            }
+            builder = builder
+                .push_int(rows as i64)
+                .push_opcode(OP_PICK)
+                .push_opcode(OP_PICK)
+                .push_opcode(OP_FROMALTSTACK)
+                .push_opcode(OP_EQUALVERIFY);
+            for _ in 0..rows / 2 {
+                builder = builder.push_opcode(OP_2DROP);
+            }
+        }
+        builder.push_opcode(OP_DROP).into_script()
+    }
+
+    fn add_compute_script(&self, builder: Builder) -> Builder {
+        // Row index, accumulated from the most significant input down
+        let mut builder = builder;
+        for _ in 1..self.get_input_size() {
+            builder = builder
+                .push_opcode(OP_DUP)
+                .push_opcode(OP_ADD)
+                .push_opcode(OP_ADD);
+        }
+        // Outputs wait in the altstack, the first one pushed last so that it comes back first
+        let rows = self.truth_table.len();
+        for output in (0..self.get_output_size()).rev() {
+            for row in self.truth_table.iter().rev() {
+                builder = builder.push_int(row[output] as i64);
+            }
+            builder = builder
+                .push_int(rows as i64)
+                .push_opcode(OP_PICK)
+                .push_opcode(OP_PICK)
+                .push_opcode(OP_TOALTSTACK);
+            for _ in 0..rows / 2 {
+                builder = builder.push_opcode(OP_2DROP);
+            }
+        }
+        builder = builder.push_opcode(OP_DROP);
+        for _ in 0..self.get_output_size() {
+            builder = builder.push_opcode(OP_FROMALTSTACK);
+        }
+        builder
+    }
+
+    // The column being looked up, the copied index and the picked value
+    fn compute_stack_overhead(&self) -> usize {
+        self.truth_table.len() + 2
+    }
+
+    fn run_gate_on_inputs(&self, inputs: Vec<bool>) -> Vec<bool> {
+        assert!(inputs.len() == self.get_input_size());
+        let index = inputs
+            .iter()
+            .rev()
+            .fold(0, |acc, b| (acc << 1) | (*b as usize));
+        self.truth_table[index].clone()
     }
 }
 
@@ -207,28 +660,48 @@ macro_rules! create_gate_without_wires {
     }};
 }
 
-pub fn create_gate(
+pub fn create_gate<C: BitCommitment>(
     gate_name: &str,
-    input_wires: Option<Vec<Arc<Mutex<Wire>>>>,
-    output_wires: Option<Vec<Arc<Mutex<Wire>>>>,
-) -> Box<dyn GateTrait + std::marker::Send> {
+    input_wires: Option<Wires<C>>,
+    output_wires: Option<Wires<C>>,
+) -> Box<dyn GateTrait<C> + std::marker::Send + std::marker::Sync> {
     match gate_name {
-        "not" => create_gate_without_wires!(NotGate, &input_wires, &output_wires),
-        "xor" => create_gate_without_wires!(XorGate, &input_wires, &output_wires),
-        "and" => create_gate_without_wires!(AndGate, &input_wires, &output_wires),
-        _ => panic!("Invalid gate name"),
+        "not" => create_gate_without_wires!(NotGate<C>, &input_wires, &output_wires),
+        "xor" => create_gate_without_wires!(XorGate<C>, &input_wires, &output_wires),
+        "and" => create_gate_without_wires!(AndGate<C>, &input_wires, &output_wires),
+        "or" => create_gate_without_wires!(OrGate<C>, &input_wires, &output_wires),
+        "nand" => create_gate_without_wires!(NandGate<C>, &input_wires, &output_wires),
+        "nor" => create_gate_without_wires!(NorGate<C>, &input_wires, &output_wires),
+        "xnor" => create_gate_without_wires!(XnorGate<C>, &input_wires, &output_wires),
+        "mux" => create_gate_without_wires!(MuxGate<C>, &input_wires, &output_wires),
+        _ => match LookupGate::<C>::parse_truth_table(gate_name) {
+            Some(truth_table) => {
+                let input_wires = input_wires.unwrap_or_else(|| {
+                    (0..truth_table.len().max(1).ilog2())
+                        .map(|_| Arc::new(Mutex::new(Wire::new(0))))
+                        .collect()
+                });
+                let output_wires = output_wires.unwrap_or_else(|| {
+                    (0..truth_table[0].len())
+                        .map(|_| Arc::new(Mutex::new(Wire::new(0))))
+                        .collect()
+                });
+                Box::new(LookupGate::new(truth_table, input_wires, output_wires))
+            }
+            None => panic!("Invalid gate name"),
+        },
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::super::wire::PreimageValue;
+    use super::super::winternitz::WinternitzCommitment;
+    use super::super::wire::{PreimageValue, RevealError, RevealOutcome};
     use super::*;
+    use crate::test_utils::{create_exec, run_script};
     use bitcoin::hashes::sha256;
     use bitcoin::hashes::Hash;
-    use bitcoin::TapLeafHash;
-    use bitcoin::Transaction;
-    use bitcoin_scriptexec::*;
+    use bitcoin_scriptexec::Exec;
     use rand::Rng;
 
     fn check_exec(mut exec: Exec, correct_exec: bool) {
@@ -247,30 +720,6 @@ mod tests {
         }
     }
 
-    fn create_exec(script: &ScriptBuf, solution_preimages: Vec<PreimageValue>) -> Exec {
-        Exec::new(
-            ExecCtx::Tapscript,
-            Options::default(),
-            TxTemplate {
-                tx: Transaction {
-                    version: bitcoin::transaction::Version::TWO,
-                    lock_time: bitcoin::locktime::absolute::LockTime::ZERO,
-                    input: vec![],
-                    output: vec![],
-                },
-                prevouts: vec![],
-                input_idx: 0,
-                taproot_annex_scriptleaf: Some((TapLeafHash::all_zeros(), None)),
-            },
-            script.clone(),
-            solution_preimages
-                .iter()
-                .map(|preimage| preimage.to_vec())
-                .collect(),
-        )
-        .expect("error creating exec")
-    }
-
     fn generate_all_possibilities(num_inputs: usize) -> Vec<Vec<bool>> {
         let mut all_possibilities = vec![];
         for i in 0..2usize.pow(num_inputs as u32) {
@@ -283,8 +732,8 @@ mod tests {
         all_possibilities
     }
 
-    fn test_gate(gate_name: &str) {
-        let mut gate: Box<dyn GateTrait> = create_gate(gate_name, None, None);
+    fn test_gate<C: BitCommitment>(gate_name: &str) {
+        let mut gate: Box<dyn GateTrait<C> + Send + Sync> = create_gate(gate_name, None, None);
         let all_possible_inputs = generate_all_possibilities(gate.get_input_size());
         let all_possible_outputs = generate_all_possibilities(gate.get_output_size());
 
@@ -298,8 +747,8 @@ mod tests {
             let gate_res = gate.run_gate_on_inputs(input.clone());
             for output in all_possible_outputs.iter() {
                 gate.set_output_bits(output.clone());
-                let solution_preimages = gate.create_response_witness(lock_preimage);
-                let exec = create_exec(&script, solution_preimages);
+                let solution_witness = gate.create_response_witness(lock_preimage);
+                let exec = create_exec(&script, solution_witness);
                 let compare_vectors = gate_res.iter().eq(output.iter());
                 check_exec(exec, compare_vectors);
             }
@@ -308,16 +757,194 @@ mod tests {
 
     #[test]
     fn test_not_gate() {
-        test_gate("not");
+        test_gate::<HashCommitment>("not");
+        test_gate::<WinternitzCommitment>("not");
     }
 
     #[test]
     fn test_xor_gate() {
-        test_gate("xor");
+        test_gate::<HashCommitment>("xor");
+        test_gate::<WinternitzCommitment>("xor");
     }
 
     #[test]
     fn test_and_gate() {
-        test_gate("and");
+        test_gate::<HashCommitment>("and");
+        test_gate::<WinternitzCommitment>("and");
+    }
+
+    #[test]
+    fn test_or_gate() {
+        test_gate::<HashCommitment>("or");
+        test_gate::<WinternitzCommitment>("or");
+    }
+
+    #[test]
+    fn test_nand_gate() {
+        test_gate::<HashCommitment>("nand");
+        test_gate::<WinternitzCommitment>("nand");
+    }
+
+    #[test]
+    fn test_nor_gate() {
+        test_gate::<HashCommitment>("nor");
+        test_gate::<WinternitzCommitment>("nor");
+    }
+
+    #[test]
+    fn test_xnor_gate() {
+        test_gate::<HashCommitment>("xnor");
+        test_gate::<WinternitzCommitment>("xnor");
+    }
+
+    #[test]
+    fn test_mux_gate() {
+        test_gate::<HashCommitment>("mux");
+        test_gate::<WinternitzCommitment>("mux");
+    }
+
+    #[test]
+    fn test_lookup_gate() {
+        for gate_name in ["lut:1,0", "lut:0,1,1,0", "lut:00,10,10,01,10,01,01,11"] {
+            test_gate::<HashCommitment>(gate_name);
+            test_gate::<WinternitzCommitment>(gate_name);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "wrong number of input wires for the truth table")]
+    fn test_lookup_gate_checks_wires() {
+        let wires = |n| -> Wires { (0..n).map(|i| Arc::new(Mutex::new(Wire::new(i)))).collect() };
+        // A Bristol line giving xor's table three inputs instead of two
+        create_gate("lut:0,1,1,0", Some(wires(3)), Some(wires(1)));
+    }
+
+    #[test]
+    fn test_compute_scripts() {
+        for gate_name in [
+            "not",
+            "and",
+            "xor",
+            "or",
+            "nand",
+            "nor",
+            "xnor",
+            "mux",
+            "lut:00,10,10,01,10,01,01,11",
+        ] {
+            let gate: Box<dyn GateTrait + Send + Sync> = create_gate(gate_name, None, None);
+            for input in generate_all_possibilities(gate.get_input_size()) {
+                let mut builder = Builder::new();
+                for bit in input.iter() {
+                    builder = builder.push_int(*bit as i64);
+                }
+                builder = gate.add_compute_script(builder);
+                for bit in gate.run_gate_on_inputs(input.clone()).iter().rev() {
+                    builder = builder.push_int(*bit as i64).push_opcode(OP_EQUALVERIFY);
+                }
+                let script = builder.push_int(1).into_script();
+                let res = run_script(&script, vec![]);
+                assert!(res.success, "{} failed on {:?}", gate_name, input);
+            }
+        }
+    }
+
+    #[test]
+    fn test_lookup_gate_matches_builtin_gates() {
+        for (gate_name, truth_table) in [
+            ("not", "lut:1,0"),
+            ("xor", "lut:0,1,1,0"),
+            ("and", "lut:0,0,0,1"),
+            ("or", "lut:0,1,1,1"),
+            ("nand", "lut:1,1,1,0"),
+            ("nor", "lut:1,0,0,0"),
+            ("xnor", "lut:1,0,0,1"),
+            ("mux", "lut:0,1,0,1,0,0,1,1"),
+        ] {
+            let gate: Box<dyn GateTrait + Send + Sync> = create_gate(gate_name, None, None);
+            let lookup_gate: Box<dyn GateTrait + Send + Sync> =
+                create_gate(truth_table, None, None);
+            for input in generate_all_possibilities(gate.get_input_size()) {
+                assert_eq!(
+                    gate.run_gate_on_inputs(input.clone()),
+                    lookup_gate.run_gate_on_inputs(input)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_add_preimages_from_witness() {
+        let wires: Wires = (0..3).map(|i| Arc::new(Mutex::new(Wire::new(i)))).collect();
+        let (prover_inputs, prover_outputs) = (wires[..2].to_vec(), wires[2..].to_vec());
+        let verifier_wires = |wires: &Wires| -> Wires {
+            wires
+                .iter()
+                .map(|w| {
+                    let w = w.lock().unwrap();
+                    Arc::new(Mutex::new(Wire::new_with_commitment(
+                        w.index.unwrap(),
+                        w.get_commitment(),
+                    )))
+                })
+                .collect()
+        };
+        let mut prover_gate: Box<dyn GateTrait + Send + Sync> = create_gate(
+            "and",
+            Some(prover_inputs.clone()),
+            Some(prover_outputs.clone()),
+        );
+        let mut verifier_gate: Box<dyn GateTrait + Send + Sync> = create_gate(
+            "and",
+            Some(verifier_wires(&prover_inputs)),
+            Some(verifier_wires(&prover_outputs)),
+        );
+        prover_gate.set_input_bits(vec![true, true]);
+        prover_gate.evaluate();
+        let mut witness = prover_gate.create_response_witness([0; 32]);
+        witness.pop();
+
+        assert_eq!(
+            verifier_gate
+                .add_preimages_from_witness(&witness[1..])
+                .unwrap_err(),
+            RevealError::WrongWitnessLength {
+                expected: 3,
+                found: 2
+            }
+        );
+        let mut malformed = witness.clone();
+        malformed[1] = vec![1, 2, 3];
+        assert_eq!(
+            verifier_gate
+                .add_preimages_from_witness(&malformed)
+                .unwrap_err(),
+            RevealError::MalformedReveal { position: 1 }
+        );
+        // Nothing is recorded when a single reveal is wrong
+        let mut unknown = witness.clone();
+        unknown[2] = vec![7; 32];
+        assert!(matches!(
+            verifier_gate.add_preimages_from_witness(&unknown),
+            Err(RevealError::UnknownReveal { .. })
+        ));
+        assert!(verifier_gate.get_input_wires().iter().all(|w| w
+            .lock()
+            .unwrap()
+            .reveals
+            .one
+            .is_none()));
+
+        let outcomes = verifier_gate.add_preimages_from_witness(&witness).unwrap();
+        assert!(outcomes
+            .iter()
+            .all(|o| matches!(o, RevealOutcome::NewBit(true))));
+
+        prover_gate.set_output_bits(vec![false]);
+        let mut witness = prover_gate.create_response_witness([0; 32]);
+        witness.pop();
+        let outcomes = verifier_gate.add_preimages_from_witness(&witness).unwrap();
+        assert!(matches!(outcomes[0], RevealOutcome::AlreadyKnown(true)));
+        assert!(matches!(outcomes[2], RevealOutcome::Equivocation(_)));
     }
 }
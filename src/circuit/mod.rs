@@ -1,30 +1,37 @@
+pub mod chunk;
 pub mod gates;
+pub mod winternitz;
 pub mod wire;
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashSet};
 use std::iter::zip;
 
 use std::sync::{Arc, Mutex};
 
+use chunk::{partition_into_chunks, ChunkLimits};
 use gates::create_gate;
-use wire::{HashTuple, Wire};
+use wire::{HashCommitment, Wire};
 
-use crate::{traits::gate::GateTrait, utils::read_lines};
+use crate::traits::bit_commitment::BitCommitment;
+use crate::{
+    traits::gate::{GateTrait, Wires},
+    utils::read_lines,
+};
 
-pub struct Circuit {
+pub struct Circuit<C: BitCommitment = HashCommitment> {
     pub input_sizes: Vec<usize>,
     pub output_sizes: Vec<usize>,
-    pub gates: Vec<Box<dyn GateTrait + Send>>,
-    pub wires: Vec<Arc<Mutex<Wire>>>,
+    pub gates: Vec<Box<dyn GateTrait<C> + Send + Sync>>,
+    pub wires: Wires<C>,
 }
 
-impl Default for Circuit {
+impl<C: BitCommitment> Default for Circuit<C> {
     fn default() -> Self {
         Self::from_bristol("bristol/test.txt", None)
     }
 }
 
-impl Circuit {
+impl<C: BitCommitment> Circuit<C> {
     pub fn num_gates(&self) -> usize {
         self.gates.len()
     }
@@ -65,22 +72,72 @@ impl Circuit {
         output
     }
 
-    pub fn get_wire_hashes(&self) -> Vec<HashTuple> {
+    // Replaces the gates with chunks of consecutive gates, so that every challenge and response
+    // covers a whole chunk from then on.
+    pub fn into_chunks(mut self, limits: &ChunkLimits) -> Self {
+        let total_output_size = self.output_sizes.iter().sum::<usize>();
+        let circuit_outputs =
+            (self.wires.len() - total_output_size..self.wires.len()).collect::<HashSet<usize>>();
+        let gates = std::mem::take(&mut self.gates);
+        self.gates = partition_into_chunks(gates, &circuit_outputs, limits)
+            .into_iter()
+            .map(|chunk| Box::new(chunk) as Box<dyn GateTrait<C> + Send + Sync>)
+            .collect();
+        self
+    }
+
+    pub fn get_wire_commitments(&self) -> Vec<C::Public> {
         self.wires
             .iter()
             .map(|wire_rcref| {
                 let wire = wire_rcref.lock().unwrap();
-                wire.get_hash_pair()
+                wire.get_commitment()
             })
-            .collect::<Vec<HashTuple>>()
+            .collect::<Vec<C::Public>>()
+    }
+
+    // Wires revealed by some response, once each. These are the gate inputs and outputs, so only
+    // the wires crossing chunk boundaries once the circuit is chunked.
+    pub fn get_revealed_wires(&self) -> Wires<C> {
+        let mut seen = HashSet::new();
+        self.gates
+            .iter()
+            .flat_map(|gate| {
+                let (input_wires, output_wires) = gate.get_wires();
+                input_wires.iter().chain(output_wires.iter()).cloned()
+            })
+            .filter(|wire_arcm| seen.insert(wire_arcm.lock().unwrap().index.unwrap()))
+            .collect()
+    }
+
+    pub fn from_bristol(file: &str, wire_commitments: Option<Vec<C::Public>>) -> Self {
+        Self::parse_bristol(file, |i| {
+            let wire = if let Some(wire_commitments) = wire_commitments.as_ref() {
+                Wire::new_with_commitment(i, wire_commitments[i].clone())
+            } else {
+                Wire::new(i)
+            };
+            Arc::new(Mutex::new(wire))
+        })
+    }
+
+    // Circuit sharing the commitments and preimages of the wires of another circuit built from the
+    // same file. The wire values and gates are its own, so it can be chunked and evaluated
+    // separately, e.g. in a game running next to others.
+    pub fn from_bristol_with_wires(file: &str, wires: &Wires<C>) -> Self {
+        let circuit = Self::parse_bristol(file, |i| {
+            Arc::new(Mutex::new(wires[i].lock().unwrap().share_commitment()))
+        });
+        assert_eq!(circuit.wires.len(), wires.len(), "wrong number of wires");
+        circuit
     }
 
-    pub fn from_bristol(file: &str, wire_hashes: Option<Vec<HashTuple>>) -> Self {
+    fn parse_bristol(file: &str, new_wire: impl Fn(usize) -> Arc<Mutex<Wire<C>>>) -> Self {
         let mut nog: usize = 0; // number of gates
         let mut now: usize = 0; // number of wires
         let mut input_sizes = Vec::<usize>::new();
         let mut output_sizes = Vec::<usize>::new();
-        let mut gates = Vec::<Box<dyn GateTrait + Send>>::new();
+        let mut gates = Vec::<Box<dyn GateTrait<C> + Send + Sync>>::new();
         let mut wire_indices = BTreeMap::new();
 
         for (i, line) in read_lines(file).unwrap().enumerate() {
@@ -90,12 +147,7 @@ impl Circuit {
                     nog = words.next().unwrap().parse().unwrap();
                     now = words.next().unwrap().parse().unwrap();
                     for i in 0..now {
-                        let wire = if let Some(wire_hashes) = wire_hashes.clone() {
-                            Wire::new_with_hash_pair(i, wire_hashes[i])
-                        } else {
-                            Wire::new(i)
-                        };
-                        wire_indices.insert(i, Arc::new(Mutex::new(wire)));
+                        wire_indices.insert(i, new_wire(i));
                     }
                 } else if i == 1 {
                     let mut words = line_str.split_whitespace();
@@ -179,10 +231,7 @@ impl Circuit {
             input_sizes,
             output_sizes,
             gates,
-            wires: wire_indices
-                .values()
-                .cloned()
-                .collect::<Vec<Arc<Mutex<Wire>>>>(),
+            wires: wire_indices.values().cloned().collect::<Wires<C>>(),
         };
     }
 }
@@ -196,19 +245,65 @@ mod tests {
 
     #[test]
     fn test_circuit() {
-        let circuit = Circuit::default();
+        let circuit: Circuit = Circuit::default();
         assert!(circuit.output_sizes[0] == 1);
     }
 
     #[test]
     fn test_bristol() {
-        let circuit = Circuit::from_bristol("bristol/add.txt", None);
+        let circuit: Circuit = Circuit::from_bristol("bristol/add.txt", None);
         assert!(circuit.output_sizes[0] == 64);
     }
 
+    #[test]
+    fn test_shared_wires() {
+        let mut circuit: Circuit = Circuit::from_bristol("bristol/add.txt", None);
+        let mut shared = Circuit::from_bristol_with_wires("bristol/add.txt", &circuit.wires)
+            .into_chunks(&ChunkLimits::default());
+        assert_eq!(
+            shared.get_wire_commitments(),
+            circuit.get_wire_commitments()
+        );
+        assert!(shared.num_gates() < circuit.num_gates());
+
+        // The preimages are shared, the values are not, so games evaluating other inputs do not
+        // overwrite each other
+        assert!(Arc::ptr_eq(
+            shared.wires[0].lock().unwrap().secret.as_ref().unwrap(),
+            circuit.wires[0].lock().unwrap().secret.as_ref().unwrap()
+        ));
+        let values = |circuit: &Circuit| {
+            circuit
+                .wires
+                .iter()
+                .map(|wire| wire.lock().unwrap().selector)
+                .collect::<Vec<_>>()
+        };
+        let inputs = vec![number_to_bool_array(5, 64), number_to_bool_array(7, 64)];
+        let sum = circuit.evaluate(inputs.clone());
+        let evaluated = values(&circuit);
+        shared.evaluate(vec![
+            number_to_bool_array(2, 64),
+            number_to_bool_array(9, 64),
+        ]);
+        assert_eq!(values(&circuit), evaluated);
+        assert_eq!(shared.evaluate(inputs), sum);
+    }
+
+    #[test]
+    fn test_revealed_wires() {
+        let circuit: Circuit = Circuit::from_bristol("bristol/add.txt", None);
+        assert_eq!(circuit.get_revealed_wires().len(), circuit.wires.len());
+        let circuit = circuit.into_chunks(&ChunkLimits::default());
+        let revealed = circuit.get_revealed_wires();
+        assert!(revealed.len() < circuit.wires.len());
+        // Every circuit input and output is revealed
+        assert!(revealed.len() >= 64 + 64 + 64);
+    }
+
     #[test]
     fn test_add_circuit() {
-        let mut circuit = Circuit::from_bristol("bristol/add.txt", None);
+        let mut circuit: Circuit = Circuit::from_bristol("bristol/add.txt", None);
         let a1 = 633;
         let a2 = 15;
         let b1 = number_to_bool_array(a1, 64);
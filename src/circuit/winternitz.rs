@@ -0,0 +1,330 @@
+use bitcoin::hashes::hash160;
+use bitcoin::hashes::Hash;
+use bitcoin::script::Builder;
+use rand::Rng;
+use serde::Deserialize;
+use serde::Serialize;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use crate::traits::bit_commitment::BitCommitment;
+use crate::traits::gate::Wires;
+use crate::transactions::{add_winternitz_commitment_script, add_winternitz_equivocation_script};
+
+// Winternitz one-time signatures over HASH160 with 4-bit digits. A value of n bits is committed
+// with ceil(n / 4) message digits plus a few checksum digits, each costing a single 20-byte hash,
+// instead of two 32-byte hashes per bit.
+pub const DIGIT_BITS: usize = 4;
+pub const MAX_DIGIT: u8 = (1 << DIGIT_BITS) - 1;
+
+pub type WinternitzHash = [u8; 20];
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WinternitzParams {
+    pub message_bits: usize,
+}
+
+impl WinternitzParams {
+    pub fn new(message_bits: usize) -> Self {
+        assert!(message_bits > 0, "at least one bit is required");
+        WinternitzParams { message_bits }
+    }
+
+    pub fn message_digits(&self) -> usize {
+        self.message_bits.div_ceil(DIGIT_BITS)
+    }
+
+    pub fn max_checksum(&self) -> usize {
+        self.message_digits() * MAX_DIGIT as usize
+    }
+
+    pub fn checksum_digits(&self) -> usize {
+        let mut digits = 1;
+        let mut max = self.max_checksum() >> DIGIT_BITS;
+        while max > 0 {
+            digits += 1;
+            max >>= DIGIT_BITS;
+        }
+        digits
+    }
+
+    pub fn total_digits(&self) -> usize {
+        self.message_digits() + self.checksum_digits()
+    }
+
+    // Message digits first (least significant first), followed by the checksum digits.
+    pub fn digits_of(&self, bits: &[bool]) -> Vec<u8> {
+        assert_eq!(bits.len(), self.message_bits, "wrong number of bits");
+        let mut digits = bits
+            .chunks(DIGIT_BITS)
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .rev()
+                    .fold(0u8, |acc, b| (acc << 1) | (*b as u8))
+            })
+            .collect::<Vec<u8>>();
+        let mut checksum = self.max_checksum() - digits.iter().map(|d| *d as usize).sum::<usize>();
+        for _ in 0..self.checksum_digits() {
+            digits.push((checksum & MAX_DIGIT as usize) as u8);
+            checksum >>= DIGIT_BITS;
+        }
+        digits
+    }
+
+    pub fn bits_of(&self, digits: &[u8]) -> Vec<bool> {
+        let mut bits = digits[..self.message_digits()]
+            .iter()
+            .flat_map(|d| (0..DIGIT_BITS).map(move |i| (d >> i) & 1 == 1))
+            .collect::<Vec<bool>>();
+        bits.truncate(self.message_bits);
+        bits
+    }
+}
+
+fn hash_times(preimage: WinternitzHash, times: u8) -> WinternitzHash {
+    (0..times).fold(preimage, |acc, _| hash160::Hash::hash(&acc).to_byte_array())
+}
+
+// Wiped when dropped, and without a Debug impl so that it cannot end up in logs
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
+pub struct WinternitzSecretKey {
+    #[zeroize(skip)]
+    pub params: WinternitzParams,
+    secrets: Vec<WinternitzHash>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WinternitzPublicKey {
+    pub params: WinternitzParams,
+    pub hashes: Vec<WinternitzHash>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WinternitzSignature {
+    pub digits: Vec<u8>,
+    pub hashes: Vec<WinternitzHash>,
+}
+
+impl WinternitzSecretKey {
+    pub fn new(params: WinternitzParams) -> Self {
+        let mut rng = rand::thread_rng();
+        WinternitzSecretKey {
+            params,
+            secrets: (0..params.total_digits()).map(|_| rng.gen()).collect(),
+        }
+    }
+
+    pub fn public_key(&self) -> WinternitzPublicKey {
+        WinternitzPublicKey {
+            params: self.params,
+            hashes: self
+                .secrets
+                .iter()
+                .map(|secret| hash_times(*secret, MAX_DIGIT))
+                .collect(),
+        }
+    }
+
+    pub fn sign(&self, bits: &[bool]) -> WinternitzSignature {
+        let digits = self.params.digits_of(bits);
+        let hashes = self
+            .secrets
+            .iter()
+            .zip(digits.iter())
+            .map(|(secret, digit)| hash_times(*secret, *digit))
+            .collect();
+        WinternitzSignature { digits, hashes }
+    }
+
+    pub fn sign_wires<C: BitCommitment>(&self, wires: &Wires<C>) -> WinternitzSignature {
+        let bits = wires
+            .iter()
+            .map(|wire_arcm| wire_arcm.lock().unwrap().selector.unwrap())
+            .collect::<Vec<bool>>();
+        self.sign(&bits)
+    }
+}
+
+impl WinternitzPublicKey {
+    pub fn verify(&self, signature: &WinternitzSignature) -> bool {
+        signature.digits.len() == self.params.total_digits()
+            && signature.hashes.len() == self.params.total_digits()
+            && signature.digits.iter().all(|d| *d <= MAX_DIGIT)
+            && self
+                .params
+                .digits_of(&self.params.bits_of(&signature.digits))
+                == signature.digits
+            && signature
+                .hashes
+                .iter()
+                .zip(signature.digits.iter())
+                .zip(self.hashes.iter())
+                .all(|((hash, digit), pk)| hash_times(*hash, MAX_DIGIT - digit) == *pk)
+    }
+
+    pub fn message_from_signature(&self, signature: &WinternitzSignature) -> Option<Vec<bool>> {
+        if !self.verify(signature) {
+            return None;
+        }
+        Some(self.params.bits_of(&signature.digits))
+    }
+
+    // Two valid signatures on different messages can only come from the holder of the secret key,
+    // since the checksum prevents deriving a second signature from a published one.
+    pub fn find_equivocation(
+        &self,
+        first: &WinternitzSignature,
+        second: &WinternitzSignature,
+    ) -> Option<(WinternitzSignature, WinternitzSignature)> {
+        match (
+            self.message_from_signature(first),
+            self.message_from_signature(second),
+        ) {
+            (Some(a), Some(b)) if a != b => Some((first.clone(), second.clone())),
+            _ => None,
+        }
+    }
+}
+
+impl WinternitzSignature {
+    // The first digit ends up on top of the stack, which is the order the commitment script
+    // consumes them in.
+    pub fn to_witness(&self) -> Vec<Vec<u8>> {
+        self.hashes
+            .iter()
+            .zip(self.digits.iter())
+            .rev()
+            .flat_map(|(hash, digit)| {
+                let digit = if *digit == 0 { vec![] } else { vec![*digit] };
+                vec![hash.to_vec(), digit]
+            })
+            .collect()
+    }
+
+    pub fn from_witness(witness: &[Vec<u8>]) -> Option<Self> {
+        if !witness.len().is_multiple_of(2) {
+            return None;
+        }
+        let mut digits = Vec::new();
+        let mut hashes = Vec::new();
+        for pair in witness.chunks(2).rev() {
+            hashes.push(pair[0].as_slice().try_into().ok()?);
+            digits.push(match pair[1].as_slice() {
+                [] => 0,
+                [digit] if *digit != 0 && *digit <= MAX_DIGIT => *digit,
+                _ => return None,
+            });
+        }
+        Some(WinternitzSignature { digits, hashes })
+    }
+}
+
+// A single bit committed with a one digit Winternitz signature, so one 20-byte hash for the bit
+// and one for the checksum.
+#[derive(Debug, Clone, Copy)]
+pub struct WinternitzCommitment;
+
+impl BitCommitment for WinternitzCommitment {
+    type Secret = WinternitzSecretKey;
+    type Public = WinternitzPublicKey;
+    type Reveal = WinternitzSignature;
+
+    const REVEAL_WITNESS_LEN: usize = 4;
+
+    // The hash chain of a digit is built next to it on the stack
+    const SCRIPT_STACK_OVERHEAD: usize = MAX_DIGIT as usize + 1;
+
+    fn generate() -> (WinternitzSecretKey, WinternitzPublicKey) {
+        let secret_key = WinternitzSecretKey::new(WinternitzParams::new(1));
+        let public_key = secret_key.public_key();
+        (secret_key, public_key)
+    }
+
+    fn reveal(secret: &WinternitzSecretKey, bit: bool) -> WinternitzSignature {
+        secret.sign(&[bit])
+    }
+
+    fn parse_reveal(public: &WinternitzPublicKey, reveal: &WinternitzSignature) -> Option<bool> {
+        public.message_from_signature(reveal).map(|bits| bits[0])
+    }
+
+    fn reveal_to_witness(reveal: &WinternitzSignature) -> Vec<Vec<u8>> {
+        reveal.to_witness()
+    }
+
+    fn reveal_from_witness(witness: &[Vec<u8>]) -> Option<WinternitzSignature> {
+        WinternitzSignature::from_witness(witness)
+    }
+
+    fn add_commitment_script(public: &WinternitzPublicKey, builder: Builder) -> Builder {
+        add_winternitz_commitment_script(public, builder)
+    }
+
+    fn add_equivocation_script(public: &WinternitzPublicKey, builder: Builder) -> Builder {
+        add_winternitz_equivocation_script(public, builder)
+    }
+
+    fn equivocation_witness(zero: &WinternitzSignature, one: &WinternitzSignature) -> Vec<Vec<u8>> {
+        let mut witness = one.to_witness();
+        witness.extend(zero.to_witness());
+        witness
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::number_to_bool_array;
+
+    #[test]
+    fn test_winternitz_params() {
+        let params = WinternitzParams::new(32);
+        assert_eq!(params.message_digits(), 8);
+        assert_eq!(params.checksum_digits(), 2);
+        let params = WinternitzParams::new(1);
+        assert_eq!(params.message_digits(), 1);
+        assert_eq!(params.checksum_digits(), 1);
+    }
+
+    #[test]
+    fn test_winternitz_sign_and_verify() {
+        let secret_key = WinternitzSecretKey::new(WinternitzParams::new(32));
+        let public_key = secret_key.public_key();
+        let message = number_to_bool_array(0xdeadbeef, 32);
+        let signature = secret_key.sign(&message);
+        assert_eq!(public_key.message_from_signature(&signature), Some(message));
+
+        let mut tampered = signature.clone();
+        tampered.digits[0] += 1;
+        assert!(!public_key.verify(&tampered));
+    }
+
+    #[test]
+    fn test_winternitz_equivocation() {
+        let secret_key = WinternitzSecretKey::new(WinternitzParams::new(32));
+        let public_key = secret_key.public_key();
+        let first = secret_key.sign(&number_to_bool_array(17, 32));
+        let second = secret_key.sign(&number_to_bool_array(18, 32));
+        assert!(public_key.find_equivocation(&first, &first).is_none());
+        assert!(public_key.find_equivocation(&first, &second).is_some());
+    }
+
+    #[test]
+    fn test_winternitz_commitment() {
+        let (secret_key, public_key) = WinternitzCommitment::generate();
+        assert_eq!(
+            WinternitzCommitment::REVEAL_WITNESS_LEN,
+            2 * public_key.params.total_digits()
+        );
+        for bit in [false, true] {
+            let reveal = WinternitzCommitment::reveal(&secret_key, bit);
+            let witness = WinternitzCommitment::reveal_to_witness(&reveal);
+            let parsed = WinternitzCommitment::reveal_from_witness(&witness).unwrap();
+            assert_eq!(parsed, reveal);
+            assert_eq!(
+                WinternitzCommitment::parse_reveal(&public_key, &parsed),
+                Some(bit)
+            );
+        }
+    }
+}
@@ -1,48 +1,55 @@
 use std::fmt::Debug;
+use std::sync::Arc;
 
 use bitcoin::hashes::sha256;
 use bitcoin::hashes::Hash;
+use bitcoin::opcodes::all::{OP_EQUALVERIFY, OP_SHA256};
+use bitcoin::script::Builder;
 use rand::Rng;
 use serde::Deserialize;
 use serde::Serialize;
+use tracing::warn;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use crate::traits::bit_commitment::BitCommitment;
+use crate::transactions::add_bit_commitment_script;
 
 pub type HashValue = [u8; 32];
 pub type PreimageValue = [u8; 32];
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub struct HashTuple {
     pub zero: HashValue,
     pub one: HashValue,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+// Wiped when dropped and redacted when printed, since revealing both preimages is an equivocation
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
 pub struct PreimageTuple {
     pub zero: Option<PreimageValue>,
     pub one: Option<PreimageValue>,
 }
 
-#[derive(Clone)]
-pub struct Wire {
-    pub preimages: Option<PreimageTuple>,
-    pub hashes: HashTuple,
-    pub selector: Option<bool>,
-    pub index: Option<usize>,
-}
-
-impl Debug for Wire {
+impl Debug for PreimageTuple {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Wire[{:?}]: {:?}", self.index, self.selector)
+        write!(f, "PreimageTuple([REDACTED])")
     }
 }
 
-impl Default for Wire {
-    fn default() -> Self {
-        Self::new(0)
-    }
-}
+// The original commitment scheme, a pair of SHA256 hashes with one preimage per bit value.
+#[derive(Debug, Clone, Copy)]
+pub struct HashCommitment;
 
-impl Wire {
-    pub fn new(index: usize) -> Self {
+impl BitCommitment for HashCommitment {
+    type Secret = PreimageTuple;
+    type Public = HashTuple;
+    type Reveal = PreimageValue;
+
+    const REVEAL_WITNESS_LEN: usize = 1;
+
+    const SCRIPT_STACK_OVERHEAD: usize = 3;
+
+    fn generate() -> (PreimageTuple, HashTuple) {
         let mut rng = rand::thread_rng();
 
         let preimage1: [u8; 32] = rng.gen();
@@ -51,74 +58,237 @@ impl Wire {
         let hash1 = sha256::Hash::hash(&preimage1).to_byte_array();
         let hash2 = sha256::Hash::hash(&preimage2).to_byte_array();
 
-        Wire {
-            preimages: Some(PreimageTuple {
+        (
+            PreimageTuple {
                 zero: Some(preimage1),
                 one: Some(preimage2),
-            }),
-            hashes: HashTuple {
+            },
+            HashTuple {
                 zero: hash1,
                 one: hash2,
             },
+        )
+    }
+
+    fn reveal(secret: &PreimageTuple, bit: bool) -> PreimageValue {
+        if !bit {
+            secret.zero.unwrap()
+        } else {
+            secret.one.unwrap()
+        }
+    }
+
+    fn parse_reveal(public: &HashTuple, reveal: &PreimageValue) -> Option<bool> {
+        let hash = sha256::Hash::hash(reveal).to_byte_array();
+        if hash == public.zero {
+            Some(false)
+        } else if hash == public.one {
+            Some(true)
+        } else {
+            None
+        }
+    }
+
+    fn reveal_to_witness(reveal: &PreimageValue) -> Vec<Vec<u8>> {
+        vec![reveal.to_vec()]
+    }
+
+    fn reveal_from_witness(witness: &[Vec<u8>]) -> Option<PreimageValue> {
+        witness.first()?.as_slice().try_into().ok()
+    }
+
+    fn add_commitment_script(public: &HashTuple, builder: Builder) -> Builder {
+        add_bit_commitment_script(*public, builder)
+    }
+
+    fn add_equivocation_script(public: &HashTuple, builder: Builder) -> Builder {
+        builder
+            .push_opcode(OP_SHA256)
+            .push_slice(public.zero)
+            .push_opcode(OP_EQUALVERIFY)
+            .push_opcode(OP_SHA256)
+            .push_slice(public.one)
+            .push_opcode(OP_EQUALVERIFY)
+    }
+
+    fn equivocation_witness(zero: &PreimageValue, one: &PreimageValue) -> Vec<Vec<u8>> {
+        vec![one.to_vec(), zero.to_vec()]
+    }
+}
+
+#[derive(Debug)]
+pub struct RevealTuple<R> {
+    pub zero: Option<R>,
+    pub one: Option<R>,
+}
+
+impl<R: Clone> Clone for RevealTuple<R> {
+    fn clone(&self) -> Self {
+        RevealTuple {
+            zero: self.zero.clone(),
+            one: self.one.clone(),
+        }
+    }
+}
+
+// What a verifier learns from a single reveal of a wire
+pub enum RevealOutcome<C: BitCommitment = HashCommitment> {
+    NewBit(bool),
+    AlreadyKnown(bool),
+    // Both values of the wire have been revealed, the wire holds the proof
+    Equivocation(Wire<C>),
+}
+
+impl<C: BitCommitment> Debug for RevealOutcome<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RevealOutcome::NewBit(bit) => write!(f, "NewBit({})", bit),
+            RevealOutcome::AlreadyKnown(bit) => write!(f, "AlreadyKnown({})", bit),
+            RevealOutcome::Equivocation(wire) => write!(f, "Equivocation({:?})", wire),
+        }
+    }
+}
+
+// Reasons to reject reveals coming from the counterparty, e.g. read from a transaction on chain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RevealError {
+    // The reveal opens neither value of the wire's commitment
+    UnknownReveal { wire: Option<usize> },
+    // The witness elements cannot be decoded as a reveal
+    MalformedReveal { position: usize },
+    WrongWitnessLength { expected: usize, found: usize },
+}
+
+pub struct Wire<C: BitCommitment = HashCommitment> {
+    // Shared by the copies of the wire in every game, so the secret is never duplicated
+    pub secret: Option<Arc<C::Secret>>,
+    pub commitment: C::Public,
+    pub reveals: RevealTuple<C::Reveal>,
+    pub selector: Option<bool>,
+    pub index: Option<usize>,
+}
+
+impl<C: BitCommitment> Clone for Wire<C> {
+    fn clone(&self) -> Self {
+        Wire {
+            secret: self.secret.clone(),
+            commitment: self.commitment.clone(),
+            reveals: self.reveals.clone(),
+            selector: self.selector,
+            index: self.index,
+        }
+    }
+}
+
+impl<C: BitCommitment> Debug for Wire<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Wire[{:?}]: {:?}", self.index, self.selector)
+    }
+}
+
+impl<C: BitCommitment> Default for Wire<C> {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl<C: BitCommitment> Wire<C> {
+    pub fn new(index: usize) -> Self {
+        let (secret, commitment) = C::generate();
+        Wire {
+            secret: Some(Arc::new(secret)),
+            commitment,
+            reveals: RevealTuple {
+                zero: None,
+                one: None,
+            },
             selector: None,
             index: Some(index),
         }
     }
 
-    pub fn new_with_hash_pair(index: usize, hash_pair: HashTuple) -> Self {
+    pub fn new_with_commitment(index: usize, commitment: C::Public) -> Self {
         Wire {
-            preimages: None,
-            hashes: hash_pair,
+            secret: None,
+            commitment,
+            reveals: RevealTuple {
+                zero: None,
+                one: None,
+            },
             selector: None,
             index: Some(index),
         }
     }
 
-    pub fn get_hash_pair(&self) -> HashTuple {
-        self.hashes
+    // Copy of the wire for another game played on the same commitment. The secret is shared, but
+    // the value and the reveals are the copy's own, so evaluating one game leaves the others alone.
+    pub fn share_commitment(&self) -> Self {
+        Wire {
+            secret: self.secret.clone(),
+            commitment: self.commitment.clone(),
+            reveals: RevealTuple {
+                zero: None,
+                one: None,
+            },
+            selector: None,
+            index: self.index,
+        }
+    }
+
+    pub fn get_commitment(&self) -> C::Public {
+        self.commitment.clone()
     }
 
-    pub fn get_preimage_of_selector(&self) -> [u8; 32] {
-        match self.preimages {
-            Some(preimage_tuple) => match self.selector {
-                Some(b) => {
-                    if !b {
-                        preimage_tuple.zero.unwrap()
-                    } else {
-                        preimage_tuple.one.unwrap()
-                    }
-                }
+    pub fn get_reveal_of_selector(&self) -> C::Reveal {
+        match &self.secret {
+            Some(secret) => match self.selector {
+                Some(b) => C::reveal(secret, b),
                 None => panic!("selector is not set"),
             },
-            None => panic!("preimages are not set"),
+            None => panic!("secret is not set"),
         }
     }
 
-    pub fn add_preimage(&mut self, preimage: PreimageValue) -> Option<Wire> {
-        let hash = sha256::Hash::hash(&preimage).to_byte_array();
-        if hash == self.hashes.zero {
-            self.preimages = Some(PreimageTuple {
-                zero: Some(preimage),
-                one: match self.preimages {
-                    Some(cur) => cur.one,
-                    None => None,
-                },
-            });
-        } else if hash == self.hashes.one {
-            self.preimages = Some(PreimageTuple {
-                zero: match self.preimages {
-                    Some(cur) => cur.zero,
-                    None => None,
-                },
-                one: Some(preimage),
-            });
+    // Checks a reveal against the commitment without recording it, returning the revealed bit
+    pub fn check_reveal(&self, reveal: &C::Reveal) -> Result<bool, RevealError> {
+        C::parse_reveal(&self.commitment, reveal)
+            .ok_or(RevealError::UnknownReveal { wire: self.index })
+    }
+
+    pub fn add_reveal(&mut self, reveal: C::Reveal) -> Result<RevealOutcome<C>, RevealError> {
+        let bit = self.check_reveal(&reveal)?;
+        let (known, other) = if bit {
+            (&mut self.reveals.one, &self.reveals.zero)
         } else {
-            panic!("preimage does not match either hash");
+            (&mut self.reveals.zero, &self.reveals.one)
+        };
+        if known.is_some() {
+            return Ok(RevealOutcome::AlreadyKnown(bit));
+        }
+        let equivocated = other.is_some();
+        *known = Some(reveal);
+        if equivocated {
+            warn!(wire = self.index, "equivocation detected");
+            return Ok(RevealOutcome::Equivocation(self.clone()));
         }
-        if self.preimages.unwrap().zero.is_some() && self.preimages.unwrap().one.is_some() {
-            return Some(self.clone());
+        Ok(RevealOutcome::NewBit(bit))
+    }
+
+    // A revealed value of the wire, preferring zero if both were revealed
+    pub fn get_known_reveal(&self) -> Option<(bool, C::Reveal)> {
+        match (&self.reveals.zero, &self.reveals.one) {
+            (Some(zero), _) => Some((false, zero.clone())),
+            (None, Some(one)) => Some((true, one.clone())),
+            (None, None) => None,
+        }
+    }
+
+    // Witness elements proving that both values of this wire were revealed
+    pub fn get_equivocation_witness(&self) -> Option<Vec<Vec<u8>>> {
+        match (&self.reveals.zero, &self.reveals.one) {
+            (Some(zero), Some(one)) => Some(C::equivocation_witness(zero, one)),
+            _ => None,
         }
-        None
     }
 }
 
@@ -128,8 +298,41 @@ mod tests {
 
     #[test]
     fn test_wire() {
-        let wire = Wire::new(0);
-        assert!(wire.preimages.is_some());
+        let wire: Wire = Wire::new(0);
+        assert!(wire.secret.is_some());
         assert!(wire.selector.is_none());
     }
+
+    #[test]
+    fn test_wire_equivocation() {
+        let mut wire: Wire = Wire::new(0);
+        let mut verifier_wire: Wire = Wire::new_with_commitment(0, wire.get_commitment());
+        wire.selector = Some(false);
+        assert!(matches!(
+            verifier_wire.add_reveal(wire.get_reveal_of_selector()),
+            Ok(RevealOutcome::NewBit(false))
+        ));
+        assert!(matches!(
+            verifier_wire.add_reveal(wire.get_reveal_of_selector()),
+            Ok(RevealOutcome::AlreadyKnown(false))
+        ));
+        wire.selector = Some(true);
+        match verifier_wire.add_reveal(wire.get_reveal_of_selector()) {
+            Ok(RevealOutcome::Equivocation(proof)) => {
+                assert!(proof.get_equivocation_witness().is_some())
+            }
+            outcome => panic!("expected an equivocation, got {:?}", outcome),
+        }
+    }
+
+    #[test]
+    fn test_wire_unknown_reveal() {
+        let wire: Wire = Wire::new(3);
+        let mut verifier_wire: Wire = Wire::new_with_commitment(3, wire.get_commitment());
+        assert_eq!(
+            verifier_wire.add_reveal([7; 32]).unwrap_err(),
+            RevealError::UnknownReveal { wire: Some(3) }
+        );
+        assert!(verifier_wire.reveals.zero.is_none() && verifier_wire.reveals.one.is_none());
+    }
 }
@@ -1,9 +1,45 @@
 use futures_util::{SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
+use std::any::type_name;
+use std::env;
 use std::error::Error;
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio_tungstenite::tungstenite::protocol::Message;
 use tokio_tungstenite::WebSocketStream;
+use tracing::{debug, info};
+
+// Comma separated websocket URLs of the verifiers the prover plays against, one game each
+pub const VERIFIERS_ENV_VAR: &str = "TOY_BITVM_VERIFIERS";
+// Address the verifier listens on for provers
+pub const VERIFIER_ADDRESS_ENV_VAR: &str = "TOY_BITVM_VERIFIER_ADDRESS";
+
+const DEFAULT_VERIFIER_ADDRESS: &str = "127.0.0.1:9000";
+
+pub fn verifier_urls() -> Vec<String> {
+    env::var(VERIFIERS_ENV_VAR)
+        .map(|urls| {
+            urls.split(',')
+                .map(|url| url.trim().to_string())
+                .filter(|url| !url.is_empty())
+                .collect()
+        })
+        .unwrap_or_else(|_| vec![format!("ws://{}", DEFAULT_VERIFIER_ADDRESS)])
+}
+
+pub fn verifier_address() -> String {
+    env::var(VERIFIER_ADDRESS_ENV_VAR).unwrap_or_else(|_| DEFAULT_VERIFIER_ADDRESS.to_string())
+}
+
+// Sent by the verifier at the start of every round, so that the prover knows whether to wait
+// for a challenge on chain or to co-sign a cooperative close instead.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoundDecision {
+    Challenge,
+    Close,
+    // Stops challenging when either side cannot sign a cooperative close, leaving the
+    // equivocation output to the prover's timelock leaf
+    Finish,
+}
 
 pub async fn send_message<T, M>(
     ws_stream: &mut WebSocketStream<T>,
@@ -14,6 +50,11 @@ where
     M: Serialize,
 {
     let serialized = serde_json::to_string(message)?;
+    info!(
+        kind = type_name::<M>(),
+        bytes = serialized.len(),
+        "sent message"
+    );
     ws_stream.send(Message::Text(serialized)).await?;
     Ok(())
 }
@@ -27,6 +68,11 @@ where
         let msg = msg?;
         if let Message::Text(text) = msg {
             let deserialized: M = serde_json::from_str(&text)?;
+            debug!(
+                kind = type_name::<M>(),
+                bytes = text.len(),
+                "received message"
+            );
             return Ok(deserialized);
         }
     }
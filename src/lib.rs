@@ -1,6 +1,15 @@
 pub mod actor;
 pub mod circuit;
 pub mod communication;
+pub mod logging;
+pub mod musig;
+pub mod report;
+pub mod secret;
+pub mod session;
+pub mod signer;
+#[cfg(test)]
+mod test_utils;
+pub mod tower;
 pub mod traits;
 pub mod transactions;
 pub mod utils;
@@ -0,0 +1,81 @@
+use std::env;
+
+use tracing::Subscriber;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::EnvFilter;
+
+// `json` for one JSON object per line, with the session and round spans of every event, anything
+// else for human readable logs. Levels are picked with RUST_LOG and default to info.
+pub const LOG_FORMAT_ENV_VAR: &str = "TOY_BITVM_LOG_FORMAT";
+
+fn subscriber<W>(json: bool, filter: EnvFilter, writer: W) -> Box<dyn Subscriber + Send + Sync>
+where
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(writer);
+    if json {
+        Box::new(
+            builder
+                .json()
+                .with_current_span(true)
+                .with_span_list(true)
+                .finish(),
+        )
+    } else {
+        Box::new(builder.finish())
+    }
+}
+
+// Logs of the binaries go to stderr, so that stdout is left to the prompts
+pub fn init_logging() {
+    let json = env::var(LOG_FORMAT_ENV_VAR).is_ok_and(|format| format == "json");
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    tracing::subscriber::set_global_default(subscriber(json, filter, std::io::stderr))
+        .expect("logging should only be initialized once");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_json_logs() {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = subscriber(true, EnvFilter::new("info"), move || writer.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            let session = tracing::info_span!("session", role = "verifier");
+            let _session = session.enter();
+            let round = tracing::info_span!("round", round = 3);
+            let _round = round.enter();
+            tracing::info!(kind = "challenge", "broadcast transaction");
+            tracing::debug!("filtered out");
+        });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 1);
+        let event: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(event["fields"]["message"], "broadcast transaction");
+        assert_eq!(event["fields"]["kind"], "challenge");
+        assert_eq!(event["span"]["round"], 3);
+        assert_eq!(event["spans"][0]["role"], "verifier");
+    }
+}
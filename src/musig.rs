@@ -0,0 +1,295 @@
+// Minimal two-round MuSig2-style key aggregation and signing, enough to let the prover and the
+// verifier co-sign a taproot key-path spend of an output whose internal key is their aggregate key.
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::secp256k1::schnorr::Signature;
+use bitcoin::secp256k1::{
+    All, Keypair, Parity, PublicKey, Scalar, Secp256k1, SecretKey, XOnlyPublicKey,
+};
+use bitcoin::{TapNodeHash, TapTweakHash};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+fn tagged_hash(tag: &str, data: &[&[u8]]) -> [u8; 32] {
+    let tag_hash = sha256::Hash::hash(tag.as_bytes());
+    let mut engine = sha256::Hash::engine();
+    engine.input(tag_hash.as_byte_array());
+    engine.input(tag_hash.as_byte_array());
+    for d in data {
+        engine.input(d);
+    }
+    sha256::Hash::from_engine(engine).to_byte_array()
+}
+
+fn hash_to_scalar(tag: &str, data: &[&[u8]]) -> Scalar {
+    Scalar::from_be_bytes(tagged_hash(tag, data)).expect("hash should be a valid scalar")
+}
+
+fn is_odd(pk: &PublicKey) -> bool {
+    pk.x_only_public_key().1 == Parity::Odd
+}
+
+#[derive(Clone, Debug)]
+pub struct KeyAggContext {
+    pubkeys: Vec<XOnlyPublicKey>,
+    coefficients: Vec<Scalar>,
+    internal_key: PublicKey,
+    output_key: PublicKey,
+    tweak: Scalar,
+}
+
+impl KeyAggContext {
+    pub fn new(
+        secp: &Secp256k1<All>,
+        pubkeys: &[XOnlyPublicKey],
+        merkle_root: Option<TapNodeHash>,
+    ) -> Self {
+        assert!(!pubkeys.is_empty(), "at least one public key is required");
+        let serialized_keys = pubkeys
+            .iter()
+            .map(|pk| pk.serialize())
+            .collect::<Vec<[u8; 32]>>();
+        let key_list_hash = tagged_hash(
+            "KeyAgg list",
+            &serialized_keys
+                .iter()
+                .map(|k| k.as_slice())
+                .collect::<Vec<&[u8]>>(),
+        );
+        let coefficients = serialized_keys
+            .iter()
+            .map(|k| hash_to_scalar("KeyAgg coefficient", &[&key_list_hash, k]))
+            .collect::<Vec<Scalar>>();
+        let weighted_keys = pubkeys
+            .iter()
+            .zip(coefficients.iter())
+            .map(|(pk, a)| {
+                PublicKey::from_x_only_public_key(*pk, Parity::Even)
+                    .mul_tweak(secp, a)
+                    .expect("coefficient should be non-zero")
+            })
+            .collect::<Vec<PublicKey>>();
+        let internal_key =
+            PublicKey::combine_keys(&weighted_keys.iter().collect::<Vec<&PublicKey>>())
+                .expect("aggregate key should not be infinity");
+        let tweak =
+            TapTweakHash::from_key_and_tweak(internal_key.x_only_public_key().0, merkle_root)
+                .to_scalar();
+        let even_internal_key = if is_odd(&internal_key) {
+            internal_key.negate(secp)
+        } else {
+            internal_key
+        };
+        let output_key = even_internal_key
+            .add_exp_tweak(secp, &tweak)
+            .expect("tweaked key should not be infinity");
+        KeyAggContext {
+            pubkeys: pubkeys.to_vec(),
+            coefficients,
+            internal_key,
+            output_key,
+            tweak,
+        }
+    }
+
+    pub fn internal_key(&self) -> XOnlyPublicKey {
+        self.internal_key.x_only_public_key().0
+    }
+
+    pub fn output_key(&self) -> XOnlyPublicKey {
+        self.output_key.x_only_public_key().0
+    }
+
+    fn coefficient_of(&self, pubkey: XOnlyPublicKey) -> Scalar {
+        let index = self
+            .pubkeys
+            .iter()
+            .position(|pk| *pk == pubkey)
+            .expect("signer is not part of the aggregate key");
+        self.coefficients[index]
+    }
+
+    pub fn session(
+        &self,
+        secp: &Secp256k1<All>,
+        public_nonces: &[PublicNonce],
+        message: [u8; 32],
+    ) -> SigningSession {
+        assert_eq!(
+            public_nonces.len(),
+            self.pubkeys.len(),
+            "wrong number of public nonces"
+        );
+        let r1 = PublicKey::combine_keys(&public_nonces.iter().map(|n| &n.r1).collect::<Vec<_>>())
+            .expect("aggregate nonce should not be infinity");
+        let r2 = PublicKey::combine_keys(&public_nonces.iter().map(|n| &n.r2).collect::<Vec<_>>())
+            .expect("aggregate nonce should not be infinity");
+        let b = hash_to_scalar(
+            "MuSig/noncecoef",
+            &[
+                &r1.serialize(),
+                &r2.serialize(),
+                &self.output_key().serialize(),
+                &message,
+            ],
+        );
+        let r = r1
+            .combine(
+                &r2.mul_tweak(secp, &b)
+                    .expect("nonce coefficient should be non-zero"),
+            )
+            .expect("final nonce should not be infinity");
+        let e = hash_to_scalar(
+            "BIP0340/challenge",
+            &[
+                &r.x_only_public_key().0.serialize(),
+                &self.output_key().serialize(),
+                &message,
+            ],
+        );
+        SigningSession {
+            key_agg_ctx: self.clone(),
+            final_nonce: r,
+            nonce_coefficient: b,
+            challenge: e,
+        }
+    }
+}
+
+pub struct SecretNonce {
+    k1: SecretKey,
+    k2: SecretKey,
+}
+
+// A nonce that leaks along with its partial signature gives away the secret key
+impl Drop for SecretNonce {
+    fn drop(&mut self) {
+        self.k1.non_secure_erase();
+        self.k2.non_secure_erase();
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct PublicNonce {
+    pub r1: PublicKey,
+    pub r2: PublicKey,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct PartialSignature(pub [u8; 32]);
+
+pub fn generate_nonce(secp: &Secp256k1<All>) -> (SecretNonce, PublicNonce) {
+    let mut rng = rand::thread_rng();
+    let k1 = SecretKey::from_slice(&rng.gen::<[u8; 32]>()).expect("random nonce");
+    let k2 = SecretKey::from_slice(&rng.gen::<[u8; 32]>()).expect("random nonce");
+    let public_nonce = PublicNonce {
+        r1: k1.public_key(secp),
+        r2: k2.public_key(secp),
+    };
+    (SecretNonce { k1, k2 }, public_nonce)
+}
+
+pub struct SigningSession {
+    key_agg_ctx: KeyAggContext,
+    final_nonce: PublicKey,
+    nonce_coefficient: Scalar,
+    challenge: Scalar,
+}
+
+impl SigningSession {
+    // The nonce is taken by value so that it can never be used for a second signature.
+    pub fn partial_sign(&self, keypair: &Keypair, secret_nonce: SecretNonce) -> PartialSignature {
+        let (pubkey, parity) = keypair.x_only_public_key();
+        let mut d = keypair.secret_key();
+        if parity == Parity::Odd {
+            d = d.negate();
+        }
+        if is_odd(&self.key_agg_ctx.internal_key) != is_odd(&self.key_agg_ctx.output_key) {
+            d = d.negate();
+        }
+        let mut k = secret_nonce
+            .k2
+            .mul_tweak(&self.nonce_coefficient)
+            .and_then(|bk2| bk2.add_tweak(&Scalar::from(secret_nonce.k1)))
+            .expect("nonce should be non-zero");
+        if is_odd(&self.final_nonce) {
+            k = k.negate();
+        }
+        let ead = d
+            .mul_tweak(&self.key_agg_ctx.coefficient_of(pubkey))
+            .and_then(|ad| ad.mul_tweak(&self.challenge))
+            .expect("partial signature should be non-zero");
+        let s = k
+            .add_tweak(&Scalar::from(ead))
+            .expect("partial signature should be non-zero");
+        PartialSignature(s.secret_bytes())
+    }
+
+    pub fn aggregate(&self, partial_signatures: &[PartialSignature]) -> Signature {
+        let mut et = SecretKey::from_slice(&self.key_agg_ctx.tweak.to_be_bytes())
+            .and_then(|t| t.mul_tweak(&self.challenge))
+            .expect("tweak should be non-zero");
+        if is_odd(&self.key_agg_ctx.output_key) {
+            et = et.negate();
+        }
+        let s = partial_signatures.iter().fold(et, |acc, partial| {
+            acc.add_tweak(
+                &Scalar::from_be_bytes(partial.0).expect("partial signature should be a scalar"),
+            )
+            .expect("signature should be non-zero")
+        });
+        let mut signature = [0u8; 64];
+        signature[..32].copy_from_slice(&self.final_nonce.x_only_public_key().0.serialize());
+        signature[32..].copy_from_slice(&s.secret_bytes());
+        Signature::from_slice(&signature).expect("should be a signature")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::secp256k1::Message;
+    use bitcoin::Address;
+
+    #[test]
+    fn test_two_party_key_spend_signature() {
+        let secp = Secp256k1::new();
+        let mut rng = rand::thread_rng();
+        let keypairs = (0..2)
+            .map(|_| Keypair::new(&secp, &mut rng))
+            .collect::<Vec<Keypair>>();
+        let pubkeys = keypairs
+            .iter()
+            .map(|kp| kp.x_only_public_key().0)
+            .collect::<Vec<XOnlyPublicKey>>();
+        let merkle_root = Some(TapNodeHash::from_byte_array(rng.gen()));
+        let ctx = KeyAggContext::new(&secp, &pubkeys, merkle_root);
+
+        let address = Address::p2tr(
+            &secp,
+            ctx.internal_key(),
+            merkle_root,
+            bitcoin::Network::Regtest,
+        );
+        assert_eq!(
+            address.script_pubkey().as_bytes()[2..],
+            ctx.output_key().serialize()
+        );
+
+        let message: [u8; 32] = rng.gen();
+        let (secnonces, pubnonces): (Vec<SecretNonce>, Vec<PublicNonce>) =
+            (0..2).map(|_| generate_nonce(&secp)).unzip();
+        let session = ctx.session(&secp, &pubnonces, message);
+        let partial_signatures = keypairs
+            .iter()
+            .zip(secnonces)
+            .map(|(kp, secnonce)| session.partial_sign(kp, secnonce))
+            .collect::<Vec<PartialSignature>>();
+        let signature = session.aggregate(&partial_signatures);
+        secp.verify_schnorr(
+            &signature,
+            &Message::from_digest(message),
+            &ctx.output_key(),
+        )
+        .unwrap();
+    }
+}
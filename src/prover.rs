@@ -1,4 +1,5 @@
 use std::borrow::BorrowMut;
+use std::sync::Arc;
 use std::time::Duration;
 
 use bitcoin::absolute::{Height, LockTime};
@@ -7,102 +8,167 @@ use bitcoin::hashes::{sha256, Hash};
 use bitcoin::secp256k1::schnorr::Signature;
 use bitcoin::secp256k1::Message;
 use bitcoin::sighash::SighashCache;
-use bitcoin::taproot::LeafVersion;
-use bitcoin::{secp256k1::Secp256k1, Amount, Transaction, XOnlyPublicKey};
+use bitcoin::taproot::{LeafVersion, TaprootSpendInfo};
+use bitcoin::{secp256k1::Secp256k1, Transaction};
 use bitcoin::{OutPoint, ScriptBuf, TapLeafHash, TxIn, TxOut, Witness};
 
-use bitcoincore_rpc::{Auth, Client, RpcApi};
 use toy_bitvm::{
-    actor::Actor,
+    actor::{Actor, ActorPublicKeys},
     circuit::wire::{HashTuple, HashValue, PreimageValue},
-    circuit::Circuit,
-    communication::{receive_message, send_message},
+    circuit::{chunk::ChunkLimits, Circuit},
+    communication::{receive_message, send_message, verifier_urls, RoundDecision},
+    logging::init_logging,
+    musig::{generate_nonce, KeyAggContext, PartialSignature, PublicNonce},
+    report::{response_reveals, GameReport, Outcome, Role},
+    transactions::cache::TaprootCache,
+    transactions::cpfp::{with_anchor, FeeBumper},
+    transactions::fees::{anchors_enabled, choose_fee_rate, FeeSchedule, DUST_LIMIT},
+    transactions::funding::fund_with_psbt,
+    transactions::psbt::{create_psbt, export_psbt, InputSpend},
+    transactions::rpc::{call_blocking, rpc_client, rpc_wallet},
     transactions::{
-        generate_2_of_2_script, generate_equivoation_address_and_info,
-        generate_gate_response_script, generate_response_second_address_and_info,
+        broadcast_transaction, cooperative_close_sighash, create_cooperative_close_tx,
+        generate_2_of_2_script, generate_2_of_2_witness,
+        generate_compact_equivocation_address_and_info, generate_gate_response_script,
+        generate_gate_response_witness, generate_response_second_address_and_info,
         watch_transaction,
     },
-    transactions::{generate_challenge_address_and_info, generate_response_address_and_info},
     utils::number_to_bool_array,
 };
 
 use tokio_tungstenite::connect_async;
+use tracing::{debug, field, info, info_span, Instrument, Span};
+
+const CIRCUIT: &str = "bristol/add.txt";
 
 #[tokio::main]
 async fn main() {
-    let url = "ws://127.0.0.1:9000";
-    let (mut ws_stream, _) = connect_async(url).await.expect("Failed to connect");
-    println!("WebSocket handshake has been successfully completed");
+    init_logging();
+    // The prover commits to the wires once and plays a game against every verifier. Each game has
+    // its own funding, keys and presigned transactions, so every verifier can challenge and slash
+    // on its own.
+    let committed: Circuit = Circuit::from_bristol(CIRCUIT, None);
+    let games = verifier_urls()
+        .into_iter()
+        .enumerate()
+        .map(|(game, url)| {
+            // Challenges and responses cover chunks of consecutive gates instead of single gates
+            let circuit = Circuit::from_bristol_with_wires(CIRCUIT, &committed.wires)
+                .into_chunks(&ChunkLimits::default());
+            let span = info_span!(
+                "session",
+                role = "prover",
+                verifier = %url,
+                game = field::Empty
+            );
+            tokio::spawn(play(url, game as u32, circuit).instrument(span))
+        })
+        .collect::<Vec<_>>();
+    for game in games {
+        game.await.unwrap();
+    }
+}
 
-    send_message(&mut ws_stream, &"bristol/add.txt".to_string())
+async fn play(url: String, game: u32, mut circuit: Circuit) {
+    let (mut ws_stream, _) = connect_async(url.as_str())
+        .await
+        .expect("Failed to connect");
+    info!(url, "connected to the verifier");
+
+    send_message(&mut ws_stream, &CIRCUIT.to_string())
         .await
         .unwrap();
 
-    let verifier_public_key_str: String = receive_message(&mut ws_stream).await.unwrap();
-    let verifier_public_key: XOnlyPublicKey = verifier_public_key_str.parse().unwrap();
-    println!("Verifier public key: {}", verifier_public_key);
-    let mut prover = Actor::new();
+    let verifier_keys: ActorPublicKeys = receive_message(&mut ws_stream).await.unwrap();
+    let verifier_public_key = verifier_keys.presign;
+    info!(keys = ?verifier_keys, "received the verifier public keys");
+    let mut prover = Actor::from_env_for_game(game);
     let prover_public_key = prover.public_key;
-    println!("Prover public key: {}", prover_public_key);
-    send_message(&mut ws_stream, &prover_public_key.to_string())
+    let prover_response_key = prover.response_public_key;
+    info!(keys = ?prover.public_keys(), "prover public keys");
+    send_message(&mut ws_stream, &prover.public_keys())
+        .await
+        .unwrap();
+    // The verifier only asks for a cooperative close if both sides can sign it
+    send_message(&mut ws_stream, &prover.can_close())
         .await
         .unwrap();
 
     // NOW PUBLIC KEY EXCHANGE IS COMPLETE
 
-    let mut circuit = Circuit::from_bristol("bristol/add.txt", None);
     let secp = Secp256k1::new();
-    let wire_hashes: Vec<HashTuple> = circuit.get_wire_hashes();
+    let wire_hashes: Vec<HashTuple> = circuit.get_wire_commitments();
 
     send_message(&mut ws_stream, &wire_hashes).await.unwrap();
 
     let bisection_length = 10;
+    let mut taproot_cache = TaprootCache::new();
 
-    let (equivocation_address, _) = generate_equivoation_address_and_info(
-        &secp,
-        &circuit,
-        prover_public_key,
-        verifier_public_key,
-    );
+    let (equivocation_address, equivocation_taproot_info) =
+        generate_compact_equivocation_address_and_info(
+            &secp,
+            &circuit,
+            prover_public_key,
+            verifier_public_key,
+        );
 
     let (response_second_address, response_second_taproot_info) =
         generate_response_second_address_and_info(&secp, prover_public_key, verifier_public_key);
 
-    let rpc = Client::new(
-        "http://localhost:18443/wallet/admin",
-        Auth::UserPass("admin".to_string(), "admin".to_string()),
-    )
-    .unwrap_or_else(|e| panic!("Failed to connect to Bitcoin RPC: {}", e));
+    let rpc = Arc::new(rpc_client());
 
-    let amt: u64 = 100_000;
-    let fee: u64 = 500;
-    let dust_limit: u64 = 546;
     let watch_interval = Duration::from_secs(1);
 
-    let initial_fund_txid = rpc
-        .send_to_address(
-            &prover.address,
-            Amount::from_sat(amt),
-            None,
-            None,
-            None,
-            None,
-            None,
-            None,
-        )
-        .unwrap_or_else(|e| panic!("Failed to send to address: {}", e));
-
-    // Send the initial fund address to the verifier
-    send_message(&mut ws_stream, &initial_fund_txid)
+    // Every transaction pays for its largest possible witness, and the game is funded up front
+    // with enough for all the rounds
+    let fee_rate = call_blocking(&rpc, choose_fee_rate).await;
+    send_message(&mut ws_stream, &fee_rate.to_sat_per_kwu())
         .await
         .unwrap();
+    // Anchors let either side bump the fee of the presigned transactions it broadcasts
+    let anchors = anchors_enabled();
+    send_message(&mut ws_stream, &anchors).await.unwrap();
+    let fee_schedule = FeeSchedule::new(
+        fee_rate,
+        &circuit,
+        prover_public_key,
+        verifier_public_key,
+        &equivocation_taproot_info,
+        &response_second_taproot_info,
+        anchors,
+    );
+    let funding = fee_schedule.required_funding(bisection_length as u64);
+    info!(%funding, %fee_rate, "funding the game");
 
-    let initial_fund_tx = rpc
-        .get_transaction(&initial_fund_txid, None)
-        .unwrap_or_else(|e| panic!("Failed to get transaction: {}", e));
+    // The funding output is found by its script, so the wallet is free to add inputs and change
+    let funding_output = TxOut {
+        script_pubkey: prover.address.script_pubkey(),
+        value: funding,
+    };
+    let funding_outpoint = call_blocking(&rpc, move |rpc| {
+        fund_with_psbt(rpc, funding_output, fee_rate).map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap_or_else(|e| panic!("Failed to fund the game: {}", e));
+    // The funding outpoint is known to both sides, so it ties their logs of the game together
+    Span::current().record("game", field::display(funding_outpoint));
+    info!(%funding_outpoint, "game funded");
+
+    // Send the funding outpoint to the verifier
+    send_message(&mut ws_stream, &funding_outpoint)
+        .await
+        .unwrap();
+    let mut report = GameReport::new(
+        Role::Prover,
+        CIRCUIT,
+        circuit.get_wire_commitments(),
+        prover.public_keys(),
+        verifier_keys,
+        funding_outpoint,
+    );
+    let mut fee_bumper = FeeBumper::new(&prover, &rpc_wallet(), &fee_schedule, watch_interval);
 
-    let mut last_txid = initial_fund_txid;
-    let _last_vout = initial_fund_tx.details[0].vout;
+    let mut last_txid = funding_outpoint.txid;
     let mut last_output: Vec<TxOut> = Vec::new();
     let mut kickoff_tx: Transaction = Transaction {
         version: bitcoin::transaction::Version::TWO,
@@ -112,94 +178,145 @@ async fn main() {
     };
 
     for i in 0..bisection_length as u64 {
-        println!("Bisection iteration: {}", i);
-        let challenge_hashes: Vec<HashValue> = receive_message(&mut ws_stream).await.unwrap();
-        prover.add_challenge_hashes(challenge_hashes.clone());
-        let (challenge_address, _) = generate_challenge_address_and_info(
-            &secp,
-            &circuit,
-            prover_public_key,
-            verifier_public_key,
-            &challenge_hashes,
-        );
+        async {
+            let challenge_hashes: Vec<HashValue> = receive_message(&mut ws_stream).await.unwrap();
+            prover.add_challenge_hashes(challenge_hashes.clone());
+            let (challenge_address, challenge_taproot_info) = taproot_cache
+                .challenge_address_and_info(
+                    &secp,
+                    i as usize,
+                    &circuit,
+                    prover_public_key,
+                    verifier_public_key,
+                    &challenge_hashes,
+                );
+
+            let (response_address, _) = taproot_cache.response_address_and_info(
+                &secp,
+                i as usize,
+                &circuit,
+                prover_response_key,
+                &challenge_hashes,
+            );
 
-        let (response_address, _) = generate_response_address_and_info(
-            &secp,
-            &circuit,
-            prover_public_key,
-            &challenge_hashes,
-        );
+            let outputs1 = vec![
+                TxOut {
+                    script_pubkey: challenge_address.script_pubkey(),
+                    value: DUST_LIMIT,
+                },
+                TxOut {
+                    script_pubkey: equivocation_address.script_pubkey(),
+                    value: fee_schedule.equivocation_value(funding, i),
+                },
+            ];
 
-        let outputs1 = vec![
-            TxOut {
-                script_pubkey: challenge_address.script_pubkey(),
-                value: Amount::from_sat(dust_limit),
-            },
-            TxOut {
-                script_pubkey: equivocation_address.script_pubkey(),
-                value: Amount::from_sat(amt - (2 * i + 1) * (fee + dust_limit)),
-            },
-        ];
-
-        let outputs2 = vec![
-            TxOut {
-                script_pubkey: response_address.script_pubkey(),
-                value: Amount::from_sat(dust_limit),
-            },
-            TxOut {
-                script_pubkey: response_second_address.script_pubkey(),
-                value: Amount::from_sat(amt - (2 * i + 2) * (fee + dust_limit)),
-            },
-        ];
-
-        let inputs = if i == 0 {
-            vec![TxIn {
-                previous_output: OutPoint {
-                    txid: initial_fund_txid,
-                    vout: initial_fund_tx.details[0].vout,
+            let outputs2 = vec![
+                TxOut {
+                    script_pubkey: response_address.script_pubkey(),
+                    value: DUST_LIMIT,
                 },
-                script_sig: ScriptBuf::new(),
-                sequence: bitcoin::transaction::Sequence::ENABLE_RBF_NO_LOCKTIME,
-                witness: Witness::new(),
-            }]
-        } else {
-            vec![
-                TxIn {
-                    previous_output: OutPoint {
-                        txid: last_txid,
-                        vout: 0,
-                    },
-                    script_sig: ScriptBuf::new(),
-                    sequence: bitcoin::transaction::Sequence::ENABLE_RBF_NO_LOCKTIME,
-                    witness: Witness::new(),
+                TxOut {
+                    script_pubkey: response_second_address.script_pubkey(),
+                    value: fee_schedule.response_second_value(funding, i),
                 },
-                TxIn {
-                    previous_output: OutPoint {
-                        txid: last_txid,
-                        vout: 1,
-                    },
+            ];
+
+            let inputs = if i == 0 {
+                vec![TxIn {
+                    previous_output: funding_outpoint,
                     script_sig: ScriptBuf::new(),
                     sequence: bitcoin::transaction::Sequence::ENABLE_RBF_NO_LOCKTIME,
                     witness: Witness::new(),
-                },
-            ]
-        };
-
-        let mut challenge_tx = Transaction {
-            version: bitcoin::transaction::Version::TWO,
-            lock_time: LockTime::from(Height::MIN),
-            input: inputs,
-            output: outputs1.clone(),
-        };
+                }]
+            } else {
+                vec![
+                    TxIn {
+                        previous_output: OutPoint {
+                            txid: last_txid,
+                            vout: 0,
+                        },
+                        script_sig: ScriptBuf::new(),
+                        sequence: bitcoin::transaction::Sequence::ENABLE_RBF_NO_LOCKTIME,
+                        witness: Witness::new(),
+                    },
+                    TxIn {
+                        previous_output: OutPoint {
+                            txid: last_txid,
+                            vout: 1,
+                        },
+                        script_sig: ScriptBuf::new(),
+                        sequence: bitcoin::transaction::Sequence::ENABLE_RBF_NO_LOCKTIME,
+                        witness: Witness::new(),
+                    },
+                ]
+            };
+
+            let mut challenge_tx = Transaction {
+                version: bitcoin::transaction::Version::TWO,
+                lock_time: LockTime::from(Height::MIN),
+                input: inputs,
+                output: with_anchor(&secp, &fee_schedule, &outputs1, prover.funding_public_key),
+            };
+
+            if i != 0 {
+                // Verifier needs needs to give signature to prover so that prover can give a response
+                let mut sighash_cache = SighashCache::new(challenge_tx.borrow_mut());
+
+                let sig_hash = sighash_cache
+                    .taproot_script_spend_signature_hash(
+                        1_usize,
+                        &bitcoin::sighash::Prevouts::All(&last_output),
+                        TapLeafHash::from_script(
+                            &generate_2_of_2_script(prover_public_key, verifier_public_key),
+                            LeafVersion::TapScript,
+                        ),
+                        bitcoin::sighash::TapSighashType::Default,
+                    )
+                    .unwrap();
+                let response_sig: Signature = receive_message(&mut ws_stream).await.unwrap();
+                secp.verify_schnorr(
+                    &response_sig,
+                    &Message::from_digest_slice(sig_hash.as_byte_array()).expect("should be hash"),
+                    &verifier_public_key,
+                )
+                .unwrap();
+                prover.add_signature(response_sig);
+            } else {
+                kickoff_tx = challenge_tx.clone();
+            }
 
-        if i != 0 {
-            // Verifier needs needs to give signature to prover so that prover can give a response
-            let mut sighash_cache = SighashCache::new(challenge_tx.borrow_mut());
+            let mut response_tx = Transaction {
+                version: bitcoin::transaction::Version::TWO,
+                lock_time: LockTime::from(Height::MIN),
+                input: vec![
+                    TxIn {
+                        previous_output: OutPoint {
+                            txid: challenge_tx.txid(),
+                            vout: 0,
+                        },
+                        script_sig: ScriptBuf::new(),
+                        sequence: bitcoin::transaction::Sequence::ENABLE_RBF_NO_LOCKTIME,
+                        witness: Witness::new(),
+                    },
+                    TxIn {
+                        previous_output: OutPoint {
+                            txid: challenge_tx.txid(),
+                            vout: 1,
+                        },
+                        script_sig: ScriptBuf::new(),
+                        sequence: bitcoin::transaction::Sequence::ENABLE_RBF_NO_LOCKTIME,
+                        witness: Witness::new(),
+                    },
+                ],
+                output: with_anchor(&secp, &fee_schedule, &outputs2, verifier_keys.funding),
+            };
+            // Prover needs to give signature to verifier so that verifier can start a challenge
+            let mut sighash_cache = SighashCache::new(response_tx.borrow_mut());
 
             let sig_hash = sighash_cache
                 .taproot_script_spend_signature_hash(
                     1_usize,
-                    &bitcoin::sighash::Prevouts::All(&last_output),
+                    &bitcoin::sighash::Prevouts::All(&outputs1),
                     TapLeafHash::from_script(
                         &generate_2_of_2_script(prover_public_key, verifier_public_key),
                         LeafVersion::TapScript,
@@ -207,71 +324,36 @@ async fn main() {
                     bitcoin::sighash::TapSighashType::Default,
                 )
                 .unwrap();
-            let response_sig: Signature = receive_message(&mut ws_stream).await.unwrap();
-            secp.verify_schnorr(
-                &response_sig,
-                &Message::from_digest_slice(sig_hash.as_byte_array()).expect("should be hash"),
-                &verifier_public_key,
-            )
-            .unwrap();
-            prover.add_signature(response_sig);
-        } else {
-            kickoff_tx = challenge_tx.clone();
-        }
-
-        let mut response_tx = Transaction {
-            version: bitcoin::transaction::Version::TWO,
-            lock_time: LockTime::from(Height::MIN),
-            input: vec![
-                TxIn {
-                    previous_output: OutPoint {
-                        txid: challenge_tx.txid(),
-                        vout: 0,
-                    },
-                    script_sig: ScriptBuf::new(),
-                    sequence: bitcoin::transaction::Sequence::ENABLE_RBF_NO_LOCKTIME,
-                    witness: Witness::new(),
-                },
-                TxIn {
-                    previous_output: OutPoint {
-                        txid: challenge_tx.txid(),
-                        vout: 1,
-                    },
-                    script_sig: ScriptBuf::new(),
-                    sequence: bitcoin::transaction::Sequence::ENABLE_RBF_NO_LOCKTIME,
-                    witness: Witness::new(),
-                },
-            ],
-            output: outputs2.clone(),
-        };
-        // Prover needs to give signature to verifier so that verifier can start a challenge
-        let mut sighash_cache = SighashCache::new(response_tx.borrow_mut());
-
-        let sig_hash = sighash_cache
-            .taproot_script_spend_signature_hash(
-                1_usize,
-                &bitcoin::sighash::Prevouts::All(&outputs1),
-                TapLeafHash::from_script(
-                    &generate_2_of_2_script(prover_public_key, verifier_public_key),
-                    LeafVersion::TapScript,
+            let challenge_sig = prover.sign(sig_hash).unwrap();
+            let musig_2of2_script = generate_2_of_2_script(prover_public_key, verifier_public_key);
+            export_psbt(
+                &format!("prover-challenge-{}-presigned", i),
+                &create_psbt(
+                    &response_tx,
+                    vec![
+                        InputSpend::new(outputs1[0].clone(), &challenge_taproot_info),
+                        InputSpend::new(outputs1[1].clone(), &equivocation_taproot_info)
+                            .with_leaf(musig_2of2_script.clone())
+                            .with_signature(prover_public_key, musig_2of2_script, challenge_sig),
+                    ],
                 ),
-                bitcoin::sighash::TapSighashType::Default,
-            )
-            .unwrap();
-        let challenge_sig = prover.sign(sig_hash);
-        println!("Challenge Sig: {:?}", challenge_sig);
+            );
+            debug!(signature = %challenge_sig, "presigned the challenge");
 
-        send_message(&mut ws_stream, &challenge_sig).await.unwrap();
+            send_message(&mut ws_stream, &challenge_sig).await.unwrap();
 
-        last_output = outputs2;
-        last_txid = response_tx.txid();
+            last_output = outputs2;
+            last_txid = response_tx.txid();
+        }
+        .instrument(info_span!("round", round = i, phase = "presign"))
+        .await;
     }
-    println!("Bisection completed");
+    info!("presigning completed");
     // now we send the funding
 
     let prevouts = vec![TxOut {
         script_pubkey: prover.address.script_pubkey(),
-        value: Amount::from_sat(amt),
+        value: funding,
     }];
 
     // if kickoff_tx uninitialized, then panic
@@ -287,16 +369,26 @@ async fn main() {
         .unwrap();
 
     // Witness::from_slice(sigHash)
-    let sig = prover.sign_with_tweak(sig_hash, None);
+    let sig = prover.sign_with_tweak(sig_hash, None).unwrap();
     let witness = sighash_cache.witness_mut(0).unwrap();
     witness.push(sig.as_ref());
 
     // println!("txid : {:?}", serialize_hex(&tx));
 
-    let kickoff_txid = rpc
-        .send_raw_transaction(&kickoff_tx)
-        .unwrap_or_else(|e| panic!("Failed to send raw transaction: {}", e));
-    println!("Initial kickoff txid: {:?}", kickoff_txid);
+    export_psbt(
+        "prover-kickoff",
+        &create_psbt(
+            &kickoff_tx,
+            vec![InputSpend::new(
+                prevouts[0].clone(),
+                &TaprootSpendInfo::new_key_spend(&secp, prover.funding_public_key, None),
+            )],
+        ),
+    );
+
+    let kickoff_txid = broadcast_transaction(&rpc, &kickoff_tx, "kickoff").await;
+    report.add_transaction("kickoff", None, kickoff_txid);
+    fee_bumper.watch(&kickoff_tx, fee_schedule.kickoff, None);
     send_message(&mut ws_stream, &kickoff_txid).await.unwrap();
 
     let a1 = 633;
@@ -309,221 +401,334 @@ async fn main() {
     let mut challenge_preimage: PreimageValue = [0; 32];
     let mut challenge_hash: HashValue = [0; 32];
     let mut challenge_gate_index: usize = 0;
-    last_txid = initial_fund_txid;
+    last_txid = funding_outpoint.txid;
     for i in 0..bisection_length as u64 {
-        let challenge_hashes: Vec<HashValue> = prover.get_challenge_hashes(i as usize);
-
-        let (challenge_address, _challenge_taproot_info) = generate_challenge_address_and_info(
-            &secp,
-            &circuit,
-            prover_public_key,
-            verifier_public_key,
-            &challenge_hashes,
-        );
-
-        let (response_address, _response_taproot_info) = generate_response_address_and_info(
-            &secp,
-            &circuit,
-            prover_public_key,
-            &challenge_hashes,
-        );
+        let closed = async {
+            let challenge_hashes: Vec<HashValue> = prover.get_challenge_hashes(i as usize);
+
+            let (challenge_address, _challenge_taproot_info) = taproot_cache
+                .challenge_address_and_info(
+                    &secp,
+                    i as usize,
+                    &circuit,
+                    prover_public_key,
+                    verifier_public_key,
+                    &challenge_hashes,
+                );
+
+            let (response_address, _response_taproot_info) = taproot_cache
+                .response_address_and_info(
+                    &secp,
+                    i as usize,
+                    &circuit,
+                    prover_response_key,
+                    &challenge_hashes,
+                );
+
+            let outputs1 = vec![
+                TxOut {
+                    script_pubkey: challenge_address.script_pubkey(),
+                    value: DUST_LIMIT,
+                },
+                TxOut {
+                    script_pubkey: equivocation_address.script_pubkey(),
+                    value: fee_schedule.equivocation_value(funding, i),
+                },
+            ];
 
-        let outputs1 = vec![
-            TxOut {
-                script_pubkey: challenge_address.script_pubkey(),
-                value: Amount::from_sat(dust_limit),
-            },
-            TxOut {
-                script_pubkey: equivocation_address.script_pubkey(),
-                value: Amount::from_sat(amt - (2 * i + 1) * (fee + dust_limit)),
-            },
-        ];
-
-        let outputs2 = vec![
-            TxOut {
-                script_pubkey: response_address.script_pubkey(),
-                value: Amount::from_sat(dust_limit),
-            },
-            TxOut {
-                script_pubkey: response_second_address.script_pubkey(),
-                value: Amount::from_sat(amt - (2 * i + 2) * (fee + dust_limit)),
-            },
-        ];
-
-        let inputs = if i == 0 {
-            vec![TxIn {
-                previous_output: OutPoint {
-                    txid: initial_fund_txid,
-                    vout: initial_fund_tx.details[0].vout,
+            let outputs2 = vec![
+                TxOut {
+                    script_pubkey: response_address.script_pubkey(),
+                    value: DUST_LIMIT,
                 },
-                script_sig: ScriptBuf::new(),
-                sequence: bitcoin::transaction::Sequence::ENABLE_RBF_NO_LOCKTIME,
-                witness: Witness::new(),
-            }]
-        } else {
-            vec![
-                TxIn {
-                    previous_output: OutPoint {
-                        txid: last_txid,
-                        vout: 0,
-                    },
-                    script_sig: ScriptBuf::new(),
-                    sequence: bitcoin::transaction::Sequence::ENABLE_RBF_NO_LOCKTIME,
-                    witness: Witness::new(),
+                TxOut {
+                    script_pubkey: response_second_address.script_pubkey(),
+                    value: fee_schedule.response_second_value(funding, i),
                 },
-                TxIn {
-                    previous_output: OutPoint {
-                        txid: last_txid,
-                        vout: 1,
-                    },
+            ];
+
+            let inputs = if i == 0 {
+                vec![TxIn {
+                    previous_output: funding_outpoint,
                     script_sig: ScriptBuf::new(),
                     sequence: bitcoin::transaction::Sequence::ENABLE_RBF_NO_LOCKTIME,
                     witness: Witness::new(),
-                },
-            ]
-        };
-
-        let mut challenge_tx = Transaction {
-            version: bitcoin::transaction::Version::TWO,
-            lock_time: LockTime::from(Height::MIN),
-            input: inputs,
-            output: outputs1.clone(),
-        };
-
-        if i != 0 {
-            let challenge_hashes: Vec<HashValue> = prover.get_challenge_hashes(i as usize - 1);
-            let (_, response_taproot_info) = generate_response_address_and_info(
-                &secp,
-                &circuit,
-                prover_public_key,
-                &challenge_hashes,
-            );
-
-            // NOW WE GIVE THE RESPONSE
-            let mut sighash_cache = SighashCache::new(challenge_tx.borrow_mut());
+                }]
+            } else {
+                vec![
+                    TxIn {
+                        previous_output: OutPoint {
+                            txid: last_txid,
+                            vout: 0,
+                        },
+                        script_sig: ScriptBuf::new(),
+                        sequence: bitcoin::transaction::Sequence::ENABLE_RBF_NO_LOCKTIME,
+                        witness: Witness::new(),
+                    },
+                    TxIn {
+                        previous_output: OutPoint {
+                            txid: last_txid,
+                            vout: 1,
+                        },
+                        script_sig: ScriptBuf::new(),
+                        sequence: bitcoin::transaction::Sequence::ENABLE_RBF_NO_LOCKTIME,
+                        witness: Witness::new(),
+                    },
+                ]
+            };
+
+            let mut challenge_tx = Transaction {
+                version: bitcoin::transaction::Version::TWO,
+                lock_time: LockTime::from(Height::MIN),
+                input: inputs,
+                output: with_anchor(&secp, &fee_schedule, &outputs1, prover.funding_public_key),
+            };
+
+            if i != 0 {
+                let challenge_hashes: Vec<HashValue> = prover.get_challenge_hashes(i as usize - 1);
+                let (_, response_taproot_info) = taproot_cache.response_address_and_info(
+                    &secp,
+                    i as usize - 1,
+                    &circuit,
+                    prover_response_key,
+                    &challenge_hashes,
+                );
+
+                // NOW WE GIVE THE RESPONSE
+                let mut sighash_cache = SighashCache::new(challenge_tx.borrow_mut());
+
+                let response_script = generate_gate_response_script(
+                    &circuit.gates[challenge_gate_index],
+                    &challenge_hash,
+                    prover_response_key,
+                );
+                let musig_2of2_script =
+                    generate_2_of_2_script(prover_public_key, verifier_public_key);
+
+                let sig_hash = sighash_cache
+                    .taproot_script_spend_signature_hash(
+                        0,
+                        &bitcoin::sighash::Prevouts::All(&last_output),
+                        TapLeafHash::from_script(&response_script, LeafVersion::TapScript),
+                        bitcoin::sighash::TapSighashType::Default,
+                    )
+                    .unwrap();
+                let prover_response_sig = prover.sign_response(sig_hash).unwrap();
+
+                let sig_hash = sighash_cache
+                    .taproot_script_spend_signature_hash(
+                        1,
+                        &bitcoin::sighash::Prevouts::All(&last_output),
+                        TapLeafHash::from_script(&musig_2of2_script, LeafVersion::TapScript),
+                        bitcoin::sighash::TapSighashType::Default,
+                    )
+                    .unwrap();
+                let provers_musig_signature = prover.sign(sig_hash).unwrap();
+
+                let verifiers_musig_signature = prover.get_signature(i as usize - 1);
+                let response_control_block = response_taproot_info
+                    .control_block(&(response_script.clone(), LeafVersion::TapScript))
+                    .expect("Cannot create control block");
+
+                let musig_control_block = response_second_taproot_info
+                    .control_block(&(musig_2of2_script.clone(), LeafVersion::TapScript))
+                    .expect("Cannot create control block");
+
+                *sighash_cache.witness_mut(0).unwrap() = generate_gate_response_witness(
+                    &prover_response_sig,
+                    &mut circuit.gates[challenge_gate_index],
+                    challenge_preimage,
+                    &response_script,
+                    &response_control_block,
+                );
+                *sighash_cache.witness_mut(1).unwrap() = generate_2_of_2_witness(
+                    &provers_musig_signature,
+                    &verifiers_musig_signature,
+                    &musig_2of2_script,
+                    &musig_control_block,
+                );
+
+                export_psbt(
+                    &format!("prover-response-{}", i),
+                    &create_psbt(
+                        &challenge_tx,
+                        vec![
+                            InputSpend::new(last_output[0].clone(), &response_taproot_info)
+                                .with_leaf(response_script.clone())
+                                .with_signature(
+                                    prover_response_key,
+                                    response_script.clone(),
+                                    prover_response_sig,
+                                ),
+                            InputSpend::new(last_output[1].clone(), &response_second_taproot_info)
+                                .with_leaf(musig_2of2_script.clone())
+                                .with_signature(
+                                    prover_public_key,
+                                    musig_2of2_script.clone(),
+                                    provers_musig_signature,
+                                )
+                                .with_signature(
+                                    verifier_public_key,
+                                    musig_2of2_script.clone(),
+                                    verifiers_musig_signature,
+                                ),
+                        ],
+                    ),
+                );
+
+                let response_txid = broadcast_transaction(&rpc, &challenge_tx, "response").await;
+                report.add_response(
+                    response_txid,
+                    response_reveals(
+                        circuit.gates[challenge_gate_index].as_ref(),
+                        &challenge_tx.input[0].witness,
+                    ),
+                );
 
-            let response_script = generate_gate_response_script(
-                &circuit.gates[challenge_gate_index],
-                &challenge_hash,
-                prover_public_key,
-            );
-            let musig_2of2_script = generate_2_of_2_script(prover_public_key, verifier_public_key);
+                fee_bumper.watch(&challenge_tx, fee_schedule.response, Some(i - 1));
 
-            let sig_hash = sighash_cache
-                .taproot_script_spend_signature_hash(
-                    0,
-                    &bitcoin::sighash::Prevouts::All(&last_output),
-                    TapLeafHash::from_script(&response_script, LeafVersion::TapScript),
-                    bitcoin::sighash::TapSighashType::Default,
-                )
-                .unwrap();
-            let prover_response_sig = prover.sign(sig_hash);
+                let a1 = 32;
+                let a2 = 70;
+                let b1 = number_to_bool_array(a1, 64);
+                let b2 = number_to_bool_array(a2, 64);
 
-            let sig_hash = sighash_cache
-                .taproot_script_spend_signature_hash(
-                    1,
-                    &bitcoin::sighash::Prevouts::All(&last_output),
-                    TapLeafHash::from_script(&musig_2of2_script, LeafVersion::TapScript),
-                    bitcoin::sighash::TapSighashType::Default,
-                )
-                .unwrap();
-            let provers_musig_signature = prover.sign(sig_hash);
-
-            let verifiers_musig_signature = prover.get_signature(i as usize - 1);
-            let response_control_block = response_taproot_info
-                .control_block(&(response_script.clone(), LeafVersion::TapScript))
-                .expect("Cannot create control block");
-
-            let musig_control_block = response_second_taproot_info
-                .control_block(&(musig_2of2_script.clone(), LeafVersion::TapScript))
-                .expect("Cannot create control block");
-
-            let witness0 = sighash_cache.witness_mut(0).unwrap();
-            witness0.push(prover_response_sig.as_ref());
-            circuit.gates[challenge_gate_index]
-                .create_response_witness(challenge_preimage)
-                .iter()
-                .for_each(|x| witness0.push(x));
-            // circuit.gates[challenge_gate_index].create_response_witness
-            witness0.push(response_script);
-            witness0.push(&response_control_block.serialize());
-
-            let witness1 = sighash_cache.witness_mut(1).unwrap();
-            witness1.push(verifiers_musig_signature.as_ref());
-            witness1.push(provers_musig_signature.as_ref());
-            witness1.push(musig_2of2_script);
-            witness1.push(&musig_control_block.serialize());
-
-            let challenge_txid = rpc
-                .send_raw_transaction(&challenge_tx)
-                .unwrap_or_else(|e| panic!("Failed to send raw transaction: {}", e));
-
-            println!("Responsing to the challenge, txid: {:?}", challenge_txid);
-
-            // let _sig = verifier.sign(sig_hash);
-            println!("Response given");
-
-            let a1 = 32;
-            let a2 = 70;
-            let b1 = number_to_bool_array(a1, 64);
-            let b2 = number_to_bool_array(a2, 64);
-
-            let _o = circuit.evaluate(vec![b1, b2]);
-            // return;
-            // send_message(&mut ws_stream, &sig).await.unwrap();
-        }
+                let _o = circuit.evaluate(vec![b1, b2]);
+                // return;
+                // send_message(&mut ws_stream, &sig).await.unwrap();
+            }
 
-        let response_tx = Transaction {
-            version: bitcoin::transaction::Version::TWO,
-            lock_time: LockTime::from(Height::MIN),
-            input: vec![
-                TxIn {
-                    previous_output: OutPoint {
-                        txid: challenge_tx.txid(),
-                        vout: 0,
+            let response_tx = Transaction {
+                version: bitcoin::transaction::Version::TWO,
+                lock_time: LockTime::from(Height::MIN),
+                input: vec![
+                    TxIn {
+                        previous_output: OutPoint {
+                            txid: challenge_tx.txid(),
+                            vout: 0,
+                        },
+                        script_sig: ScriptBuf::new(),
+                        sequence: bitcoin::transaction::Sequence::ENABLE_RBF_NO_LOCKTIME,
+                        witness: Witness::new(),
                     },
-                    script_sig: ScriptBuf::new(),
-                    sequence: bitcoin::transaction::Sequence::ENABLE_RBF_NO_LOCKTIME,
-                    witness: Witness::new(),
-                },
-                TxIn {
-                    previous_output: OutPoint {
+                    TxIn {
+                        previous_output: OutPoint {
+                            txid: challenge_tx.txid(),
+                            vout: 1,
+                        },
+                        script_sig: ScriptBuf::new(),
+                        sequence: bitcoin::transaction::Sequence::ENABLE_RBF_NO_LOCKTIME,
+                        witness: Witness::new(),
+                    },
+                ],
+                output: with_anchor(&secp, &fee_schedule, &outputs2, verifier_keys.funding),
+            };
+
+            let decision: RoundDecision = receive_message(&mut ws_stream).await.unwrap();
+            if decision == RoundDecision::Finish {
+                info!("verifier stopped challenging");
+                for (round, txid) in fee_bumper.finish().await {
+                    report.add_transaction("cpfp", round, txid);
+                }
+                report.write(Outcome::Finished);
+                return true;
+            }
+            if decision == RoundDecision::Close {
+                // The verifier gives up on challenging, we co-sign a key path spend of the equivocation output
+                let mut close_tx = create_cooperative_close_tx(
+                    OutPoint {
                         txid: challenge_tx.txid(),
                         vout: 1,
                     },
-                    script_sig: ScriptBuf::new(),
-                    sequence: bitcoin::transaction::Sequence::ENABLE_RBF_NO_LOCKTIME,
-                    witness: Witness::new(),
-                },
-            ],
-            output: outputs2.clone(),
-        };
-
-        // println!("response txid: {:?}", response_tx.txid());
-        // Prover waits for challenge
-        println!("Waiting for a challenge...");
-        let challenge_tx = watch_transaction(&rpc, &response_tx.txid(), watch_interval).unwrap();
-        let preimage: &[u8; 32] = challenge_tx.input[0]
-            .witness
-            .nth(1)
-            .unwrap()
-            .try_into()
-            .expect("Slice with incorrect length");
-        challenge_preimage = preimage.to_owned();
-        challenge_hash = sha256::Hash::hash(&challenge_preimage).to_byte_array();
-        // println!("Challenged preimage: {:?}", challenge_preimage);
-        // println!("Challenged hash: {:?}", challenge_hash);
-        // find the challenge hash in the challenge hashes
-        let mut challenge_index = 0;
-        for (i, hash) in challenge_hashes.iter().enumerate() {
-            if hash == &challenge_hash {
-                challenge_index = i;
-                break;
+                    vec![TxOut {
+                        script_pubkey: prover.address.script_pubkey(),
+                        value: outputs1[1].value - fee_schedule.close,
+                    }],
+                );
+                let sig_hash = cooperative_close_sighash(&close_tx, &outputs1[1]).unwrap();
+                let key_agg_ctx = KeyAggContext::new(
+                    &secp,
+                    &[prover_public_key, verifier_public_key],
+                    equivocation_taproot_info.merkle_root(),
+                );
+                let (secret_nonce, public_nonce) = generate_nonce(&secp);
+                let verifier_nonce: PublicNonce = receive_message(&mut ws_stream).await.unwrap();
+                send_message(&mut ws_stream, &public_nonce).await.unwrap();
+                let session = key_agg_ctx.session(
+                    &secp,
+                    &[public_nonce, verifier_nonce],
+                    sig_hash.to_byte_array(),
+                );
+                let partial_sig = prover.musig_partial_sign(&session, secret_nonce);
+                let verifier_partial_sig: PartialSignature =
+                    receive_message(&mut ws_stream).await.unwrap();
+                let close_sig = session.aggregate(&[partial_sig, verifier_partial_sig]);
+                secp.verify_schnorr(
+                    &close_sig,
+                    &Message::from_digest_slice(sig_hash.as_byte_array()).expect("should be hash"),
+                    &key_agg_ctx.output_key(),
+                )
+                .unwrap();
+                close_tx.input[0].witness.push(close_sig.as_ref());
+                export_psbt(
+                    "prover-close",
+                    &create_psbt(
+                        &close_tx,
+                        vec![InputSpend::new(
+                            outputs1[1].clone(),
+                            &equivocation_taproot_info,
+                        )],
+                    ),
+                );
+                let close_txid = broadcast_transaction(&rpc, &close_tx, "close").await;
+                info!("game closed cooperatively");
+                report.add_transaction("close", Some(i), close_txid);
+                for (round, txid) in fee_bumper.finish().await {
+                    report.add_transaction("cpfp", round, txid);
+                }
+                report.write(Outcome::Closed { txid: close_txid });
+                send_message(&mut ws_stream, &close_txid).await.unwrap();
+                return true;
             }
-        }
-        challenge_gate_index = challenge_index;
 
-        last_output = outputs2;
-        last_txid = response_tx.txid();
+            // println!("response txid: {:?}", response_tx.txid());
+            // Prover waits for challenge
+            info!("waiting for a challenge");
+            let challenge_tx = watch_transaction(&rpc, &response_tx.txid(), watch_interval)
+                .await
+                .unwrap();
+            let preimage: &[u8; 32] = challenge_tx.input[0]
+                .witness
+                .nth(1)
+                .unwrap()
+                .try_into()
+                .expect("Slice with incorrect length");
+            challenge_preimage = preimage.to_owned();
+            challenge_hash = sha256::Hash::hash(&challenge_preimage).to_byte_array();
+            // println!("Challenged preimage: {:?}", challenge_preimage);
+            // println!("Challenged hash: {:?}", challenge_hash);
+            // find the challenge hash in the challenge hashes
+            let mut challenge_index = 0;
+            for (i, hash) in challenge_hashes.iter().enumerate() {
+                if hash == &challenge_hash {
+                    challenge_index = i;
+                    break;
+                }
+            }
+            challenge_gate_index = challenge_index;
+            report.add_challenge(i, challenge_index, challenge_hash, challenge_tx.txid());
+
+            last_output = outputs2;
+            last_txid = response_tx.txid();
+            false
+        }
+        .instrument(info_span!("round", round = i, phase = "dispute"))
+        .await;
+        if closed {
+            return;
+        }
+    }
+    for (round, txid) in fee_bumper.finish().await {
+        report.add_transaction("cpfp", round, txid);
     }
+    report.write(Outcome::Finished);
 }
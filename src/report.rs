@@ -0,0 +1,575 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::secp256k1::{Secp256k1, Verification};
+use bitcoin::{Address, OutPoint, ScriptBuf, Transaction, Txid, Witness};
+use bitcoincore_rpc::{Client, RpcApi};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::actor::ActorPublicKeys;
+use crate::circuit::chunk::ChunkLimits;
+use crate::circuit::wire::{HashCommitment, HashTuple, HashValue, PreimageValue};
+use crate::circuit::Circuit;
+use crate::traits::bit_commitment::BitCommitment;
+use crate::traits::gate::GateTrait;
+use crate::transactions::generate_wrong_output_script;
+
+// Directory the binaries write their report to at the end of the game, the current one if unset
+pub const REPORT_DIR_ENV_VAR: &str = "TOY_BITVM_REPORT_DIR";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Prover,
+    Verifier,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CircuitReport {
+    pub path: String,
+    // Hash of the wire commitments in order, each as its zero hash followed by its one hash
+    pub commitment: sha256::Hash,
+    pub wires: Vec<HashTuple>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TransactionRecord {
+    // What the transaction does in the game, e.g. "kickoff" or "slash"
+    pub kind: String,
+    pub round: Option<u64>,
+    pub txid: Txid,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WireReveal {
+    pub wire: usize,
+    pub bit: bool,
+    #[serde(with = "hex::serde")]
+    pub preimage: PreimageValue,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ChallengeRecord {
+    pub round: u64,
+    pub chunk: usize,
+    #[serde(with = "hex::serde")]
+    pub challenge_hash: HashValue,
+    pub challenge_txid: Txid,
+    // Set once the prover answered, with the wire bits its response revealed
+    pub response_txid: Option<Txid>,
+    pub reveals: Vec<WireReveal>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FraudProof {
+    Equivocation {
+        wire: usize,
+        #[serde(with = "hex::serde")]
+        zero: PreimageValue,
+        #[serde(with = "hex::serde")]
+        one: PreimageValue,
+    },
+    WrongOutput {
+        chunk: usize,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Outcome {
+    // Every round was played without the prover being caught
+    Finished,
+    Closed { txid: Txid },
+    Slashed { txid: Txid },
+}
+
+// Everything an actor saw of a game, written at its end so the game can be audited later
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct GameReport {
+    pub role: Role,
+    pub circuit: CircuitReport,
+    pub prover_keys: ActorPublicKeys,
+    pub verifier_keys: ActorPublicKeys,
+    pub funding: OutPoint,
+    pub transactions: Vec<TransactionRecord>,
+    pub challenges: Vec<ChallengeRecord>,
+    pub fraud: Option<FraudProof>,
+    pub outcome: Option<Outcome>,
+}
+
+pub fn circuit_commitment(wires: &[HashTuple]) -> sha256::Hash {
+    let mut engine = sha256::Hash::engine();
+    for wire in wires {
+        engine.input(&wire.zero);
+        engine.input(&wire.one);
+    }
+    sha256::Hash::from_engine(engine)
+}
+
+// The wire bits revealed by a response, read from the witness of its first input
+pub fn response_reveals(gate: &dyn GateTrait, witness: &Witness) -> Vec<WireReveal> {
+    let (input_wires, output_wires) = gate.get_wires();
+    input_wires
+        .iter()
+        .chain(output_wires.iter())
+        .zip(witness.iter().skip(1))
+        .filter_map(|(wire_arcm, element)| {
+            let wire = wire_arcm.lock().unwrap();
+            let preimage: PreimageValue = element.try_into().ok()?;
+            Some(WireReveal {
+                wire: wire.index?,
+                bit: HashCommitment::parse_reveal(&wire.get_commitment(), &preimage)?,
+                preimage,
+            })
+        })
+        .collect()
+}
+
+impl GameReport {
+    pub fn new(
+        role: Role,
+        circuit_path: &str,
+        wires: Vec<HashTuple>,
+        prover_keys: ActorPublicKeys,
+        verifier_keys: ActorPublicKeys,
+        funding: OutPoint,
+    ) -> Self {
+        GameReport {
+            role,
+            circuit: CircuitReport {
+                path: circuit_path.to_string(),
+                commitment: circuit_commitment(&wires),
+                wires,
+            },
+            prover_keys,
+            verifier_keys,
+            funding,
+            transactions: vec![TransactionRecord {
+                kind: "funding".to_string(),
+                round: None,
+                txid: funding.txid,
+            }],
+            challenges: Vec::new(),
+            fraud: None,
+            outcome: None,
+        }
+    }
+
+    pub fn add_transaction(&mut self, kind: &str, round: Option<u64>, txid: Txid) {
+        self.transactions.push(TransactionRecord {
+            kind: kind.to_string(),
+            round,
+            txid,
+        });
+    }
+
+    pub fn add_challenge(
+        &mut self,
+        round: u64,
+        chunk: usize,
+        challenge_hash: HashValue,
+        challenge_txid: Txid,
+    ) {
+        self.add_transaction("challenge", Some(round), challenge_txid);
+        self.challenges.push(ChallengeRecord {
+            round,
+            chunk,
+            challenge_hash,
+            challenge_txid,
+            response_txid: None,
+            reveals: Vec::new(),
+        });
+    }
+
+    // Answers the last challenge
+    pub fn add_response(&mut self, response_txid: Txid, reveals: Vec<WireReveal>) {
+        let challenge = self
+            .challenges
+            .last_mut()
+            .expect("a response should answer a challenge");
+        challenge.response_txid = Some(response_txid);
+        challenge.reveals = reveals;
+        let round = challenge.round;
+        self.add_transaction("response", Some(round), response_txid);
+    }
+
+    // Writes the report to the report directory, named after the role and the funding outpoint
+    pub fn write(&mut self, outcome: Outcome) -> PathBuf {
+        self.outcome = Some(outcome);
+        let dir = PathBuf::from(env::var(REPORT_DIR_ENV_VAR).unwrap_or_else(|_| ".".to_string()));
+        fs::create_dir_all(&dir).unwrap();
+        let role = match self.role {
+            Role::Prover => "prover",
+            Role::Verifier => "verifier",
+        };
+        let path = dir.join(format!(
+            "{}-report-{}-{}.json",
+            role, self.funding.txid, self.funding.vout
+        ));
+        fs::write(&path, serde_json::to_string_pretty(self).unwrap()).unwrap();
+        info!(path = %path.display(), "wrote the game report");
+        path
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    // Checks the report against the transactions, returning every inconsistency found. Transactions
+    // are looked up by txid, None meaning the transaction is unknown.
+    pub fn check<C: Verification, F: Fn(&Txid) -> Option<Transaction>>(
+        &self,
+        secp: &Secp256k1<C>,
+        get_transaction: F,
+    ) -> Vec<String> {
+        let mut problems = Vec::new();
+        if circuit_commitment(&self.circuit.wires) != self.circuit.commitment {
+            problems.push("circuit commitment does not match the wire commitments".to_string());
+        }
+        let wire_hashes = |wire: usize| self.circuit.wires.get(wire);
+        let mut txids: Vec<Txid> = self.transactions.iter().map(|record| record.txid).collect();
+        if let Some(Outcome::Closed { txid } | Outcome::Slashed { txid }) = &self.outcome {
+            txids.push(*txid);
+        }
+        let mut chain = HashMap::new();
+        for txid in txids {
+            if chain.contains_key(&txid) {
+                continue;
+            }
+            match get_transaction(&txid) {
+                Some(tx) => {
+                    chain.insert(txid, tx);
+                }
+                None => problems.push(format!("transaction {} is not on chain", txid)),
+            }
+        }
+        let fetch = |txid: &Txid| chain.get(txid);
+
+        let funding_script = Address::p2tr(
+            secp,
+            self.prover_keys.funding,
+            None,
+            bitcoin::Network::Regtest,
+        )
+        .script_pubkey();
+        if let Some(funding_tx) = fetch(&self.funding.txid) {
+            if funding_tx
+                .output
+                .get(self.funding.vout as usize)
+                .map(|output| &output.script_pubkey)
+                != Some(&funding_script)
+            {
+                problems.push("funding output does not pay to the prover".to_string());
+            }
+        }
+        let kickoffs: Vec<&TransactionRecord> = self
+            .transactions
+            .iter()
+            .filter(|record| record.kind == "kickoff")
+            .collect();
+        for kickoff in &kickoffs {
+            if let Some(tx) = fetch(&kickoff.txid) {
+                if tx.input[0].previous_output != self.funding {
+                    problems.push(format!(
+                        "kickoff {} does not spend the funding",
+                        kickoff.txid
+                    ));
+                }
+            }
+        }
+
+        for challenge in &self.challenges {
+            let Some(challenge_tx) = fetch(&challenge.challenge_txid) else {
+                continue;
+            };
+            let revealed = challenge_tx.input[0]
+                .witness
+                .nth(1)
+                .map(|preimage| sha256::Hash::hash(preimage).to_byte_array());
+            if revealed != Some(challenge.challenge_hash) {
+                problems.push(format!(
+                    "challenge {} does not reveal the challenge hash of round {}",
+                    challenge.challenge_txid, challenge.round
+                ));
+            }
+            let Some(response_txid) = challenge.response_txid else {
+                continue;
+            };
+            let Some(response_tx) = fetch(&response_txid) else {
+                continue;
+            };
+            if response_tx.input[0].previous_output.txid != challenge.challenge_txid {
+                problems.push(format!(
+                    "response {} does not spend challenge {}",
+                    response_txid, challenge.challenge_txid
+                ));
+            }
+            for reveal in &challenge.reveals {
+                let on_chain = response_tx.input[0]
+                    .witness
+                    .iter()
+                    .any(|element| element == reveal.preimage);
+                let committed = wire_hashes(reveal.wire)
+                    .and_then(|hashes| HashCommitment::parse_reveal(hashes, &reveal.preimage));
+                if !on_chain || committed != Some(reveal.bit) {
+                    problems.push(format!(
+                        "reveal of wire {} is not in response {}",
+                        reveal.wire, response_txid
+                    ));
+                }
+            }
+        }
+
+        let spent_outcome = match &self.outcome {
+            Some(Outcome::Closed { txid }) | Some(Outcome::Slashed { txid }) => {
+                fetch(txid).map(|tx| (*txid, tx))
+            }
+            _ => None,
+        };
+        if let Some((txid, tx)) = &spent_outcome {
+            // Closing and slashing both spend the second output of a kickoff or response
+            let spent = tx.input[0].previous_output.txid;
+            if !self.transactions.iter().any(|record| {
+                record.txid == spent && (record.kind == "kickoff" || record.kind == "response")
+            }) {
+                problems.push(format!("{} does not spend a transaction of the game", txid));
+            }
+        }
+        if let Some(FraudProof::Equivocation { wire, zero, one }) = &self.fraud {
+            let committed = wire_hashes(*wire).map(|hashes| {
+                (
+                    HashCommitment::parse_reveal(hashes, zero),
+                    HashCommitment::parse_reveal(hashes, one),
+                )
+            });
+            if committed != Some((Some(false), Some(true))) {
+                problems.push(format!("equivocation proof of wire {} is invalid", wire));
+            }
+            match (&self.outcome, &spent_outcome) {
+                (Some(Outcome::Slashed { .. }), Some((txid, tx))) => {
+                    let witness = &tx.input[0].witness;
+                    if !witness.iter().any(|element| element == zero)
+                        || !witness.iter().any(|element| element == one)
+                    {
+                        problems.push(format!(
+                            "slash {} does not carry the equivocation proof",
+                            txid
+                        ));
+                    }
+                }
+                (Some(Outcome::Slashed { .. }), None) => {}
+                _ => problems.push("equivocation found but the prover was not slashed".to_string()),
+            }
+        }
+        if let Some(FraudProof::WrongOutput { chunk }) = &self.fraud {
+            match (&self.outcome, &spent_outcome) {
+                (Some(Outcome::Slashed { .. }), Some((txid, tx))) => {
+                    match self.wrong_output_script(*chunk) {
+                        Some(script) => {
+                            if tx.input[0].witness.tapscript() != Some(script.as_script()) {
+                                problems.push(format!(
+                                    "slash {} does not spend the wrong output leaf of chunk {}",
+                                    txid, chunk
+                                ));
+                            }
+                        }
+                        None => problems.push(format!(
+                            "circuit {} has no chunk {}",
+                            self.circuit.path, chunk
+                        )),
+                    }
+                }
+                (Some(Outcome::Slashed { .. }), None) => {}
+                _ => problems.push("wrong output found but the prover was not slashed".to_string()),
+            }
+        }
+        if matches!(self.outcome, Some(Outcome::Slashed { .. })) && self.fraud.is_none() {
+            problems.push("prover was slashed without a fraud proof".to_string());
+        }
+        problems
+    }
+
+    // Wrong output leaf of a chunk, rebuilt from the circuit file chunked the way both sides
+    // chunk it, or None if the file is gone or does not match the wires of the report
+    fn wrong_output_script(&self, chunk: usize) -> Option<ScriptBuf> {
+        if !Path::new(&self.circuit.path).is_file() {
+            return None;
+        }
+        let wires = Circuit::<HashCommitment>::from_bristol(&self.circuit.path, None).wires;
+        if wires.len() != self.circuit.wires.len() {
+            return None;
+        }
+        let circuit: Circuit =
+            Circuit::from_bristol(&self.circuit.path, Some(self.circuit.wires.clone()))
+                .into_chunks(&ChunkLimits::default());
+        circuit
+            .gates
+            .get(chunk)
+            .map(|gate| generate_wrong_output_script(gate, self.verifier_keys.presign))
+    }
+
+    // Checks the report against the chain of the node, which needs -txindex for confirmed
+    // transactions the wallet does not know about
+    pub fn verify(&self, rpc: &Client) -> Vec<String> {
+        self.check(&Secp256k1::verification_only(), |txid| {
+            rpc.get_raw_transaction(txid, None).ok()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actor::Actor;
+    use crate::circuit::wire::HashCommitment;
+    use bitcoin::absolute::LockTime;
+    use bitcoin::transaction::Version;
+    use bitcoin::{Amount, ScriptBuf, Sequence, TxIn, TxOut};
+
+    fn spend(
+        previous_output: OutPoint,
+        witness: Vec<Vec<u8>>,
+        script_pubkey: ScriptBuf,
+    ) -> Transaction {
+        Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::from_slice(&witness),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(10_000),
+                script_pubkey,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_report() {
+        let secp = Secp256k1::new();
+        let prover = Actor::new();
+        let verifier = Actor::new();
+        let (secrets, wires): (Vec<_>, Vec<_>) = (0..5).map(|_| HashCommitment::generate()).unzip();
+        let challenge_preimage = [7; 32];
+
+        let funding_tx = spend(OutPoint::null(), vec![], prover.address.script_pubkey());
+        let funding = OutPoint::new(funding_tx.txid(), 0);
+        let kickoff_tx = spend(funding, vec![vec![1; 64]], ScriptBuf::new());
+        let challenge_tx = spend(
+            OutPoint::new(kickoff_tx.txid(), 0),
+            vec![vec![1; 64], challenge_preimage.to_vec()],
+            ScriptBuf::new(),
+        );
+        let revealed = HashCommitment::reveal(&secrets[2], true);
+        let response_tx = spend(
+            OutPoint::new(challenge_tx.txid(), 0),
+            vec![vec![1; 64], revealed.to_vec()],
+            ScriptBuf::new(),
+        );
+        let zero = HashCommitment::reveal(&secrets[3], false);
+        let one = HashCommitment::reveal(&secrets[3], true);
+        let slash_tx = spend(
+            OutPoint::new(response_tx.txid(), 1),
+            vec![vec![1; 64], one.to_vec(), zero.to_vec()],
+            verifier.address.script_pubkey(),
+        );
+        let chain: HashMap<Txid, Transaction> = [
+            funding_tx,
+            kickoff_tx.clone(),
+            challenge_tx.clone(),
+            response_tx.clone(),
+            slash_tx.clone(),
+        ]
+        .into_iter()
+        .map(|tx| (tx.txid(), tx))
+        .collect();
+
+        let mut report = GameReport::new(
+            Role::Verifier,
+            "bristol/test.txt",
+            wires,
+            prover.public_keys(),
+            verifier.public_keys(),
+            funding,
+        );
+        report.add_transaction("kickoff", None, kickoff_tx.txid());
+        report.add_challenge(
+            1,
+            0,
+            sha256::Hash::hash(&challenge_preimage).to_byte_array(),
+            challenge_tx.txid(),
+        );
+        report.add_response(
+            response_tx.txid(),
+            vec![WireReveal {
+                wire: 2,
+                bit: true,
+                preimage: revealed,
+            }],
+        );
+        report.add_transaction("slash", Some(2), slash_tx.txid());
+        report.fraud = Some(FraudProof::Equivocation { wire: 3, zero, one });
+        report.outcome = Some(Outcome::Slashed {
+            txid: slash_tx.txid(),
+        });
+
+        let loaded: GameReport =
+            serde_json::from_str(&serde_json::to_string(&report).unwrap()).unwrap();
+        assert_eq!(loaded, report);
+        let lookup = |txid: &Txid| chain.get(txid).cloned();
+        assert_eq!(report.check(&secp, lookup), Vec::<String>::new());
+
+        // A reveal the response never made is caught
+        let mut forged = report.clone();
+        forged.challenges[0].reveals[0].bit = false;
+        assert_eq!(forged.check(&secp, lookup).len(), 1);
+
+        // So is a transaction missing from the chain
+        let mut missing = report.clone();
+        missing.add_transaction("response", Some(2), Txid::all_zeros());
+        assert_eq!(missing.check(&secp, lookup).len(), 1);
+
+        // And a slash without a fraud proof
+        let mut unproven = report.clone();
+        unproven.fraud = None;
+        assert_eq!(unproven.check(&secp, lookup).len(), 1);
+
+        // A wrong output slash has to spend the leaf of the chunk it names
+        let circuit: Circuit =
+            Circuit::from_bristol("bristol/test.txt", Some(report.circuit.wires.clone()))
+                .into_chunks(&ChunkLimits::default());
+        let script = generate_wrong_output_script(&circuit.gates[0], verifier.public_key);
+        let wrong_output_tx = spend(
+            OutPoint::new(response_tx.txid(), 1),
+            vec![vec![1; 64], script.to_bytes(), vec![0xc0; 33]],
+            ScriptBuf::new(),
+        );
+        let mut chain = chain;
+        chain.insert(wrong_output_tx.txid(), wrong_output_tx.clone());
+        let lookup = |txid: &Txid| chain.get(txid).cloned();
+        let mut wrong_output = report;
+        wrong_output.transactions.pop();
+        wrong_output.add_transaction("slash", Some(2), wrong_output_tx.txid());
+        wrong_output.fraud = Some(FraudProof::WrongOutput { chunk: 0 });
+        wrong_output.outcome = Some(Outcome::Slashed {
+            txid: wrong_output_tx.txid(),
+        });
+        assert_eq!(wrong_output.check(&secp, lookup), Vec::<String>::new());
+        wrong_output.fraud = Some(FraudProof::WrongOutput { chunk: 1 });
+        assert_eq!(wrong_output.check(&secp, lookup).len(), 1);
+        // The equivocation slash does not spend the wrong output leaf
+        wrong_output.fraud = Some(FraudProof::WrongOutput { chunk: 0 });
+        wrong_output.outcome = Some(Outcome::Slashed {
+            txid: slash_tx.txid(),
+        });
+        assert!(!wrong_output.check(&secp, lookup).is_empty());
+    }
+}
@@ -0,0 +1,243 @@
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use bitcoin::OutPoint;
+use rand::Rng;
+use tracing::{info, warn};
+
+use crate::transactions::rpc::rpc_wallet;
+use crate::utils::take_stdin;
+
+// How the verifier picks its challenges: `stdin`, `random`, `close` or a list of chunks like `1,1`
+pub const CHALLENGE_STRATEGY_ENV_VAR: &str = "TOY_BITVM_CHALLENGE_STRATEGY";
+// Directory the circuits asked for by provers must be in
+pub const CIRCUIT_DIR_ENV_VAR: &str = "TOY_BITVM_CIRCUIT_DIR";
+
+// Held while a session asks on stdin, so that prompts and answers of sessions do not interleave
+static STDIN_LOCK: Mutex<()> = Mutex::new(());
+
+pub type SessionId = u64;
+
+// What the verifier does in a round of the dispute
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Challenge {
+    Chunk(usize),
+    Close,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChallengeStrategy {
+    // Asks for every challenge on stdin, one session at a time
+    Stdin,
+    // Challenges a random chunk every round
+    Random,
+    // Challenges the chunks in order, then closes
+    Chunks(VecDeque<usize>),
+}
+
+impl FromStr for ChallengeStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "stdin" => Ok(ChallengeStrategy::Stdin),
+            "random" => Ok(ChallengeStrategy::Random),
+            "close" => Ok(ChallengeStrategy::Chunks(VecDeque::new())),
+            chunks => chunks
+                .split(',')
+                .map(|chunk| chunk.trim().parse::<usize>())
+                .collect::<Result<_, _>>()
+                .map(ChallengeStrategy::Chunks)
+                .map_err(|_| format!("unknown challenge strategy: {}", s)),
+        }
+    }
+}
+
+impl ChallengeStrategy {
+    pub fn from_env() -> Self {
+        env::var(CHALLENGE_STRATEGY_ENV_VAR).map_or(ChallengeStrategy::Stdin, |strategy| {
+            strategy
+                .parse()
+                .expect("challenge strategy should be valid")
+        })
+    }
+
+    // The challenge of the next round, out of the given number of chunks
+    pub async fn next(&mut self, id: SessionId, num_chunks: usize) -> Challenge {
+        match self {
+            ChallengeStrategy::Stdin => {
+                let prompt = format!(
+                    "[session {}] Enter your challenge chunk if you want to challenge the prover, or `close` to settle cooperatively\n",
+                    id
+                );
+                // Reading stdin blocks, so it gets a thread of its own
+                tokio::task::spawn_blocking(move || {
+                    let _stdin = STDIN_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+                    loop {
+                        match take_stdin::<String>(&prompt).unwrap().as_str() {
+                            "close" => return Challenge::Close,
+                            chunk => match chunk.parse() {
+                                Ok(chunk) if chunk < num_chunks => return Challenge::Chunk(chunk),
+                                _ => println!("Not a chunk below {}: {}", num_chunks, chunk),
+                            },
+                        }
+                    }
+                })
+                .await
+                .unwrap()
+            }
+            ChallengeStrategy::Random => {
+                Challenge::Chunk(rand::thread_rng().gen_range(0..num_chunks))
+            }
+            ChallengeStrategy::Chunks(chunks) => {
+                // The prover picks the circuit, so a listed chunk may not exist in it
+                while let Some(chunk) = chunks.pop_front() {
+                    if chunk < num_chunks {
+                        return Challenge::Chunk(chunk);
+                    }
+                    warn!(
+                        session = id,
+                        chunk, num_chunks, "skipped a chunk the circuit does not have"
+                    );
+                }
+                Challenge::Close
+            }
+        }
+    }
+}
+
+// Path of a circuit asked for by a prover, which must be a file in the circuit directory
+pub fn circuit_path(name: &str) -> Result<PathBuf, String> {
+    let dir = env::var(CIRCUIT_DIR_ENV_VAR).unwrap_or_else(|_| "bristol".to_string());
+    let dir = fs::canonicalize(&dir).map_err(|e| format!("circuit directory {}: {}", dir, e))?;
+    let path = fs::canonicalize(name).map_err(|e| format!("circuit {}: {}", name, e))?;
+    if !path.starts_with(&dir) || !path.is_file() {
+        return Err(format!("circuit {} is not in {}", name, dir.display()));
+    }
+    Ok(path)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionInfo {
+    pub peer: String,
+    // Funding outpoint of the game, once the prover has funded it
+    pub game: Option<OutPoint>,
+}
+
+// Keeps track of the sessions of a verifier. Each session owns its connection, keys, circuit and
+// RPC client of its own wallet, so ending one, even with a slash, leaves the others running.
+#[derive(Default)]
+pub struct SessionManager {
+    next_id: AtomicU64,
+    sessions: Mutex<HashMap<SessionId, SessionInfo>>,
+}
+
+impl SessionManager {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    pub fn start(self: &Arc<Self>, peer: String, strategy: ChallengeStrategy) -> Session {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.sessions
+            .lock()
+            .unwrap()
+            .insert(id, SessionInfo { peer, game: None });
+        Session {
+            id,
+            wallet: rpc_wallet().replace("{session}", &id.to_string()),
+            strategy,
+            manager: self.clone(),
+        }
+    }
+
+    // Active sessions, oldest first
+    pub fn sessions(&self) -> Vec<(SessionId, SessionInfo)> {
+        let mut sessions = self
+            .sessions
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, info)| (*id, info.clone()))
+            .collect::<Vec<_>>();
+        sessions.sort_by_key(|(id, _)| *id);
+        sessions
+    }
+}
+
+// A session of the manager, removed from it once dropped
+pub struct Session {
+    pub id: SessionId,
+    // Wallet of the node the session funds its games from
+    pub wallet: String,
+    pub strategy: ChallengeStrategy,
+    manager: Arc<SessionManager>,
+}
+
+impl Session {
+    pub fn set_game(&self, game: OutPoint) {
+        if let Some(info) = self.manager.sessions.lock().unwrap().get_mut(&self.id) {
+            info.game = Some(game);
+        }
+    }
+
+    pub async fn next_challenge(&mut self, num_chunks: usize) -> Challenge {
+        self.strategy.next(self.id, num_chunks).await
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let mut sessions = self.manager.sessions.lock().unwrap();
+        sessions.remove(&self.id);
+        info!(session = self.id, active = sessions.len(), "session ended");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_sessions() {
+        assert_eq!(
+            "1, 1,3".parse::<ChallengeStrategy>().unwrap(),
+            ChallengeStrategy::Chunks(VecDeque::from([1, 1, 3]))
+        );
+        assert!("sometimes".parse::<ChallengeStrategy>().is_err());
+
+        let manager = SessionManager::new();
+        let mut first = manager.start("a".to_string(), "1,1".parse().unwrap());
+        let mut second = manager.start("b".to_string(), "close".parse().unwrap());
+        assert_ne!(first.id, second.id);
+        first.set_game(OutPoint::null());
+
+        // Every session follows its own strategy
+        assert_eq!(first.next_challenge(4).await, Challenge::Chunk(1));
+        assert_eq!(second.next_challenge(4).await, Challenge::Close);
+        assert_eq!(first.next_challenge(4).await, Challenge::Chunk(1));
+        assert_eq!(first.next_challenge(4).await, Challenge::Close);
+
+        // Chunks past the end of the circuit are skipped instead of challenged
+        let mut past_end = manager.start("d".to_string(), "7,2,4".parse().unwrap());
+        assert_eq!(past_end.next_challenge(4).await, Challenge::Chunk(2));
+        assert_eq!(past_end.next_challenge(4).await, Challenge::Close);
+        drop(past_end);
+        let mut random = manager.start("c".to_string(), ChallengeStrategy::Random);
+        assert!(matches!(random.next_challenge(4).await, Challenge::Chunk(chunk) if chunk < 4));
+
+        drop(second);
+        let sessions = manager.sessions();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].1.game, Some(OutPoint::null()));
+        assert_eq!(sessions[1].1.peer, "c");
+
+        assert!(circuit_path("bristol/add.txt").is_ok());
+        assert!(circuit_path("Cargo.toml").is_err());
+    }
+}
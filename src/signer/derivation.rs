@@ -0,0 +1,126 @@
+use std::env;
+
+use bitcoin::bip32::{ChildNumber, DerivationPath, Xpriv};
+use bitcoin::secp256k1::{All, Secp256k1};
+use bitcoin::Network;
+use zeroize::Zeroizing;
+
+use super::InMemorySigner;
+
+// Hex encoded seed, or an extended private key, to derive the keys of the actor from
+pub const SEED_ENV_VAR: &str = "TOY_BITVM_SEED";
+pub const XPRIV_ENV_VAR: &str = "TOY_BITVM_XPRIV";
+// Session number, so that every game played from the same seed gets its own keys
+pub const SESSION_ENV_VAR: &str = "TOY_BITVM_SESSION";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum KeyRole {
+    // Receives the funding and anchors, and signs their key path spends
+    Funding,
+    // Signs the 2-of-2 leaves of the presigned transactions and the cooperative close
+    Presign,
+    // Signs the leaves only this actor can spend, i.e. the responses of the prover
+    Response,
+}
+
+impl KeyRole {
+    pub const ALL: [KeyRole; 3] = [KeyRole::Funding, KeyRole::Presign, KeyRole::Response];
+
+    // Funding keys are on the receive chain, so a BIP 86 wallet restored from the seed finds the
+    // funding outputs. The change chain 1 is left to the wallet.
+    fn chain(self) -> u32 {
+        match self {
+            KeyRole::Funding => 0,
+            KeyRole::Presign => 2,
+            KeyRole::Response => 3,
+        }
+    }
+}
+
+pub fn master_key(seed: &[u8]) -> Xpriv {
+    Xpriv::new_master(Network::Regtest, seed).expect("seed should have a valid length")
+}
+
+// BIP 86 style path, with the session as the account, the role as the chain and the game as the
+// address index: m/86'/1'/<session>'/<role>/<game>
+pub fn derivation_path(session: u32, role: KeyRole, game: u32) -> DerivationPath {
+    DerivationPath::from(vec![
+        ChildNumber::from_hardened_idx(86).unwrap(),
+        ChildNumber::from_hardened_idx(1).unwrap(),
+        ChildNumber::from_hardened_idx(session).expect("session should be below 2^31"),
+        ChildNumber::from_normal_idx(role.chain()).unwrap(),
+        ChildNumber::from_normal_idx(game).expect("game should be below 2^31"),
+    ])
+}
+
+pub fn derive_signer(
+    secp: &Secp256k1<All>,
+    master: &Xpriv,
+    session: u32,
+    role: KeyRole,
+    game: u32,
+) -> InMemorySigner {
+    let mut xpriv = master
+        .derive_priv(secp, &derivation_path(session, role, game))
+        .expect("derivation should not fail");
+    let signer = InMemorySigner::new(xpriv.private_key);
+    xpriv.private_key.non_secure_erase();
+    signer
+}
+
+// The master key and session from the environment, if a seed or extended private key is set
+pub fn master_key_from_env() -> Option<(Xpriv, u32)> {
+    let master = if let Ok(seed) = env::var(SEED_ENV_VAR) {
+        master_key(&Zeroizing::new(
+            hex::decode(seed.trim()).expect("seed should be hex"),
+        ))
+    } else {
+        env::var(XPRIV_ENV_VAR)
+            .ok()?
+            .trim()
+            .parse()
+            .expect("extended private key should be valid")
+    };
+    let session = env::var(SESSION_ENV_VAR).map_or(0, |session| {
+        session.parse().expect("session should be a number")
+    });
+    Some((master, session))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traits::signer::Signer;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_derivation() {
+        let secp = Secp256k1::new();
+        let master = master_key(&[42; 32]);
+        assert_eq!(
+            derivation_path(7, KeyRole::Response, 0).to_string(),
+            "m/86'/1'/7'/3/0"
+        );
+
+        // Same seed, same keys, so a restarted actor recognizes its outputs
+        let keys = |master: &Xpriv, session| {
+            KeyRole::ALL
+                .map(|role| derive_signer(&secp, master, session, role, 0).public_key())
+                .to_vec()
+        };
+        assert_eq!(keys(&master, 0), keys(&master_key(&[42; 32]), 0));
+        let restored: Xpriv = master.to_string().parse().unwrap();
+        assert_eq!(keys(&master, 0), keys(&restored, 0));
+
+        // Every role of every session gets its own key
+        let all: HashSet<_> = [
+            keys(&master, 0),
+            keys(&master, 1),
+            keys(&master_key(&[1; 32]), 0),
+        ]
+        .concat()
+        .into_iter()
+        .collect();
+        assert_eq!(all.len(), 9);
+    }
+}
@@ -0,0 +1,238 @@
+use std::error::Error;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Child, Command, Stdio};
+use std::sync::Mutex;
+
+use bitcoin::hashes::Hash;
+use bitcoin::secp256k1::schnorr::Signature;
+use bitcoin::secp256k1::{Message, Secp256k1, VerifyOnly, XOnlyPublicKey};
+use bitcoin::{TapNodeHash, TapSighash, TapTweakHash};
+use serde::{Deserialize, Serialize};
+
+use crate::traits::signer::{Signer, SignerError};
+
+// One request per line on the signer's stdin, answered by one response per line on its stdout,
+// both as JSON, e.g. {"method":"sign","sighash":"<hex>"} answered by {"signature":"<hex>"}
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum SignerRequest {
+    PublicKey,
+    Sign {
+        sighash: TapSighash,
+    },
+    SignWithTweak {
+        sighash: TapSighash,
+        merkle_root: Option<TapNodeHash>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SignerResponse {
+    PublicKey(XOnlyPublicKey),
+    Signature(Signature),
+    Error(String),
+}
+
+struct Connection {
+    reader: Box<dyn BufRead + Send>,
+    writer: Box<dyn Write + Send>,
+}
+
+// Signer in another process, so that the key never enters the protocol process. Every signature
+// it returns is checked against its public key.
+pub struct ExternalSigner {
+    secp: Secp256k1<VerifyOnly>,
+    connection: Mutex<Connection>,
+    child: Option<Child>,
+    public_key: XOnlyPublicKey,
+}
+
+impl ExternalSigner {
+    pub fn new(
+        reader: Box<dyn BufRead + Send>,
+        writer: Box<dyn Write + Send>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let connection = Mutex::new(Connection { reader, writer });
+        let public_key = match send_request(&connection, &SignerRequest::PublicKey)? {
+            SignerResponse::PublicKey(public_key) => public_key,
+            response => return Err(format!("expected a public key, got {:?}", response).into()),
+        };
+        Ok(ExternalSigner {
+            secp: Secp256k1::verification_only(),
+            connection,
+            child: None,
+            public_key,
+        })
+    }
+
+    // Runs the command with the shell and talks to it over its stdin and stdout
+    pub fn spawn(command: &str) -> Result<Self, Box<dyn Error>> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let writer = Box::new(child.stdin.take().expect("stdin is piped"));
+        let reader = Box::new(BufReader::new(
+            child.stdout.take().expect("stdout is piped"),
+        ));
+        let mut signer = Self::new(reader, writer)?;
+        signer.child = Some(child);
+        Ok(signer)
+    }
+
+    fn request_signature(
+        &self,
+        request: &SignerRequest,
+        sighash: TapSighash,
+        key: XOnlyPublicKey,
+    ) -> Result<Signature, SignerError> {
+        let signature = match send_request(&self.connection, request) {
+            Ok(SignerResponse::Signature(signature)) => signature,
+            Ok(SignerResponse::Error(e)) => return Err(SignerError::Refused(e)),
+            Ok(response) => return Err(SignerError::Refused(format!("{:?}", response))),
+            Err(e) => return Err(SignerError::Connection(e.to_string())),
+        };
+        self.secp
+            .verify_schnorr(
+                &signature,
+                &Message::from_digest(sighash.to_byte_array()),
+                &key,
+            )
+            .map_err(|_| SignerError::InvalidSignature)?;
+        Ok(signature)
+    }
+}
+
+fn send_request(
+    connection: &Mutex<Connection>,
+    request: &SignerRequest,
+) -> Result<SignerResponse, Box<dyn Error>> {
+    let mut connection = connection.lock().unwrap();
+    writeln!(connection.writer, "{}", serde_json::to_string(request)?)?;
+    connection.writer.flush()?;
+    let mut line = String::new();
+    if connection.reader.read_line(&mut line)? == 0 {
+        return Err("signer closed the connection".into());
+    }
+    Ok(serde_json::from_str(&line)?)
+}
+
+impl Signer for ExternalSigner {
+    fn public_key(&self) -> XOnlyPublicKey {
+        self.public_key
+    }
+
+    fn sign(&self, sighash: TapSighash) -> Result<Signature, SignerError> {
+        self.request_signature(&SignerRequest::Sign { sighash }, sighash, self.public_key)
+    }
+
+    fn sign_with_tweak(
+        &self,
+        sighash: TapSighash,
+        merkle_root: Option<TapNodeHash>,
+    ) -> Result<Signature, SignerError> {
+        let tweak = TapTweakHash::from_key_and_tweak(self.public_key, merkle_root).to_scalar();
+        let (output_key, _) = self.public_key.add_tweak(&self.secp, &tweak).unwrap();
+        self.request_signature(
+            &SignerRequest::SignWithTweak {
+                sighash,
+                merkle_root,
+            },
+            sighash,
+            output_key,
+        )
+    }
+}
+
+impl Drop for ExternalSigner {
+    fn drop(&mut self) {
+        if let Some(child) = self.child.as_mut() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+// The signer side of the protocol, answering requests with the given signer until the input ends
+pub fn serve_signer<R: BufRead, W: Write>(
+    signer: &dyn Signer,
+    input: R,
+    mut output: W,
+) -> io::Result<()> {
+    for line in input.lines() {
+        let answer = match serde_json::from_str(&line?) {
+            Ok(SignerRequest::PublicKey) => Ok(SignerResponse::PublicKey(signer.public_key())),
+            Ok(SignerRequest::Sign { sighash }) => {
+                signer.sign(sighash).map(SignerResponse::Signature)
+            }
+            Ok(SignerRequest::SignWithTweak {
+                sighash,
+                merkle_root,
+            }) => signer
+                .sign_with_tweak(sighash, merkle_root)
+                .map(SignerResponse::Signature),
+            Err(e) => Err(SignerError::Refused(e.to_string())),
+        };
+        let response = answer.unwrap_or_else(|e| SignerResponse::Error(e.to_string()));
+        writeln!(output, "{}", serde_json::to_string(&response)?)?;
+        output.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signer::InMemorySigner;
+    use std::thread;
+
+    #[test]
+    fn test_external_signer() {
+        let (request_reader, request_writer) = io::pipe().unwrap();
+        let (response_reader, response_writer) = io::pipe().unwrap();
+        let server = thread::spawn(move || {
+            let signer = InMemorySigner::random();
+            serve_signer(&signer, BufReader::new(request_reader), response_writer).unwrap();
+            signer.public_key()
+        });
+
+        let signer = ExternalSigner::new(
+            Box::new(BufReader::new(response_reader)),
+            Box::new(request_writer),
+        )
+        .unwrap();
+        // Signatures are checked against the key on the way back
+        let sighash = TapSighash::from_byte_array([9; 32]);
+        signer.sign(sighash).unwrap();
+        signer.sign_with_tweak(sighash, None).unwrap();
+        signer
+            .sign_with_tweak(sighash, Some(TapNodeHash::from_byte_array([1; 32])))
+            .unwrap();
+        let public_key = signer.public_key();
+
+        drop(signer);
+        assert_eq!(server.join().unwrap(), public_key);
+
+        // A signer that refuses, or is gone, fails the signature instead of the caller
+        let answers = format!(
+            "{}\n{}\n",
+            serde_json::to_string(&SignerResponse::PublicKey(public_key)).unwrap(),
+            serde_json::to_string(&SignerResponse::Error("locked".to_string())).unwrap()
+        );
+        let signer =
+            ExternalSigner::new(Box::new(io::Cursor::new(answers)), Box::new(io::sink())).unwrap();
+        assert!(matches!(signer.sign(sighash), Err(SignerError::Refused(e)) if e == "locked"));
+        assert!(matches!(
+            signer.sign(sighash),
+            Err(SignerError::Connection(_))
+        ));
+
+        assert_eq!(
+            serde_json::to_string(&SignerRequest::Sign { sighash }).unwrap(),
+            format!(r#"{{"method":"sign","sighash":"{}"}}"#, sighash)
+        );
+    }
+}
@@ -0,0 +1,148 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use bitcoin::secp256k1::{rand, SecretKey, XOnlyPublicKey};
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use crate::traits::signer::Signer;
+
+use super::InMemorySigner;
+
+// Cost of the key derivation for new keystores, as the base 2 logarithm of the scrypt N parameter
+pub const DEFAULT_LOG_N: u8 = 15;
+
+#[derive(Debug)]
+pub enum KeystoreError {
+    Io(std::io::Error),
+    Format(serde_json::Error),
+    // The password is wrong or the file was tampered with
+    Decryption,
+    // The decrypted key does not match the public key stored next to it
+    KeyMismatch,
+}
+
+impl fmt::Display for KeystoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeystoreError::Io(e) => write!(f, "cannot access keystore: {}", e),
+            KeystoreError::Format(e) => write!(f, "malformed keystore: {}", e),
+            KeystoreError::Decryption => write!(f, "wrong password or corrupted keystore"),
+            KeystoreError::KeyMismatch => write!(f, "keystore key does not match its public key"),
+        }
+    }
+}
+
+impl std::error::Error for KeystoreError {}
+
+// Secret key encrypted with ChaCha20-Poly1305 under a key derived from a password with scrypt.
+// The public key is kept in the clear so the keystore can be identified without the password.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Keystore {
+    pub public_key: XOnlyPublicKey,
+    log_n: u8,
+    #[serde(with = "hex::serde")]
+    salt: [u8; 16],
+    #[serde(with = "hex::serde")]
+    nonce: [u8; 12],
+    #[serde(with = "hex::serde")]
+    ciphertext: Vec<u8>,
+}
+
+fn cipher(password: &str, salt: &[u8; 16], log_n: u8) -> ChaCha20Poly1305 {
+    let params = scrypt::Params::new(log_n, 8, 1, 32).expect("valid scrypt parameters");
+    let mut key = Zeroizing::new([0u8; 32]);
+    scrypt::scrypt(password.as_bytes(), salt, &params, key.as_mut_slice())
+        .expect("valid key length");
+    ChaCha20Poly1305::new(Key::from_slice(key.as_slice()))
+}
+
+impl Keystore {
+    pub fn encrypt(secret_key: &SecretKey, password: &str, log_n: u8) -> Self {
+        let mut rng = rand::thread_rng();
+        let salt: [u8; 16] = rng.gen();
+        let nonce: [u8; 12] = rng.gen();
+        let cipher = cipher(password, &salt, log_n);
+        let plaintext = Zeroizing::new(secret_key.secret_bytes());
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce), plaintext.as_slice())
+            .expect("encryption should not fail");
+        Keystore {
+            public_key: InMemorySigner::new(*secret_key).public_key(),
+            log_n,
+            salt,
+            nonce,
+            ciphertext,
+        }
+    }
+
+    pub fn decrypt(&self, password: &str) -> Result<InMemorySigner, KeystoreError> {
+        let cipher = cipher(password, &self.salt, self.log_n);
+        let plaintext = Zeroizing::new(
+            cipher
+                .decrypt(Nonce::from_slice(&self.nonce), self.ciphertext.as_slice())
+                .map_err(|_| KeystoreError::Decryption)?,
+        );
+        let mut secret_key =
+            SecretKey::from_slice(&plaintext).map_err(|_| KeystoreError::Decryption)?;
+        let signer = InMemorySigner::new(secret_key);
+        secret_key.non_secure_erase();
+        if signer.public_key() != self.public_key {
+            return Err(KeystoreError::KeyMismatch);
+        }
+        Ok(signer)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, KeystoreError> {
+        let contents = fs::read_to_string(path).map_err(KeystoreError::Io)?;
+        serde_json::from_str(&contents).map_err(KeystoreError::Format)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), KeystoreError> {
+        let contents = serde_json::to_string_pretty(self).map_err(KeystoreError::Format)?;
+        fs::write(path, contents).map_err(KeystoreError::Io)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keystore() {
+        let secret_key = SecretKey::new(&mut rand::thread_rng());
+        // Cheap key derivation, the default one is slow in debug builds
+        let keystore = Keystore::encrypt(&secret_key, "hunter2", 4);
+
+        let path = std::env::temp_dir().join(format!("keystore-{}.json", keystore.public_key));
+        keystore.save(&path).unwrap();
+        let loaded = Keystore::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, keystore);
+
+        let signer = loaded.decrypt("hunter2").unwrap();
+        assert_eq!(signer.expose_secret_key(), secret_key);
+        assert!(matches!(
+            loaded.decrypt("hunter3"),
+            Err(KeystoreError::Decryption)
+        ));
+
+        let mut tampered = keystore.clone();
+        tampered.ciphertext[0] ^= 1;
+        assert!(matches!(
+            tampered.decrypt("hunter2"),
+            Err(KeystoreError::Decryption)
+        ));
+
+        let mut other = keystore;
+        other.public_key = InMemorySigner::random().public_key();
+        assert!(matches!(
+            other.decrypt("hunter2"),
+            Err(KeystoreError::KeyMismatch)
+        ));
+    }
+}
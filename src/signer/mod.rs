@@ -0,0 +1,162 @@
+pub mod derivation;
+pub mod external;
+pub mod keystore;
+
+use std::env;
+use std::path::Path;
+
+use bitcoin::hashes::Hash;
+use bitcoin::secp256k1::schnorr::Signature;
+use bitcoin::secp256k1::{rand, All, Keypair, Message, Secp256k1, SecretKey, XOnlyPublicKey};
+use bitcoin::{TapNodeHash, TapSighash, TapTweakHash};
+use tracing::info;
+
+use crate::musig::{PartialSignature, SecretNonce, SigningSession};
+use crate::traits::signer::{Signer, SignerError};
+use crate::utils::take_stdin;
+
+use external::ExternalSigner;
+use keystore::{Keystore, DEFAULT_LOG_N};
+
+// Which signer the binaries use: unset for a fresh in-memory key, `keystore:<path>` for an
+// encrypted keystore file, created if missing, or `exec:<command>` for an external signer process
+pub const SIGNER_ENV_VAR: &str = "TOY_BITVM_SIGNER";
+// Password of the keystore, asked on stdin if not set
+pub const KEYSTORE_PASSWORD_ENV_VAR: &str = "TOY_BITVM_KEYSTORE_PASSWORD";
+
+pub struct InMemorySigner {
+    secp: Secp256k1<All>,
+    keypair: Keypair,
+}
+
+impl InMemorySigner {
+    pub fn new(secret_key: SecretKey) -> Self {
+        let secp = Secp256k1::new();
+        let keypair = Keypair::from_secret_key(&secp, &secret_key);
+        InMemorySigner { secp, keypair }
+    }
+
+    pub fn random() -> Self {
+        Self::new(SecretKey::new(&mut rand::thread_rng()))
+    }
+
+    // Only for moving the key somewhere safer, like a keystore
+    pub fn expose_secret_key(&self) -> SecretKey {
+        self.keypair.secret_key()
+    }
+
+    fn sign_with_keypair(&self, sighash: TapSighash, keypair: &Keypair) -> Signature {
+        self.secp.sign_schnorr_with_rng(
+            &Message::from_digest_slice(sighash.as_byte_array()).expect("should be hash"),
+            keypair,
+            &mut rand::thread_rng(),
+        )
+    }
+}
+
+// Best effort, the compiler may still leave copies of the key behind
+impl Drop for InMemorySigner {
+    fn drop(&mut self) {
+        self.keypair.non_secure_erase();
+    }
+}
+
+impl Signer for InMemorySigner {
+    fn public_key(&self) -> XOnlyPublicKey {
+        self.keypair.x_only_public_key().0
+    }
+
+    fn sign(&self, sighash: TapSighash) -> Result<Signature, SignerError> {
+        Ok(self.sign_with_keypair(sighash, &self.keypair))
+    }
+
+    fn sign_with_tweak(
+        &self,
+        sighash: TapSighash,
+        merkle_root: Option<TapNodeHash>,
+    ) -> Result<Signature, SignerError> {
+        let tweaked = self
+            .keypair
+            .add_xonly_tweak(
+                &self.secp,
+                &TapTweakHash::from_key_and_tweak(self.public_key(), merkle_root).to_scalar(),
+            )
+            .unwrap();
+        Ok(self.sign_with_keypair(sighash, &tweaked))
+    }
+
+    fn supports_musig(&self) -> bool {
+        true
+    }
+
+    fn musig_partial_sign(
+        &self,
+        session: &SigningSession,
+        secret_nonce: SecretNonce,
+    ) -> Option<PartialSignature> {
+        Some(session.partial_sign(&self.keypair, secret_nonce))
+    }
+}
+
+// The signer picked by the environment, see SIGNER_ENV_VAR
+pub fn signer_from_env() -> Box<dyn Signer> {
+    let Ok(spec) = env::var(SIGNER_ENV_VAR) else {
+        return Box::new(InMemorySigner::random());
+    };
+    if let Some(path) = spec.strip_prefix("keystore:") {
+        let password = env::var(KEYSTORE_PASSWORD_ENV_VAR)
+            .unwrap_or_else(|_| take_stdin("Enter the keystore password: ").unwrap());
+        // A missing keystore is created with a fresh key, so that the key survives restarts
+        if !Path::new(path).exists() {
+            Keystore::encrypt(
+                &SecretKey::new(&mut rand::thread_rng()),
+                &password,
+                DEFAULT_LOG_N,
+            )
+            .save(path)
+            .unwrap_or_else(|e| panic!("Failed to create keystore {}: {}", path, e));
+            info!(path, "created keystore");
+        }
+        let signer = Keystore::load(path)
+            .and_then(|keystore| keystore.decrypt(&password))
+            .unwrap_or_else(|e| panic!("Failed to open keystore {}: {}", path, e));
+        Box::new(signer)
+    } else if let Some(command) = spec.strip_prefix("exec:") {
+        let signer = ExternalSigner::spawn(command)
+            .unwrap_or_else(|e| panic!("Failed to start signer `{}`: {}", command, e));
+        Box::new(signer)
+    } else {
+        panic!("Unknown signer `{}`", spec);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_in_memory_signer() {
+        let secp = Secp256k1::verification_only();
+        let signer = InMemorySigner::random();
+        let sighash = TapSighash::from_byte_array([3; 32]);
+        let message = Message::from_digest(sighash.to_byte_array());
+
+        secp.verify_schnorr(
+            &signer.sign(sighash).unwrap(),
+            &message,
+            &signer.public_key(),
+        )
+        .unwrap();
+
+        let merkle_root = Some(TapNodeHash::from_byte_array([5; 32]));
+        let tweaked_pk =
+            TapTweakHash::from_key_and_tweak(signer.public_key(), merkle_root).to_scalar();
+        let (output_key, _) = signer.public_key().add_tweak(&secp, &tweaked_pk).unwrap();
+        secp.verify_schnorr(
+            &signer.sign_with_tweak(sighash, merkle_root).unwrap(),
+            &message,
+            &output_key,
+        )
+        .unwrap();
+    }
+}
@@ -0,0 +1,59 @@
+// Script execution shared by the tests, with the tapscript rules a node applies to a leaf
+use bitcoin::absolute::LockTime;
+use bitcoin::hashes::Hash;
+use bitcoin::taproot::LeafVersion;
+use bitcoin::{ScriptBuf, TapLeafHash, Transaction, TxOut};
+use bitcoin_scriptexec::{Exec, ExecCtx, ExecutionResult, Options, TxTemplate};
+
+fn leaf_exec(
+    tx: Transaction,
+    prevouts: Vec<TxOut>,
+    leaf_hash: TapLeafHash,
+    script: ScriptBuf,
+    stack: Vec<Vec<u8>>,
+) -> Exec {
+    Exec::new(
+        ExecCtx::Tapscript,
+        Options::default(),
+        TxTemplate {
+            tx,
+            prevouts,
+            input_idx: 0,
+            taproot_annex_scriptleaf: Some((leaf_hash, None)),
+        },
+        script,
+        stack,
+    )
+    .expect("error creating exec")
+}
+
+// Execution of a leaf outside of any transaction, so no signature check can pass
+pub fn create_exec(script: &ScriptBuf, stack: Vec<Vec<u8>>) -> Exec {
+    let tx = Transaction {
+        version: bitcoin::transaction::Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![],
+        output: vec![],
+    };
+    leaf_exec(tx, vec![], TapLeafHash::all_zeros(), script.clone(), stack)
+}
+
+pub fn finish(mut exec: Exec) -> ExecutionResult {
+    while exec.exec_next().is_ok() {}
+    exec.result().unwrap().clone()
+}
+
+pub fn run_script(script: &ScriptBuf, stack: Vec<Vec<u8>>) -> ExecutionResult {
+    finish(create_exec(script, stack))
+}
+
+// Execution of the leaf spent by the first input of the transaction
+pub fn run_spend(
+    tx: &Transaction,
+    prevouts: Vec<TxOut>,
+    script: ScriptBuf,
+    stack: Vec<Vec<u8>>,
+) -> ExecutionResult {
+    let leaf_hash = TapLeafHash::from_script(&script, LeafVersion::TapScript);
+    finish(leaf_exec(tx.clone(), prevouts, leaf_hash, script, stack))
+}
@@ -0,0 +1,484 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::{thread, time};
+
+use bitcoin::absolute::{Height, LockTime};
+use bitcoin::hashes::Hash;
+use bitcoin::secp256k1::schnorr::Signature;
+use bitcoin::secp256k1::{Message, Secp256k1, Verification};
+use bitcoin::sighash::{Prevouts, SighashCache, TapSighashType};
+use bitcoin::taproot::{LeafVersion, TaprootSpendInfo};
+use bitcoin::{
+    OutPoint, ScriptBuf, TapLeafHash, TapSighash, Transaction, TxIn, TxOut, Txid, Witness,
+    XOnlyPublicKey,
+};
+use bitcoincore_rpc::{Client, RpcApi};
+use serde::{Deserialize, Serialize};
+use tokio_tungstenite::connect_async;
+use tracing::{info, warn};
+
+use crate::actor::Actor;
+use crate::circuit::wire::{HashCommitment, HashTuple, PreimageValue, RevealTuple};
+use crate::circuit::Circuit;
+use crate::communication::{receive_message, send_message};
+use crate::traits::bit_commitment::BitCommitment;
+use crate::traits::signer::SignerError;
+use crate::transactions::generate_anti_contradiction_script;
+
+// Address of a watchtower, e.g. ws://127.0.0.1:9001, for the verifier to hand its game to
+pub const WATCHTOWER_ENV_VAR: &str = "TOY_BITVM_WATCHTOWER";
+
+// A wire whose equivocation the tower watches for, with the leaf that proves it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WatchedWire {
+    pub index: usize,
+    pub commitment: HashTuple,
+    pub script: ScriptBuf,
+    #[serde(with = "hex::serde")]
+    pub control_block: Vec<u8>,
+}
+
+// The equivocation output of a round, with a slash of it presigned for every watched wire
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WatchedRound {
+    pub round: u64,
+    // The kickoff or response holding the equivocation output at vout 1
+    pub txid: Txid,
+    // The verifier's challenge spending it, which means the game goes on
+    pub challenge_txid: Txid,
+    pub prevout: TxOut,
+    pub slash_tx: Transaction,
+    pub signatures: HashMap<usize, Signature>,
+}
+
+// Everything a watchtower needs to slash an equivocating prover on behalf of the verifier. Every
+// slash is signed up front, so the tower never holds a key of the verifier.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WatchtowerPackage {
+    pub funding: OutPoint,
+    pub verifier_public_key: XOnlyPublicKey,
+    pub wires: Vec<WatchedWire>,
+    pub rounds: Vec<WatchedRound>,
+}
+
+// The wires covered by the anti-contradiction leaves of the equivocation tree
+pub fn watched_wires(
+    circuit: &Circuit,
+    equivocation_info: &TaprootSpendInfo,
+    verifier_pk: XOnlyPublicKey,
+) -> Vec<WatchedWire> {
+    circuit
+        .get_revealed_wires()
+        .iter()
+        .map(|wire_arcm| {
+            let wire = wire_arcm.lock().unwrap();
+            let script =
+                generate_anti_contradiction_script::<HashCommitment>(&wire.commitment, verifier_pk);
+            let control_block = equivocation_info
+                .control_block(&(script.clone(), LeafVersion::TapScript))
+                .expect("Cannot create control block");
+            WatchedWire {
+                index: wire.index.unwrap(),
+                commitment: wire.commitment,
+                script,
+                control_block: control_block.serialize(),
+            }
+        })
+        .collect()
+}
+
+fn slash_sighash(slash_tx: &Transaction, prevout: &TxOut, script: &ScriptBuf) -> TapSighash {
+    SighashCache::new(slash_tx)
+        .taproot_script_spend_signature_hash(
+            0,
+            &Prevouts::All(std::slice::from_ref(prevout)),
+            TapLeafHash::from_script(script, LeafVersion::TapScript),
+            TapSighashType::Default,
+        )
+        .unwrap()
+}
+
+// Signs a slash of the equivocation output at vout 1 of txid for every watched wire, paying the
+// output minus the fee to the payout
+pub fn presign_slashes(
+    verifier: &Actor,
+    round: u64,
+    txid: Txid,
+    challenge_txid: Txid,
+    prevout: TxOut,
+    payout: TxOut,
+    wires: &[WatchedWire],
+) -> Result<WatchedRound, SignerError> {
+    let slash_tx = Transaction {
+        version: bitcoin::transaction::Version::TWO,
+        lock_time: LockTime::from(Height::MIN),
+        input: vec![TxIn {
+            previous_output: OutPoint { txid, vout: 1 },
+            script_sig: ScriptBuf::new(),
+            sequence: bitcoin::transaction::Sequence::ENABLE_RBF_NO_LOCKTIME,
+            witness: Witness::new(),
+        }],
+        output: vec![payout],
+    };
+    let signatures = wires
+        .iter()
+        .map(|wire| {
+            let sighash = slash_sighash(&slash_tx, &prevout, &wire.script);
+            Ok((wire.index, verifier.sign(sighash)?))
+        })
+        .collect::<Result<_, SignerError>>()?;
+    Ok(WatchedRound {
+        round,
+        txid,
+        challenge_txid,
+        prevout,
+        slash_tx,
+        signatures,
+    })
+}
+
+// Hands the package to the tower at the given address, which confirms once it checked it
+pub async fn register_with_watchtower(
+    url: &str,
+    package: &WatchtowerPackage,
+) -> Result<(), Box<dyn Error>> {
+    let (mut ws_stream, _) = connect_async(url).await?;
+    send_message(&mut ws_stream, package).await?;
+    let accepted: bool = receive_message(&mut ws_stream).await?;
+    if !accepted {
+        return Err("watchtower rejected the package".into());
+    }
+    info!(
+        url,
+        rounds = package.rounds.len(),
+        "registered with the watchtower"
+    );
+    Ok(())
+}
+
+pub struct Watchtower {
+    package: WatchtowerPackage,
+    reveals: HashMap<usize, RevealTuple<PreimageValue>>,
+}
+
+impl Watchtower {
+    // Checks every presigned slash against the verifier's public key
+    pub fn new<C: Verification>(
+        secp: &Secp256k1<C>,
+        package: WatchtowerPackage,
+    ) -> Result<Self, String> {
+        for round in &package.rounds {
+            for wire in &package.wires {
+                let signature = round.signatures.get(&wire.index).ok_or(format!(
+                    "no slash of wire {} in round {}",
+                    wire.index, round.round
+                ))?;
+                let sighash = slash_sighash(&round.slash_tx, &round.prevout, &wire.script);
+                secp.verify_schnorr(
+                    signature,
+                    &Message::from_digest(sighash.to_byte_array()),
+                    &package.verifier_public_key,
+                )
+                .map_err(|_| {
+                    format!(
+                        "invalid slash of wire {} in round {}",
+                        wire.index, round.round
+                    )
+                })?;
+            }
+        }
+        Ok(Watchtower {
+            package,
+            reveals: HashMap::new(),
+        })
+    }
+
+    // Records the reveals in the first input of a round's transaction. Returns the signed slash of
+    // the round once both values of a wire have been revealed.
+    pub fn process(&mut self, round: usize, tx: &Transaction) -> Option<Transaction> {
+        let round = &self.package.rounds[round];
+        for element in tx.input.first()?.witness.iter() {
+            let Ok(preimage) = PreimageValue::try_from(element) else {
+                continue;
+            };
+            for wire in &self.package.wires {
+                if let Some(bit) = HashCommitment::parse_reveal(&wire.commitment, &preimage) {
+                    let reveals = self.reveals.entry(wire.index).or_insert(RevealTuple {
+                        zero: None,
+                        one: None,
+                    });
+                    if bit {
+                        reveals.one = Some(preimage);
+                    } else {
+                        reveals.zero = Some(preimage);
+                    }
+                }
+            }
+        }
+        let (wire, zero, one) = self.package.wires.iter().find_map(|wire| {
+            let reveals = self.reveals.get(&wire.index)?;
+            Some((wire, reveals.zero?, reveals.one?))
+        })?;
+        warn!(
+            wire = wire.index,
+            round = round.round,
+            "equivocation detected"
+        );
+        let mut slash_tx = round.slash_tx.clone();
+        let mut witness = Witness::new();
+        witness.push(round.signatures[&wire.index].as_ref());
+        for element in HashCommitment::equivocation_witness(&zero, &one) {
+            witness.push(element);
+        }
+        witness.push(wire.script.as_bytes());
+        witness.push(&wire.control_block);
+        slash_tx.input[0].witness = witness;
+        Some(slash_tx)
+    }
+
+    // Follows the rounds of the game on chain, slashing the prover as soon as it equivocates.
+    // Returns the txid of the slash, or None once the game ended without one. A slash that fails
+    // to broadcast is retried until it goes through or the equivocation output is spent.
+    pub fn watch<N: TowerNode + ?Sized>(
+        &mut self,
+        node: &N,
+        interval: time::Duration,
+    ) -> Option<Txid> {
+        let mut next = 0;
+        let mut pending: Option<Transaction> = None;
+        loop {
+            while pending.is_none() && next < self.package.rounds.len() {
+                let txid = self.package.rounds[next].txid;
+                let Some(tx) = node.find_transaction(&txid) else {
+                    break;
+                };
+                info!(round = self.package.rounds[next].round, %txid, "detected spend");
+                pending = self.process(next, &tx);
+                next += 1;
+            }
+            if let Some(slash_tx) = &pending {
+                match node.broadcast(slash_tx) {
+                    Ok(txid) => {
+                        info!(kind = "slash", %txid, "broadcast transaction");
+                        return Some(txid);
+                    }
+                    Err(e) => warn!(error = %e, "failed to broadcast the slash, retrying"),
+                }
+                // Once the equivocation output is spent the slash can never confirm
+                if node.is_spent(slash_tx.input[0].previous_output) == Ok(true) {
+                    warn!("equivocation output spent before the slash");
+                    return None;
+                }
+            } else if next == self.package.rounds.len() {
+                info!("all rounds watched");
+                return None;
+            } else if let Some(last) = next.checked_sub(1).map(|i| &self.package.rounds[i]) {
+                // An equivocation output spent by anything but the verifier's challenge ends the
+                // game
+                let spent = node
+                    .is_spent(OutPoint {
+                        txid: last.txid,
+                        vout: 1,
+                    })
+                    .unwrap_or(true);
+                if spent && node.find_transaction(&last.challenge_txid).is_none() {
+                    info!(round = last.round, "game ended");
+                    return None;
+                }
+            }
+            thread::sleep(interval);
+        }
+    }
+}
+
+// What the tower needs from the node, so that following a game does not depend on a live one
+pub trait TowerNode {
+    fn find_transaction(&self, txid: &Txid) -> Option<Transaction>;
+
+    fn broadcast(&self, tx: &Transaction) -> Result<Txid, String>;
+
+    // Whether the output is spent, counting spends in the mempool
+    fn is_spent(&self, outpoint: OutPoint) -> Result<bool, String>;
+}
+
+impl TowerNode for Client {
+    fn find_transaction(&self, txid: &Txid) -> Option<Transaction> {
+        self.get_raw_transaction(txid, None).ok()
+    }
+
+    fn broadcast(&self, tx: &Transaction) -> Result<Txid, String> {
+        self.send_raw_transaction(tx).map_err(|e| e.to_string())
+    }
+
+    fn is_spent(&self, outpoint: OutPoint) -> Result<bool, String> {
+        self.get_tx_out(&outpoint.txid, outpoint.vout, Some(true))
+            .map(|unspent| unspent.is_none())
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transactions::generate_compact_equivocation_address_and_info;
+    use bitcoin::transaction::Version;
+    use bitcoin::{Amount, Sequence};
+    use std::cell::Cell;
+
+    struct FakeNode {
+        chain: HashMap<Txid, Transaction>,
+        failures: Cell<usize>,
+        broadcasts: Cell<usize>,
+        spent: bool,
+    }
+
+    impl TowerNode for FakeNode {
+        fn find_transaction(&self, txid: &Txid) -> Option<Transaction> {
+            self.chain.get(txid).cloned()
+        }
+
+        fn broadcast(&self, tx: &Transaction) -> Result<Txid, String> {
+            self.broadcasts.set(self.broadcasts.get() + 1);
+            if self.failures.get() > 0 {
+                self.failures.set(self.failures.get() - 1);
+                return Err("connection refused".to_string());
+            }
+            Ok(tx.txid())
+        }
+
+        fn is_spent(&self, _outpoint: OutPoint) -> Result<bool, String> {
+            Ok(self.spent)
+        }
+    }
+
+    fn response_with(preimages: &[PreimageValue]) -> Transaction {
+        let mut witness = Witness::new();
+        witness.push([1; 64]);
+        for preimage in preimages {
+            witness.push(preimage);
+        }
+        Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness,
+            }],
+            output: vec![],
+        }
+    }
+
+    #[test]
+    fn test_watchtower() {
+        let secp = Secp256k1::new();
+        let prover = Actor::new();
+        let verifier = Actor::new();
+        let circuit = Circuit::default();
+        let (_, equivocation_info) = generate_compact_equivocation_address_and_info(
+            &secp,
+            &circuit,
+            prover.public_key,
+            verifier.public_key,
+        );
+        let wires = watched_wires(&circuit, &equivocation_info, verifier.public_key);
+        let prevout = TxOut {
+            value: Amount::from_sat(100_000),
+            script_pubkey: ScriptBuf::new(),
+        };
+        let payout = TxOut {
+            value: Amount::from_sat(90_000),
+            script_pubkey: verifier.address.script_pubkey(),
+        };
+        let rounds = (0..3)
+            .map(|round| {
+                presign_slashes(
+                    &verifier,
+                    round,
+                    Txid::from_byte_array([round as u8 + 1; 32]),
+                    Txid::all_zeros(),
+                    prevout.clone(),
+                    payout.clone(),
+                    &wires,
+                )
+                .unwrap()
+            })
+            .collect();
+        let package = WatchtowerPackage {
+            funding: OutPoint::null(),
+            verifier_public_key: verifier.public_key,
+            wires: wires.clone(),
+            rounds,
+        };
+        let package: WatchtowerPackage =
+            serde_json::from_str(&serde_json::to_string(&package).unwrap()).unwrap();
+
+        // Signatures by another key are refused
+        let mut forged = package.clone();
+        forged.verifier_public_key = prover.public_key;
+        assert!(Watchtower::new(&secp, forged).is_err());
+
+        let mut tower = Watchtower::new(&secp, package.clone()).unwrap();
+        let wire = circuit.wires[wires[0].index].lock().unwrap();
+        let secret = wire.secret.as_ref().unwrap();
+        let zero = HashCommitment::reveal(secret, false);
+        let one = HashCommitment::reveal(secret, true);
+        assert!(tower.process(0, &response_with(&[])).is_none());
+        assert!(tower.process(1, &response_with(&[zero, [0; 32]])).is_none());
+
+        // The second value of the wire, revealed a round later, gets the prover slashed
+        let slash_tx = tower.process(2, &response_with(&[one])).unwrap();
+        assert_eq!(
+            slash_tx.input[0].previous_output.txid,
+            package.rounds[2].txid
+        );
+        let witness = &slash_tx.input[0].witness;
+        assert_eq!(witness.len(), 5);
+        assert_eq!(witness.nth(1), Some(&one[..]));
+        assert_eq!(witness.nth(2), Some(&zero[..]));
+        let sighash = slash_sighash(&slash_tx, &prevout, &wires[0].script);
+        secp.verify_schnorr(
+            &Signature::from_slice(witness.nth(0).unwrap()).unwrap(),
+            &Message::from_digest(sighash.to_byte_array()),
+            &verifier.public_key,
+        )
+        .unwrap();
+
+        // A slash that fails to broadcast is retried until it goes through
+        let responses = [
+            response_with(&[]),
+            response_with(&[zero, [0; 32]]),
+            response_with(&[one]),
+        ];
+        let chain: HashMap<Txid, Transaction> = package
+            .rounds
+            .iter()
+            .map(|round| round.txid)
+            .zip(responses)
+            .collect();
+        let node = FakeNode {
+            chain: chain.clone(),
+            failures: Cell::new(2),
+            broadcasts: Cell::new(0),
+            spent: false,
+        };
+        let mut tower = Watchtower::new(&secp, package.clone()).unwrap();
+        assert_eq!(
+            tower.watch(&node, time::Duration::ZERO),
+            Some(slash_tx.txid())
+        );
+        assert_eq!(node.broadcasts.get(), 3);
+
+        // and given up once the equivocation output is spent
+        let node = FakeNode {
+            chain,
+            failures: Cell::new(usize::MAX),
+            broadcasts: Cell::new(0),
+            spent: true,
+        };
+        let mut tower = Watchtower::new(&secp, package).unwrap();
+        assert_eq!(tower.watch(&node, time::Duration::ZERO), None);
+        assert_eq!(node.broadcasts.get(), 1);
+    }
+}
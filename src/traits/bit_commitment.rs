@@ -0,0 +1,37 @@
+use std::fmt::Debug;
+
+use bitcoin::script::Builder;
+use serde::{de::DeserializeOwned, Serialize};
+
+// A scheme to commit to a single bit in a way that can be opened inside tapscript. The committer
+// keeps the secret and publishes the public commitment, opening it later by revealing one of the
+// two values. Revealing both values is an equivocation, which can be proven on chain.
+pub trait BitCommitment: Send + Sync + 'static {
+    type Secret: Clone + Send + Sync;
+    type Public: Clone + Debug + Serialize + DeserializeOwned + Send + Sync;
+    type Reveal: Clone + Debug + PartialEq + Send + Sync;
+
+    // Number of witness elements a single reveal takes
+    const REVEAL_WITNESS_LEN: usize;
+
+    // Extra stack elements the commitment script needs on top of the reveal it consumes
+    const SCRIPT_STACK_OVERHEAD: usize;
+
+    fn generate() -> (Self::Secret, Self::Public);
+
+    fn reveal(secret: &Self::Secret, bit: bool) -> Self::Reveal;
+
+    fn parse_reveal(public: &Self::Public, reveal: &Self::Reveal) -> Option<bool>;
+
+    fn reveal_to_witness(reveal: &Self::Reveal) -> Vec<Vec<u8>>;
+
+    fn reveal_from_witness(witness: &[Vec<u8>]) -> Option<Self::Reveal>;
+
+    // Consumes a reveal from the top of the stack and leaves the committed bit in its place.
+    fn add_commitment_script(public: &Self::Public, builder: Builder) -> Builder;
+
+    // Consumes the reveals of both bits from the top of the stack, failing unless both are valid.
+    fn add_equivocation_script(public: &Self::Public, builder: Builder) -> Builder;
+
+    fn equivocation_witness(zero: &Self::Reveal, one: &Self::Reveal) -> Vec<Vec<u8>>;
+}
@@ -1,18 +1,57 @@
-use crate::circuit::wire::{HashValue, PreimageValue, Wire};
+use crate::circuit::wire::{
+    HashCommitment, HashValue, PreimageValue, RevealError, RevealOutcome, Wire,
+};
+use crate::traits::bit_commitment::BitCommitment;
+use bitcoin::script::Builder;
 use bitcoin::ScriptBuf;
 use std::{
     iter::zip,
     sync::{Arc, Mutex},
 };
 
-pub type Wires = Vec<Arc<Mutex<Wire>>>;
+pub type Wires<C = HashCommitment> = Vec<Arc<Mutex<Wire<C>>>>;
+
+pub fn commitments_of<C: BitCommitment>(wires: &Wires<C>) -> Vec<C::Public> {
+    wires
+        .iter()
+        .map(|wire_arcm| wire_arcm.lock().unwrap().get_commitment())
+        .collect()
+}
+
+// The wire accessors of GateTrait, for gates keeping their wires in `input_wires` and
+// `output_wires` fields
+macro_rules! wire_accessors {
+    () => {
+        fn get_input_wires(&mut self) -> &mut Wires<C> {
+            &mut self.input_wires
+        }
+
+        fn get_output_wires(&mut self) -> &mut Wires<C> {
+            &mut self.output_wires
+        }
+
+        fn get_wires(&self) -> (&Wires<C>, &Wires<C>) {
+            (&self.input_wires, &self.output_wires)
+        }
+    };
+}
+pub(crate) use wire_accessors;
+
+// Reveals of all the wires of a gate, whose outputs don't match the gate evaluated on the inputs
+pub struct WrongOutputProof<C: BitCommitment = HashCommitment> {
+    pub input_reveals: Vec<C::Reveal>,
+    pub output_reveals: Vec<C::Reveal>,
+}
 
-pub trait GateTrait {
+pub trait GateTrait<C: BitCommitment = HashCommitment> {
     fn get_input_size(&self) -> usize;
     fn get_output_size(&self) -> usize;
 
-    fn get_input_wires(&mut self) -> &mut Wires;
-    fn get_output_wires(&mut self) -> &mut Wires;
+    fn get_input_wires(&mut self) -> &mut Wires<C>;
+    fn get_output_wires(&mut self) -> &mut Wires<C>;
+
+    // The input and output wires, for when the gate is only borrowed
+    fn get_wires(&self) -> (&Wires<C>, &Wires<C>);
 
     fn get_input_bits(&mut self) -> Vec<bool> {
         self.get_input_wires()
@@ -48,39 +87,88 @@ pub trait GateTrait {
 
     fn create_response_script(&self, lock_hash: HashValue) -> ScriptBuf;
 
-    fn create_response_witness(&mut self, hashlock_preimage: PreimageValue) -> Vec<PreimageValue> {
-        let input_preimages = self
+    // Consumes the input bits from the stack, the last input on top, and leaves the output bits in
+    // their place, the last output on top. Used to evaluate gates inside a larger script.
+    fn add_compute_script(&self, builder: Builder) -> Builder;
+
+    // Extra stack elements the compute script needs on top of its inputs
+    fn compute_stack_overhead(&self) -> usize {
+        0
+    }
+
+    fn create_response_witness(&mut self, hashlock_preimage: PreimageValue) -> Vec<Vec<u8>> {
+        let input_reveals = self
             .get_input_wires()
             .iter()
-            .map(|wire_arcm| wire_arcm.lock().unwrap().get_preimage_of_selector())
-            .collect::<Vec<[u8; 32]>>();
-        let output_preimages = self
+            .flat_map(|wire_arcm| {
+                C::reveal_to_witness(&wire_arcm.lock().unwrap().get_reveal_of_selector())
+            })
+            .collect::<Vec<Vec<u8>>>();
+        let output_reveals = self
             .get_output_wires()
             .iter()
-            .map(|wire_arcm| wire_arcm.lock().unwrap().get_preimage_of_selector())
-            .collect::<Vec<[u8; 32]>>();
-        let mut witness = input_preimages;
-        witness.extend(output_preimages);
-        witness.push(hashlock_preimage);
+            .flat_map(|wire_arcm| {
+                C::reveal_to_witness(&wire_arcm.lock().unwrap().get_reveal_of_selector())
+            })
+            .collect::<Vec<Vec<u8>>>();
+        let mut witness = input_reveals;
+        witness.extend(output_reveals);
+        witness.push(hashlock_preimage.to_vec());
         witness
     }
 
-    fn add_preimages_from_witness(&mut self, witness: Vec<PreimageValue>) -> Option<Wire> {
-        let input_preimages = witness[0..self.get_input_size()].to_vec();
-        let output_preimages = witness[self.get_input_size()..].to_vec();
-        for (wire_arcm, preimage) in zip(&mut self.get_input_wires().iter(), input_preimages) {
-            let found_contradiction = wire_arcm.lock().unwrap().add_preimage(preimage);
-            if found_contradiction.is_some() {
-                return found_contradiction;
-            }
+    // Records the reveals of the input and output wires from a response witness, returning what was
+    // learned about each wire. The whole witness is checked before anything is recorded.
+    fn add_preimages_from_witness(
+        &mut self,
+        witness: &[Vec<u8>],
+    ) -> Result<Vec<RevealOutcome<C>>, RevealError> {
+        let expected = (self.get_input_size() + self.get_output_size()) * C::REVEAL_WITNESS_LEN;
+        if witness.len() != expected {
+            return Err(RevealError::WrongWitnessLength {
+                expected,
+                found: witness.len(),
+            });
+        }
+        let reveals = witness
+            .chunks(C::REVEAL_WITNESS_LEN)
+            .enumerate()
+            .map(|(i, chunk)| {
+                C::reveal_from_witness(chunk).ok_or(RevealError::MalformedReveal {
+                    position: i * C::REVEAL_WITNESS_LEN,
+                })
+            })
+            .collect::<Result<Vec<C::Reveal>, RevealError>>()?;
+        let mut wires = self.get_input_wires().clone();
+        wires.extend(self.get_output_wires().iter().cloned());
+        for (wire_arcm, reveal) in zip(&wires, &reveals) {
+            wire_arcm.lock().unwrap().check_reveal(reveal)?;
         }
-        for (wire_arcm, preimage) in zip(&mut self.get_output_wires().iter(), output_preimages) {
-            let found_contradiction = wire_arcm.lock().unwrap().add_preimage(preimage);
-            if found_contradiction.is_some() {
-                return found_contradiction;
-            }
+        zip(&wires, reveals)
+            .map(|(wire_arcm, reveal)| wire_arcm.lock().unwrap().add_reveal(reveal))
+            .collect()
+    }
+
+    // Looks for revealed values of all the input and output wires for which the outputs don't
+    // match the gate evaluated on the inputs.
+    fn find_wrong_output(&mut self) -> Option<WrongOutputProof<C>> {
+        let known = |wires: &Wires<C>| {
+            wires
+                .iter()
+                .map(|wire_arcm| wire_arcm.lock().unwrap().get_known_reveal())
+                .collect::<Option<Vec<(bool, C::Reveal)>>>()
+        };
+        let (input_bits, input_reveals): (Vec<bool>, Vec<C::Reveal>) =
+            known(self.get_input_wires())?.into_iter().unzip();
+        let (output_bits, output_reveals): (Vec<bool>, Vec<C::Reveal>) =
+            known(self.get_output_wires())?.into_iter().unzip();
+        if self.run_gate_on_inputs(input_bits) == output_bits {
+            return None;
         }
-        None
+        Some(WrongOutputProof {
+            input_reveals,
+            output_reveals,
+        })
     }
 
     fn run_gate_on_inputs(&self, inputs: Vec<bool>) -> Vec<bool>;
@@ -1 +1,3 @@
+pub mod bit_commitment;
 pub mod gate;
+pub mod signer;
@@ -0,0 +1,58 @@
+use std::fmt;
+
+use bitcoin::secp256k1::schnorr::Signature;
+use bitcoin::secp256k1::XOnlyPublicKey;
+use bitcoin::{TapNodeHash, TapSighash};
+
+use crate::musig::{PartialSignature, SecretNonce, SigningSession};
+
+#[derive(Debug)]
+pub enum SignerError {
+    // The signer could not be reached, e.g. an external signer that exited
+    Connection(String),
+    // The signer answered with something other than a signature
+    Refused(String),
+    // The signature does not verify against the key it should have been made with
+    InvalidSignature,
+}
+
+impl fmt::Display for SignerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SignerError::Connection(e) => write!(f, "cannot reach the signer: {}", e),
+            SignerError::Refused(e) => write!(f, "signer did not sign: {}", e),
+            SignerError::InvalidSignature => write!(f, "signer returned an invalid signature"),
+        }
+    }
+}
+
+impl std::error::Error for SignerError {}
+
+// Holds the key of an actor and signs for it, so that the key can live outside the protocol
+// process. Signatures are BIP 340 Schnorr signatures over taproot sighashes.
+pub trait Signer: Send + Sync {
+    fn public_key(&self) -> XOnlyPublicKey;
+
+    fn sign(&self, sighash: TapSighash) -> Result<Signature, SignerError>;
+
+    // Signs for the key path of a P2TR output with the public key as internal key
+    fn sign_with_tweak(
+        &self,
+        sighash: TapSighash,
+        merkle_root: Option<TapNodeHash>,
+    ) -> Result<Signature, SignerError>;
+
+    // A MuSig partial signature needs the secret key and the secret nonce together, so only
+    // signers with the key in process can take part in a cooperative close
+    fn supports_musig(&self) -> bool {
+        false
+    }
+
+    fn musig_partial_sign(
+        &self,
+        _session: &SigningSession,
+        _secret_nonce: SecretNonce,
+    ) -> Option<PartialSignature> {
+        None
+    }
+}
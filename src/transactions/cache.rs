@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::script::Builder;
+use bitcoin::secp256k1::{All, Secp256k1};
+use bitcoin::taproot::TaprootSpendInfo;
+use bitcoin::{Address, XOnlyPublicKey};
+use tracing::warn;
+
+use crate::circuit::wire::HashValue;
+use crate::circuit::Circuit;
+use crate::traits::bit_commitment::BitCommitment;
+use crate::traits::gate::commitments_of;
+
+use super::{generate_challenge_address_and_info, generate_response_address_and_info};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LeafSet {
+    Challenge,
+    Response,
+}
+
+// Challenge and response trees have one leaf per gate and are needed several times per round by
+// both actors, so they are built once per (round, leaf set) and shared afterwards. Each entry
+// keeps a digest of everything its leaves were built from, so a lookup with other inputs rebuilds
+// the tree instead of returning a stale one.
+#[derive(Default)]
+pub struct TaprootCache {
+    entries: HashMap<(usize, LeafSet), (sha256::Hash, Address, Arc<TaprootSpendInfo>)>,
+}
+
+// Tags a part of the inputs and prefixes it and each of its items with their length, so that
+// inputs of other shapes cannot hash alike
+fn input_part(engine: &mut sha256::HashEngine, tag: &[u8], items: &[Vec<u8>]) {
+    engine.input(&(tag.len() as u64).to_le_bytes());
+    engine.input(tag);
+    engine.input(&(items.len() as u64).to_le_bytes());
+    for item in items {
+        engine.input(&(item.len() as u64).to_le_bytes());
+        engine.input(item);
+    }
+}
+
+// Challenge leaves only depend on the keys and hashes. Response leaves also check the commitments
+// of the wires of their gate and compute the gate, so those of the circuit are covered too.
+fn inputs_digest<C: BitCommitment>(
+    keys: &[XOnlyPublicKey],
+    challenge_hashes: &[HashValue],
+    circuit: Option<&Circuit<C>>,
+) -> sha256::Hash {
+    let mut engine = sha256::Hash::engine();
+    let keys = keys
+        .iter()
+        .map(|key| key.serialize().to_vec())
+        .collect::<Vec<_>>();
+    input_part(&mut engine, b"keys", &keys);
+    let hashes = challenge_hashes
+        .iter()
+        .map(|hash| hash.to_vec())
+        .collect::<Vec<_>>();
+    input_part(&mut engine, b"challenge hashes", &hashes);
+    if let Some(circuit) = circuit {
+        let gates = circuit
+            .gates
+            .iter()
+            .flat_map(|gate| {
+                let (inputs, outputs) = gate.get_wires();
+                let commitments =
+                    serde_json::to_vec(&(commitments_of(inputs), commitments_of(outputs))).unwrap();
+                let compute = gate.add_compute_script(Builder::new()).into_script();
+                [commitments, compute.into_bytes()]
+            })
+            .collect::<Vec<_>>();
+        input_part(&mut engine, b"gates", &gates);
+    }
+    sha256::Hash::from_engine(engine)
+}
+
+impl TaprootCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, round: usize, leaf_set: LeafSet) -> Option<(Address, Arc<TaprootSpendInfo>)> {
+        self.entries
+            .get(&(round, leaf_set))
+            .map(|(_, address, tree_info)| (address.clone(), tree_info.clone()))
+    }
+
+    pub fn get_or_insert_with<F>(
+        &mut self,
+        round: usize,
+        leaf_set: LeafSet,
+        inputs: sha256::Hash,
+        build: F,
+    ) -> (Address, Arc<TaprootSpendInfo>)
+    where
+        F: FnOnce() -> (Address, TaprootSpendInfo),
+    {
+        match self.entries.get(&(round, leaf_set)) {
+            Some((cached_inputs, address, tree_info)) if *cached_inputs == inputs => {
+                return (address.clone(), tree_info.clone());
+            }
+            Some(_) => warn!(
+                ?leaf_set,
+                round, "cached tree was built from other inputs, rebuilding"
+            ),
+            None => {}
+        }
+        let (address, tree_info) = build();
+        let tree_info = Arc::new(tree_info);
+        self.entries.insert(
+            (round, leaf_set),
+            (inputs, address.clone(), tree_info.clone()),
+        );
+        (address, tree_info)
+    }
+
+    pub fn challenge_address_and_info<C: BitCommitment>(
+        &mut self,
+        secp: &Secp256k1<All>,
+        round: usize,
+        circuit: &Circuit<C>,
+        prover_pk: XOnlyPublicKey,
+        verifier_pk: XOnlyPublicKey,
+        challenge_hashes: &[HashValue],
+    ) -> (Address, Arc<TaprootSpendInfo>) {
+        let inputs = inputs_digest::<C>(&[prover_pk, verifier_pk], challenge_hashes, None);
+        self.get_or_insert_with(round, LeafSet::Challenge, inputs, || {
+            generate_challenge_address_and_info(
+                secp,
+                circuit,
+                prover_pk,
+                verifier_pk,
+                challenge_hashes,
+            )
+        })
+    }
+
+    pub fn response_address_and_info<C: BitCommitment>(
+        &mut self,
+        secp: &Secp256k1<All>,
+        round: usize,
+        circuit: &Circuit<C>,
+        prover_pk: XOnlyPublicKey,
+        challenge_hashes: &[HashValue],
+    ) -> (Address, Arc<TaprootSpendInfo>) {
+        let inputs = inputs_digest(&[prover_pk], challenge_hashes, Some(circuit));
+        self.get_or_insert_with(round, LeafSet::Response, inputs, || {
+            generate_response_address_and_info(secp, circuit, prover_pk, challenge_hashes)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::secp256k1::rand;
+
+    #[test]
+    fn test_cache_rebuilds_for_other_inputs() {
+        let secp = Secp256k1::new();
+        let circuit: Circuit = Circuit::default();
+        let pk = secp
+            .generate_keypair(&mut rand::thread_rng())
+            .1
+            .x_only_public_key()
+            .0;
+        let hashes = |byte| vec![[byte; 32]; circuit.num_gates()];
+        let mut cache = TaprootCache::new();
+        let first = cache.response_address_and_info(&secp, 0, &circuit, pk, &hashes(1));
+        let again = cache.response_address_and_info(&secp, 0, &circuit, pk, &hashes(1));
+        assert_eq!(first.0, again.0);
+
+        let other_hashes = cache.response_address_and_info(&secp, 0, &circuit, pk, &hashes(2));
+        assert_ne!(first.0, other_hashes.0);
+        assert_eq!(cache.get(0, LeafSet::Response).unwrap().0, other_hashes.0);
+
+        // The same keys and hashes with the commitments of another circuit
+        let other: Circuit = Circuit::default();
+        let other_circuit = cache.response_address_and_info(&secp, 0, &other, pk, &hashes(2));
+        assert_ne!(other_hashes.0, other_circuit.0);
+        assert_eq!(
+            other_circuit.0,
+            generate_response_address_and_info(&secp, &other, pk, &hashes(2)).0
+        );
+    }
+}
@@ -0,0 +1,349 @@
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use bitcoin::absolute::{Height, LockTime};
+use bitcoin::secp256k1::{All, Secp256k1};
+use bitcoin::sighash::{Prevouts, SighashCache, TapSighashType};
+use bitcoin::{
+    Address, Amount, FeeRate, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid,
+    Weight, Witness, XOnlyPublicKey,
+};
+use bitcoincore_rpc::json::SignRawTransactionInput;
+use bitcoincore_rpc::{Client, RpcApi};
+use tokio::task::JoinHandle;
+use tracing::{info, warn, Span};
+
+use crate::actor::Actor;
+use crate::traits::signer::Signer;
+
+use super::fees::{
+    choose_fee_rate, dummy_witness, estimate_weight, fee_for, FeeSchedule, ANCHOR_VALUE, DUST_LIMIT,
+};
+use super::rpc::rpc_client_for_wallet;
+use super::TIMELOCK_BLOCKS;
+
+// Largest witness of a wallet input, a P2WPKH signature and public key
+const WALLET_INPUT_WITNESS_SIZES: [usize; 2] = [72, 33];
+
+// Output only the given actor can spend, so that it can bump the fee of a presigned transaction
+// by spending it in a child transaction
+pub fn anchor_output(secp: &Secp256k1<All>, actor_pk: XOnlyPublicKey) -> TxOut {
+    TxOut {
+        value: ANCHOR_VALUE,
+        script_pubkey: Address::p2tr(secp, actor_pk, None, bitcoin::Network::Regtest)
+            .script_pubkey(),
+    }
+}
+
+// The outputs of a presigned transaction, with the anchor of the actor broadcasting it if the
+// schedule has anchors. The anchor always goes last so that the other outputs keep their index.
+pub fn with_anchor(
+    secp: &Secp256k1<All>,
+    fee_schedule: &FeeSchedule,
+    outputs: &[TxOut],
+    actor_pk: XOnlyPublicKey,
+) -> Vec<TxOut> {
+    let mut outputs = outputs.to_vec();
+    if fee_schedule.anchor > Amount::ZERO {
+        outputs.push(anchor_output(secp, actor_pk));
+    }
+    outputs
+}
+
+// Child spending the anchor and a wallet coin into a single change output, paying enough for the
+// parent and the child together to reach the fee rate. Returns None if the coin is too small.
+pub fn create_cpfp_tx(
+    anchor: (OutPoint, TxOut),
+    wallet_coin: (OutPoint, TxOut),
+    change_script_pubkey: ScriptBuf,
+    parent_weight: Weight,
+    parent_fee: Amount,
+    fee_rate: FeeRate,
+) -> Option<Transaction> {
+    let child_weight = estimate_weight(
+        vec![
+            dummy_witness(&[64], None),
+            dummy_witness(&WALLET_INPUT_WITNESS_SIZES, None),
+        ],
+        1,
+    );
+    let package_fee = fee_for(fee_rate, parent_weight + child_weight);
+    let child_fee = package_fee
+        .checked_sub(parent_fee)
+        .unwrap_or(Amount::ZERO)
+        .max(fee_for(fee_rate, child_weight));
+    let change = (anchor.1.value + wallet_coin.1.value).checked_sub(child_fee)?;
+    if change < DUST_LIMIT {
+        return None;
+    }
+    Some(Transaction {
+        version: bitcoin::transaction::Version::TWO,
+        lock_time: LockTime::from(Height::MIN),
+        input: [anchor.0, wallet_coin.0]
+            .into_iter()
+            .map(|previous_output| TxIn {
+                previous_output,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::new(),
+            })
+            .collect(),
+        output: vec![TxOut {
+            value: change,
+            script_pubkey: change_script_pubkey,
+        }],
+    })
+}
+
+// Bumps a broadcast transaction to the fee rate with a child spending the anchor of the funding
+// key and a coin of the node's wallet, which signs its own input. A child bumping the same
+// transaction before is replaced, since both spend the anchor.
+pub fn bump_with_cpfp(
+    rpc: &Client,
+    funding_signer: &dyn Signer,
+    parent: &Transaction,
+    parent_fee: Amount,
+    fee_rate: FeeRate,
+) -> Result<Txid, Box<dyn Error>> {
+    let secp = Secp256k1::new();
+    let anchor_script_pubkey = anchor_output(&secp, funding_signer.public_key()).script_pubkey;
+    let anchor_vout = parent
+        .output
+        .iter()
+        .position(|output| output.script_pubkey == anchor_script_pubkey)
+        .ok_or("transaction has no anchor of this actor")?;
+    let anchor = (
+        OutPoint {
+            txid: parent.txid(),
+            vout: anchor_vout as u32,
+        },
+        parent.output[anchor_vout].clone(),
+    );
+    let change_address = rpc.get_raw_change_address(None)?.assume_checked();
+    let (mut child, wallet_prevout) = rpc
+        .list_unspent(Some(1), None, None, Some(false), None)?
+        .into_iter()
+        .filter(|coin| coin.spendable)
+        .find_map(|coin| {
+            let wallet_prevout = TxOut {
+                value: coin.amount,
+                script_pubkey: coin.script_pub_key,
+            };
+            let child = create_cpfp_tx(
+                anchor.clone(),
+                (
+                    OutPoint {
+                        txid: coin.txid,
+                        vout: coin.vout,
+                    },
+                    wallet_prevout.clone(),
+                ),
+                change_address.script_pubkey(),
+                parent.weight(),
+                parent_fee,
+                fee_rate,
+            )?;
+            Some((child, wallet_prevout))
+        })
+        .ok_or("no wallet coin is large enough to bump the fee")?;
+
+    let sig_hash = SighashCache::new(&child).taproot_key_spend_signature_hash(
+        0,
+        &Prevouts::All(&[anchor.1.clone(), wallet_prevout]),
+        TapSighashType::Default,
+    )?;
+    child.input[0]
+        .witness
+        .push(funding_signer.sign_with_tweak(sig_hash, None)?.as_ref());
+
+    let signed = rpc.sign_raw_transaction_with_wallet(
+        &child,
+        Some(&[SignRawTransactionInput {
+            txid: anchor.0.txid,
+            vout: anchor.0.vout,
+            script_pub_key: anchor.1.script_pubkey,
+            redeem_script: None,
+            amount: Some(anchor.1.value),
+        }]),
+        None,
+    )?;
+    if !signed.complete {
+        return Err(format!("wallet could not sign the child: {:?}", signed.errors).into());
+    }
+    let txid = rpc.send_raw_transaction(&signed.transaction()?)?;
+    info!(kind = "cpfp", %txid, parent = %parent.txid(), fee_rate = %fee_rate, "broadcast transaction");
+    Ok(txid)
+}
+
+// Bumps a broadcast presigned transaction whenever the fee rate rises above the last one it paid,
+// until it leaves the mempool or the timelock of the other side expires, since it cannot win the
+// output after that. Every child replaces the one before it, so only the last is returned.
+fn keep_bumped(
+    rpc: &Client,
+    funding_signer: &dyn Signer,
+    fee_schedule: &FeeSchedule,
+    parent: &Transaction,
+    parent_fee: Amount,
+    interval: Duration,
+    stop: &AtomicBool,
+) -> Option<Txid> {
+    let parent_txid = parent.txid();
+    let deadline = rpc.get_block_count().unwrap() + TIMELOCK_BLOCKS as u64;
+    let mut paid = fee_schedule.fee_rate;
+    let mut child = None;
+    while !stop.load(Ordering::Relaxed) {
+        // Out of the mempool means confirmed, or beaten by a spend of the other side
+        if rpc.get_mempool_entry(&parent_txid).is_err()
+            || rpc.get_block_count().unwrap() >= deadline
+        {
+            break;
+        }
+        // A replacement pays at least the incremental relay fee rate more than the child before
+        let increment = if child.is_some() {
+            FeeRate::BROADCAST_MIN.to_sat_per_kwu()
+        } else {
+            0
+        };
+        let fee_rate = choose_fee_rate(rpc);
+        if fee_rate.to_sat_per_kwu() > paid.to_sat_per_kwu() + increment {
+            match bump_with_cpfp(rpc, funding_signer, parent, parent_fee, fee_rate) {
+                Ok(txid) => {
+                    paid = fee_rate;
+                    child = Some(txid);
+                }
+                Err(e) => warn!(parent = %parent_txid, error = %e, "failed to bump the fee"),
+            }
+        }
+        thread::sleep(interval);
+    }
+    child
+}
+
+// Keeps the presigned transactions an actor broadcasts bumped while they wait for confirmation.
+// Every transaction is watched on a thread of its own, with its own RPC client of the given wallet.
+pub struct FeeBumper {
+    funding_signer: Arc<dyn Signer>,
+    wallet: String,
+    fee_schedule: FeeSchedule,
+    interval: Duration,
+    stop: Arc<AtomicBool>,
+    watched: Vec<(Option<u64>, JoinHandle<Option<Txid>>)>,
+}
+
+impl FeeBumper {
+    pub fn new(
+        actor: &Actor,
+        wallet: &str,
+        fee_schedule: &FeeSchedule,
+        interval: Duration,
+    ) -> Self {
+        FeeBumper {
+            funding_signer: actor.funding_signer(),
+            wallet: wallet.to_string(),
+            fee_schedule: *fee_schedule,
+            interval,
+            stop: Arc::new(AtomicBool::new(false)),
+            watched: Vec::new(),
+        }
+    }
+
+    // Starts watching a transaction that was just broadcast, if it has an anchor to bump it with
+    pub fn watch(&mut self, parent: &Transaction, parent_fee: Amount, round: Option<u64>) {
+        if self.fee_schedule.anchor == Amount::ZERO {
+            return;
+        }
+        let funding_signer = self.funding_signer.clone();
+        let wallet = self.wallet.clone();
+        let fee_schedule = self.fee_schedule;
+        let interval = self.interval;
+        let stop = self.stop.clone();
+        let parent = parent.clone();
+        let span = Span::current();
+        let handle = tokio::task::spawn_blocking(move || {
+            let _span = span.enter();
+            keep_bumped(
+                &rpc_client_for_wallet(&wallet),
+                funding_signer.as_ref(),
+                &fee_schedule,
+                &parent,
+                parent_fee,
+                interval,
+                &stop,
+            )
+        });
+        self.watched.push((round, handle));
+    }
+
+    // Stops watching and returns the child of every bumped transaction, with the round of its parent
+    pub async fn finish(&mut self) -> Vec<(Option<u64>, Txid)> {
+        self.stop.store(true, Ordering::Relaxed);
+        let mut children = Vec::new();
+        for (round, handle) in self.watched.drain(..) {
+            children.extend(handle.await.unwrap().map(|txid| (round, txid)));
+        }
+        children
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+    use bitcoin::secp256k1::rand;
+
+    #[test]
+    fn test_cpfp_tx() {
+        let secp = Secp256k1::new();
+        let actor_pk = secp
+            .generate_keypair(&mut rand::thread_rng())
+            .1
+            .x_only_public_key()
+            .0;
+        let anchor = (
+            OutPoint::new(Txid::all_zeros(), 2),
+            anchor_output(&secp, actor_pk),
+        );
+        let coin = |sats| {
+            (
+                OutPoint::new(Txid::all_zeros(), 0),
+                TxOut {
+                    value: Amount::from_sat(sats),
+                    script_pubkey: ScriptBuf::new_op_return(&[]),
+                },
+            )
+        };
+        let fee_rate = FeeRate::from_sat_per_vb_unchecked(20);
+        let parent_weight = Weight::from_vb_unchecked(1000);
+        let parent_fee = Amount::from_sat(2000);
+
+        let child = create_cpfp_tx(
+            anchor.clone(),
+            coin(100_000),
+            ScriptBuf::new(),
+            parent_weight,
+            parent_fee,
+            fee_rate,
+        )
+        .unwrap();
+        let child_fee = ANCHOR_VALUE + Amount::from_sat(100_000) - child.output[0].value;
+        // The package pays the fee rate for the weight of the parent and the signed child
+        let mut signed = child.clone();
+        signed.input[0].witness = dummy_witness(&[64], None);
+        signed.input[1].witness = dummy_witness(&WALLET_INPUT_WITNESS_SIZES, None);
+        assert!(parent_fee + child_fee >= fee_for(fee_rate, parent_weight + signed.weight()));
+        assert!(child_fee < Amount::from_sat(30_000));
+
+        assert!(create_cpfp_tx(
+            anchor,
+            coin(1000),
+            ScriptBuf::new(),
+            parent_weight,
+            parent_fee,
+            fee_rate
+        )
+        .is_none());
+    }
+}
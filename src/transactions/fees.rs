@@ -0,0 +1,399 @@
+use std::env;
+
+use bitcoin::absolute::{Height, LockTime};
+use bitcoin::taproot::{
+    LeafVersion, TaprootSpendInfo, TAPROOT_CONTROL_BASE_SIZE, TAPROOT_CONTROL_NODE_SIZE,
+};
+use bitcoin::{
+    Amount, FeeRate, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Weight, Witness,
+    XOnlyPublicKey,
+};
+use bitcoincore_rpc::{Client, RpcApi};
+
+use crate::circuit::Circuit;
+use crate::traits::bit_commitment::BitCommitment;
+
+use super::{generate_2_of_2_script, generate_challenge_script, generate_gate_response_script};
+
+pub const DUST_LIMIT: Amount = Amount::from_sat(546);
+// Dust limit of a P2TR output, the smallest an anchor can be
+pub const ANCHOR_VALUE: Amount = Amount::from_sat(330);
+// Used when neither the environment nor the node gives a fee rate, e.g. on a fresh regtest chain
+pub const DEFAULT_FEE_RATE: FeeRate = FeeRate::from_sat_per_vb_unchecked(2);
+// Fee rates above this are rejected when proposed by the counterparty
+pub const MAX_FEE_RATE: FeeRate = FeeRate::from_sat_per_vb_unchecked(1000);
+// Fee rates below our own estimate divided by this are rejected too, since the presigned
+// transactions could then not confirm before their timelocks expire
+pub const MIN_FEE_RATE_DIVISOR: u64 = 2;
+pub const FEE_RATE_ENV_VAR: &str = "TOY_BITVM_FEE_RATE";
+pub const ANCHORS_ENV_VAR: &str = "TOY_BITVM_ANCHORS";
+
+const SCHNORR_SIGNATURE_SIZE: usize = 64;
+const P2TR_SCRIPT_PUBKEY_SIZE: usize = 34;
+const CONFIRMATION_TARGET: u16 = 6;
+
+// Fee rate in sat/vB from the environment variable if set, otherwise estimated by the node
+pub fn choose_fee_rate(rpc: &Client) -> FeeRate {
+    if let Ok(value) = env::var(FEE_RATE_ENV_VAR) {
+        let sat_per_vb = value
+            .parse::<u64>()
+            .unwrap_or_else(|e| panic!("Invalid {}: {}", FEE_RATE_ENV_VAR, e));
+        return FeeRate::from_sat_per_vb(sat_per_vb).expect("fee rate is too large");
+    }
+    match rpc.estimate_smart_fee(CONFIRMATION_TARGET, None) {
+        // The node gives the fee rate per kvB, a kvB being 4000 weight units
+        Ok(estimate) => estimate
+            .fee_rate
+            .map(|per_kvb| FeeRate::from_sat_per_kwu(per_kvb.to_sat() / 4))
+            .unwrap_or(DEFAULT_FEE_RATE),
+        Err(_) => DEFAULT_FEE_RATE,
+    }
+    .max(FeeRate::BROADCAST_MIN)
+}
+
+// Checks a fee rate proposed by the counterparty against our own estimate
+pub fn check_proposed_fee_rate(proposed: FeeRate, estimate: FeeRate) -> Result<(), String> {
+    if proposed > MAX_FEE_RATE {
+        return Err(format!("fee rate {} is too high", proposed));
+    }
+    let floor = FeeRate::from_sat_per_kwu(estimate.to_sat_per_kwu() / MIN_FEE_RATE_DIVISOR)
+        .max(FeeRate::BROADCAST_MIN);
+    if proposed < floor {
+        return Err(format!(
+            "fee rate {} is too far below the estimate of {}",
+            proposed, estimate
+        ));
+    }
+    Ok(())
+}
+
+// Whether the presigned transactions should get anchor outputs, set by the environment variable
+pub fn anchors_enabled() -> bool {
+    env::var(ANCHORS_ENV_VAR).is_ok_and(|value| value != "0")
+}
+
+pub(crate) fn fee_for(fee_rate: FeeRate, weight: Weight) -> Amount {
+    Amount::from_sat((fee_rate.to_sat_per_kwu() * weight.to_wu()).div_ceil(1000))
+}
+
+// Witness with dummy stack items of the given sizes, followed by the script and control block if
+// it is a script path spend. Only meant for weight estimation.
+pub(crate) fn dummy_witness(stack_item_sizes: &[usize], leaf: Option<(usize, usize)>) -> Witness {
+    let mut witness = Witness::new();
+    for size in stack_item_sizes {
+        witness.push(vec![0; *size]);
+    }
+    if let Some((script_size, control_block_size)) = leaf {
+        witness.push(vec![0; script_size]);
+        witness.push(vec![0; control_block_size]);
+    }
+    witness
+}
+
+// Weight of a transaction spending inputs with the given witnesses into P2TR outputs
+pub(crate) fn estimate_weight(witnesses: Vec<Witness>, num_outputs: usize) -> Weight {
+    let tx = Transaction {
+        version: bitcoin::transaction::Version::TWO,
+        lock_time: LockTime::from(Height::MIN),
+        input: witnesses
+            .into_iter()
+            .map(|witness| TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness,
+            })
+            .collect(),
+        output: (0..num_outputs)
+            .map(|_| TxOut {
+                value: Amount::ZERO,
+                script_pubkey: ScriptBuf::from_bytes(vec![0; P2TR_SCRIPT_PUBKEY_SIZE]),
+            })
+            .collect(),
+    };
+    tx.weight()
+}
+
+// Deepest control block of a tree built by taproot_address_from_script_leaves. A single leaf is
+// the root itself, e.g. the challenge tree of a circuit in one chunk.
+fn balanced_control_block_size(num_leaves: usize) -> usize {
+    if num_leaves <= 1 {
+        return TAPROOT_CONTROL_BASE_SIZE;
+    }
+    let depth = (num_leaves - 1).ilog2() as usize + 1;
+    TAPROOT_CONTROL_BASE_SIZE + depth * TAPROOT_CONTROL_NODE_SIZE
+}
+
+fn control_block_size(tree_info: &TaprootSpendInfo, script: &ScriptBuf) -> usize {
+    tree_info
+        .control_block(&(script.clone(), LeafVersion::TapScript))
+        .expect("script should be in the tree")
+        .size()
+}
+
+// Sizes of the witness elements of a single reveal, whichever bit it opens
+fn reveal_item_sizes<C: BitCommitment>() -> Vec<usize> {
+    let (secret, _) = C::generate();
+    let zero = C::reveal_to_witness(&C::reveal(&secret, false));
+    let one = C::reveal_to_witness(&C::reveal(&secret, true));
+    zero.iter()
+        .zip(one.iter())
+        .map(|(a, b)| a.len().max(b.len()))
+        .collect()
+}
+
+// Fees of every transaction of the dispute, sized from the largest witness each one can have so
+// that the amounts can be fixed before any of them is signed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FeeSchedule {
+    pub fee_rate: FeeRate,
+    pub kickoff: Amount,
+    // Sent by the verifier, spends a challenge leaf and the 2-of-2 leaf of the equivocation output
+    pub challenge: Amount,
+    // Sent by the prover, spends a response leaf and the 2-of-2 leaf of the second response output
+    pub response: Amount,
+    // Spends any leaf of the equivocation output other than the 2-of-2 one
+    pub slash: Amount,
+    pub close: Amount,
+    // Value of the anchor output of each presigned transaction, zero if they have none
+    pub anchor: Amount,
+}
+
+impl FeeSchedule {
+    pub fn new<C: BitCommitment>(
+        fee_rate: FeeRate,
+        circuit: &Circuit<C>,
+        prover_pk: XOnlyPublicKey,
+        verifier_pk: XOnlyPublicKey,
+        equivocation_info: &TaprootSpendInfo,
+        response_second_info: &TaprootSpendInfo,
+        anchors: bool,
+    ) -> Self {
+        // The kickoff, challenge and response transactions all have two outputs, plus the anchor
+        let round_outputs = 2 + usize::from(anchors);
+        let reveal_sizes = reveal_item_sizes::<C>();
+        let sig = SCHNORR_SIGNATURE_SIZE;
+        let musig_script = generate_2_of_2_script(prover_pk, verifier_pk);
+        let musig_witness = |tree_info: &TaprootSpendInfo| {
+            dummy_witness(
+                &[sig, sig],
+                Some((
+                    musig_script.len(),
+                    control_block_size(tree_info, &musig_script),
+                )),
+            )
+        };
+        let key_path_witness = dummy_witness(&[sig], None);
+
+        let challenge_script = generate_challenge_script(prover_pk, verifier_pk, &[0; 32]);
+        let challenge_witness = dummy_witness(
+            &[sig, 32],
+            Some((
+                challenge_script.len(),
+                balanced_control_block_size(circuit.num_gates()),
+            )),
+        );
+
+        // Every response leaf has the same depth, the largest witness depends on the chunk
+        let response_witness = circuit
+            .gates
+            .iter()
+            .map(|gate| {
+                let num_wires = gate.get_input_size() + gate.get_output_size();
+                let mut stack = vec![sig];
+                for _ in 0..num_wires {
+                    stack.extend(&reveal_sizes);
+                }
+                stack.push(32);
+                let script = generate_gate_response_script(gate, &[0; 32], prover_pk);
+                dummy_witness(
+                    &stack,
+                    Some((
+                        script.len(),
+                        balanced_control_block_size(circuit.num_gates()),
+                    )),
+                )
+            })
+            .max_by_key(|witness| witness.size())
+            .unwrap();
+
+        // Bounded by the largest stack of a fraud proof, script and control block in the tree,
+        // even if they belong to different leaves
+        let max_wires = circuit
+            .gates
+            .iter()
+            .map(|gate| gate.get_input_size() + gate.get_output_size())
+            .max()
+            .unwrap()
+            .max(2);
+        let mut slash_stack = vec![sig];
+        for _ in 0..max_wires {
+            slash_stack.extend(&reveal_sizes);
+        }
+        let script_map = equivocation_info.script_map();
+        let max_script_size = script_map.keys().map(|(script, _)| script.len()).max();
+        let max_depth = script_map
+            .values()
+            .flat_map(|branches| branches.iter().map(|branch| branch.len()))
+            .max();
+        let slash_witness = dummy_witness(
+            &slash_stack,
+            Some((
+                max_script_size.unwrap(),
+                TAPROOT_CONTROL_BASE_SIZE + max_depth.unwrap() * TAPROOT_CONTROL_NODE_SIZE,
+            )),
+        );
+
+        FeeSchedule {
+            fee_rate,
+            kickoff: fee_for(
+                fee_rate,
+                estimate_weight(vec![key_path_witness.clone()], round_outputs),
+            ),
+            challenge: fee_for(
+                fee_rate,
+                estimate_weight(
+                    vec![challenge_witness, musig_witness(equivocation_info)],
+                    round_outputs,
+                ),
+            ),
+            response: fee_for(
+                fee_rate,
+                estimate_weight(
+                    vec![response_witness, musig_witness(response_second_info)],
+                    round_outputs,
+                ),
+            ),
+            slash: fee_for(fee_rate, estimate_weight(vec![slash_witness], 1)),
+            close: fee_for(fee_rate, estimate_weight(vec![key_path_witness], 1)),
+            anchor: if anchors { ANCHOR_VALUE } else { Amount::ZERO },
+        }
+    }
+
+    // Value of the equivocation output created in the given round. Each round pays for a challenge
+    // and a response, the first one for the kickoff instead of a response, and their anchors.
+    pub fn equivocation_value(&self, funding: Amount, round: u64) -> Amount {
+        funding
+            - DUST_LIMIT
+            - self.kickoff
+            - self.anchor
+            - (self.challenge + self.response + self.anchor * 2) * round
+    }
+
+    pub fn response_second_value(&self, funding: Amount, round: u64) -> Amount {
+        self.equivocation_value(funding, round) - self.challenge - self.anchor
+    }
+
+    // Enough for every round to be played and for the equivocation output of the one after the
+    // last to still be slashed or closed into an output above the dust limit
+    pub fn required_funding(&self, rounds: u64) -> Amount {
+        DUST_LIMIT
+            + self.kickoff
+            + self.anchor
+            + (self.challenge + self.response + self.anchor * 2) * rounds
+            + self.slash.max(self.close)
+            + DUST_LIMIT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::chunk::ChunkLimits;
+    use crate::transactions::{
+        generate_compact_equivocation_address_and_info, generate_response_second_address_and_info,
+    };
+    use bitcoin::secp256k1::{rand, Secp256k1};
+
+    #[test]
+    fn test_fee_schedule() {
+        let secp = Secp256k1::new();
+        let prover_pk = secp.generate_keypair(&mut rand::thread_rng()).1;
+        let verifier_pk = secp.generate_keypair(&mut rand::thread_rng()).1;
+        let (prover_pk, verifier_pk) = (
+            prover_pk.x_only_public_key().0,
+            verifier_pk.x_only_public_key().0,
+        );
+        let circuit: Circuit =
+            Circuit::from_bristol("bristol/add.txt", None).into_chunks(&ChunkLimits::default());
+        let (_, equivocation_info) =
+            generate_compact_equivocation_address_and_info(&secp, &circuit, prover_pk, verifier_pk);
+        let (_, response_second_info) =
+            generate_response_second_address_and_info(&secp, prover_pk, verifier_pk);
+        let schedule = |fee_rate, anchors| {
+            FeeSchedule::new(
+                fee_rate,
+                &circuit,
+                prover_pk,
+                verifier_pk,
+                &equivocation_info,
+                &response_second_info,
+                anchors,
+            )
+        };
+
+        let low = schedule(FeeRate::from_sat_per_vb_unchecked(1), false);
+        let high = schedule(FeeRate::from_sat_per_vb_unchecked(10), false);
+        // A key path spend is a bit over 100 vB, a response with its script far more
+        assert!(low.kickoff > Amount::from_sat(100) && low.kickoff < Amount::from_sat(200));
+        assert!(low.response > low.challenge && low.challenge > low.kickoff);
+        assert!(high.response > low.response * 9);
+
+        let rounds = 10;
+        let funding = low.required_funding(rounds);
+        let last = low.response_second_value(funding, rounds - 1);
+        assert!(last - low.response >= low.slash.max(low.close) + DUST_LIMIT);
+        assert_eq!(
+            low.equivocation_value(funding, 1),
+            low.response_second_value(funding, 0) - low.response
+        );
+
+        // Anchors cost their value and a bit of weight in every presigned transaction
+        let anchored = schedule(FeeRate::from_sat_per_vb_unchecked(1), true);
+        assert!(anchored.response > low.response && anchored.slash == low.slash);
+        let funding = anchored.required_funding(rounds);
+        assert!(funding > low.required_funding(rounds) + ANCHOR_VALUE * (2 * rounds + 1));
+        let last = anchored.response_second_value(funding, rounds - 1);
+        assert!(
+            last - anchored.response - ANCHOR_VALUE
+                >= anchored.slash.max(anchored.close) + DUST_LIMIT
+        );
+    }
+
+    #[test]
+    fn test_proposed_fee_rate() {
+        let estimate = FeeRate::from_sat_per_vb_unchecked(20);
+        assert!(check_proposed_fee_rate(FeeRate::from_sat_per_vb_unchecked(15), estimate).is_ok());
+        assert!(check_proposed_fee_rate(FeeRate::BROADCAST_MIN, estimate).is_err());
+        assert!(
+            check_proposed_fee_rate(FeeRate::from_sat_per_vb_unchecked(2000), estimate).is_err()
+        );
+    }
+
+    #[test]
+    fn test_one_chunk_fee_schedule() {
+        let secp = Secp256k1::new();
+        let pk = secp
+            .generate_keypair(&mut rand::thread_rng())
+            .1
+            .x_only_public_key()
+            .0;
+        // The challenge and response trees of a circuit in a single chunk are one leaf each
+        let circuit: Circuit = Circuit::default().into_chunks(&ChunkLimits::default());
+        assert_eq!(circuit.num_gates(), 1);
+        assert_eq!(balanced_control_block_size(1), TAPROOT_CONTROL_BASE_SIZE);
+        let (_, equivocation_info) =
+            generate_compact_equivocation_address_and_info(&secp, &circuit, pk, pk);
+        let (_, response_second_info) = generate_response_second_address_and_info(&secp, pk, pk);
+        let schedule = FeeSchedule::new(
+            FeeRate::from_sat_per_vb_unchecked(1),
+            &circuit,
+            pk,
+            pk,
+            &equivocation_info,
+            &response_second_info,
+            false,
+        );
+        assert!(schedule.response > schedule.challenge);
+    }
+}
@@ -0,0 +1,239 @@
+use std::env;
+use std::error::Error;
+use std::sync::Mutex;
+
+use bitcoin::absolute::{Height, LockTime};
+use bitcoin::psbt::Psbt;
+use bitcoin::{
+    Amount, FeeRate, OutPoint, Script, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness,
+};
+use bitcoincore_rpc::{Client, RpcApi};
+use tracing::info;
+
+use crate::utils::take_stdin;
+
+use super::fees::{dummy_witness, estimate_weight, fee_for, DUST_LIMIT};
+
+// Set to sign the funding PSBT with another wallet instead of the node's
+pub const EXTERNAL_FUNDING_ENV_VAR: &str = "TOY_BITVM_EXTERNAL_FUNDING";
+
+// Inputs can be of any type the signing wallet supports, so they are sized as the larger of a
+// P2WPKH and a P2TR key path spend
+const FUNDING_INPUT_WITNESS_SIZES: [usize; 2] = [72, 33];
+
+// Held while a game is funded, so that games funded at the same time do not pick the same coins
+static FUNDING_LOCK: Mutex<()> = Mutex::new(());
+
+pub fn external_funding_enabled() -> bool {
+    env::var(EXTERNAL_FUNDING_ENV_VAR).is_ok_and(|value| value != "0")
+}
+
+// Index of the first output paying to the script, instead of trusting the order a wallet chose
+pub fn find_output(tx: &Transaction, script_pubkey: &Script) -> Option<u32> {
+    tx.output
+        .iter()
+        .position(|output| output.script_pubkey.as_script() == script_pubkey)
+        .map(|vout| vout as u32)
+}
+
+// Unsigned PSBT paying the funding output from the given coins, largest first, with the rest going
+// back to the change script unless it would be dust. Returns None if the coins are not enough.
+pub fn create_funding_psbt(
+    coins: &[(OutPoint, TxOut)],
+    funding_output: TxOut,
+    change_script_pubkey: ScriptBuf,
+    fee_rate: FeeRate,
+) -> Option<Psbt> {
+    let mut coins = coins.to_vec();
+    coins.sort_by_key(|(_, coin)| std::cmp::Reverse(coin.value));
+
+    let fee_with = |num_inputs: usize, num_outputs: usize| {
+        fee_for(
+            fee_rate,
+            estimate_weight(
+                vec![dummy_witness(&FUNDING_INPUT_WITNESS_SIZES, None); num_inputs],
+                num_outputs,
+            ),
+        )
+    };
+
+    let mut selected = Vec::new();
+    let mut total = Amount::ZERO;
+    for coin in coins {
+        total += coin.1.value;
+        selected.push(coin);
+        if total >= funding_output.value + fee_with(selected.len(), 1) {
+            break;
+        }
+    }
+    let available = total.checked_sub(funding_output.value + fee_with(selected.len(), 1))?;
+
+    let mut outputs = vec![funding_output];
+    let change = available
+        .checked_sub(fee_with(selected.len(), 2) - fee_with(selected.len(), 1))
+        .filter(|change| *change >= DUST_LIMIT);
+    if let Some(change) = change {
+        outputs.push(TxOut {
+            value: change,
+            script_pubkey: change_script_pubkey,
+        });
+    }
+
+    let tx = Transaction {
+        version: bitcoin::transaction::Version::TWO,
+        lock_time: LockTime::from(Height::MIN),
+        input: selected
+            .iter()
+            .map(|(previous_output, _)| TxIn {
+                previous_output: *previous_output,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::new(),
+            })
+            .collect(),
+        output: outputs,
+    };
+    let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+    for (input, (_, coin)) in psbt.inputs.iter_mut().zip(selected) {
+        input.witness_utxo = Some(coin);
+    }
+    Some(psbt)
+}
+
+// Funds the output from the coins of the node's wallet. The PSBT is signed by the node, or printed
+// for another wallet to sign if external funding is enabled, in which case the coins can also be
+// watch-only ones. Returns the outpoint of the output.
+pub fn fund_with_psbt(
+    rpc: &Client,
+    funding_output: TxOut,
+    fee_rate: FeeRate,
+) -> Result<OutPoint, Box<dyn Error>> {
+    let _funding = FUNDING_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let external = external_funding_enabled();
+    let coins: Vec<(OutPoint, TxOut)> = rpc
+        .list_unspent(Some(1), None, None, Some(false), None)?
+        .into_iter()
+        .filter(|coin| coin.spendable || (external && coin.solvable))
+        .map(|coin| {
+            (
+                OutPoint {
+                    txid: coin.txid,
+                    vout: coin.vout,
+                },
+                TxOut {
+                    value: coin.amount,
+                    script_pubkey: coin.script_pub_key,
+                },
+            )
+        })
+        .collect();
+    let change_address = rpc.get_raw_change_address(None)?.assume_checked();
+    let funding_script_pubkey = funding_output.script_pubkey.clone();
+    let psbt = create_funding_psbt(
+        &coins,
+        funding_output,
+        change_address.script_pubkey(),
+        fee_rate,
+    )
+    .ok_or("wallet does not have enough coins to fund the game")?;
+
+    let signed = if external {
+        println!("Funding PSBT:\n{}", psbt);
+        take_stdin::<Psbt>("Enter the signed funding PSBT: ")?.to_string()
+    } else {
+        rpc.wallet_process_psbt(&psbt.to_string(), Some(true), None, None)?
+            .psbt
+    };
+    let finalized = rpc.finalize_psbt(&signed, Some(true))?;
+    if !finalized.complete {
+        return Err("funding PSBT is not fully signed".into());
+    }
+    let tx: Transaction = bitcoin::consensus::deserialize(
+        &finalized
+            .hex
+            .ok_or("finalized funding PSBT has no transaction")?,
+    )?;
+    let txid = rpc.send_raw_transaction(&tx)?;
+    info!(kind = "funding", %txid, "broadcast transaction");
+    let vout = find_output(&tx, &funding_script_pubkey)
+        .ok_or("funding transaction does not pay the funding output")?;
+    Ok(OutPoint { txid, vout })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+    use bitcoin::Txid;
+
+    #[test]
+    fn test_funding_psbt() {
+        let fee_rate = FeeRate::from_sat_per_vb_unchecked(10);
+        let script = |byte| ScriptBuf::new_op_return(&[byte]);
+        let coin = |vout, sats| {
+            (
+                OutPoint::new(Txid::all_zeros(), vout),
+                TxOut {
+                    value: Amount::from_sat(sats),
+                    script_pubkey: script(vout as u8),
+                },
+            )
+        };
+        let funding_output = TxOut {
+            value: Amount::from_sat(150_000),
+            script_pubkey: script(100),
+        };
+        let coins = vec![coin(0, 20_000), coin(1, 100_000), coin(2, 80_000)];
+
+        // Needs the two largest coins, and the rest goes to change
+        let psbt =
+            create_funding_psbt(&coins, funding_output.clone(), script(200), fee_rate).unwrap();
+        let tx = &psbt.unsigned_tx;
+        assert_eq!(tx.input.len(), 2);
+        assert_eq!(tx.input[0].previous_output.vout, 1);
+        assert_eq!(tx.input[1].previous_output.vout, 2);
+        assert_eq!(psbt.inputs[1].witness_utxo, Some(coins[2].1.clone()));
+        assert_eq!(tx.output.len(), 2);
+        assert_eq!(find_output(tx, &script(100)), Some(0));
+        assert_eq!(find_output(tx, &script(200)), Some(1));
+        assert_eq!(find_output(tx, &script(0)), None);
+        let fee = Amount::from_sat(180_000) - funding_output.value - tx.output[1].value;
+        let mut signed = tx.clone();
+        for input in signed.input.iter_mut() {
+            input.witness = dummy_witness(&FUNDING_INPUT_WITNESS_SIZES, None);
+        }
+        assert!(fee >= fee_for(fee_rate, signed.weight()));
+
+        // Change below the dust limit is left to the fee
+        let two_inputs_fee = fee_for(
+            fee_rate,
+            estimate_weight(
+                vec![dummy_witness(&FUNDING_INPUT_WITNESS_SIZES, None); 2],
+                1,
+            ),
+        );
+        let exact = Amount::from_sat(180_000) - two_inputs_fee - Amount::from_sat(100);
+        let psbt = create_funding_psbt(
+            &coins,
+            TxOut {
+                value: exact,
+                ..funding_output.clone()
+            },
+            script(200),
+            fee_rate,
+        )
+        .unwrap();
+        assert_eq!(psbt.unsigned_tx.output.len(), 1);
+
+        assert!(create_funding_psbt(
+            &coins,
+            TxOut {
+                value: Amount::from_sat(200_000),
+                ..funding_output
+            },
+            script(200),
+            fee_rate
+        )
+        .is_none());
+    }
+}
@@ -1,37 +1,66 @@
 use std::error::Error;
 use std::str::FromStr;
-use std::{thread, time};
+use std::sync::Arc;
+use std::time;
 
-use bitcoin::secp256k1::{All, Secp256k1};
-use bitcoin::taproot::{TaprootBuilder, TaprootSpendInfo};
-use bitcoin::{Address, ScriptBuf, Transaction, Txid, XOnlyPublicKey};
+use bitcoin::absolute::{Height, LockTime};
+use bitcoin::secp256k1::{schnorr::Signature, All, Secp256k1};
+use bitcoin::sighash::SighashCache;
+use bitcoin::taproot::{ControlBlock, LeafVersion, NodeInfo, TaprootSpendInfo};
+use bitcoin::{
+    Address, OutPoint, ScriptBuf, TapSighash, Transaction, TxIn, TxOut, Txid, VarInt, Witness,
+    XOnlyPublicKey,
+};
 
 use bitcoin::blockdata::script::Builder;
 use bitcoin::opcodes::all::*;
 use bitcoincore_rpc::{Client, RpcApi};
+use rayon::prelude::*;
+use tracing::{info, trace};
 
-use crate::circuit::wire::{HashTuple, HashValue};
-use crate::traits::gate::GateTrait;
+use crate::circuit::winternitz::{WinternitzPublicKey, DIGIT_BITS, MAX_DIGIT};
+use crate::circuit::wire::{HashTuple, HashValue, PreimageValue, Wire};
+use crate::traits::bit_commitment::BitCommitment;
+use crate::traits::gate::{commitments_of, GateTrait, Wires, WrongOutputProof};
 
 use crate::circuit::Circuit;
+use crate::musig::KeyAggContext;
+use rpc::call_blocking;
+
+pub mod cache;
+pub mod cpfp;
+pub mod fees;
+pub mod funding;
+pub mod psbt;
+pub mod resources;
+pub mod rpc;
+
+// Blocks the other side has to answer in before the timelock leaves of the response second and
+// equivocation trees can be spent
+pub const TIMELOCK_BLOCKS: u32 = 10;
+
+pub fn unspendable_internal_key() -> XOnlyPublicKey {
+    XOnlyPublicKey::from_str("93c7378d96518a75448821c4f7c8f4bae7ce60f804d03d1f0628dd5dd0f5de51")
+        .unwrap()
+}
 
 pub fn taproot_address_from_script_leaves(
     secp: &Secp256k1<All>,
     scripts: Vec<ScriptBuf>,
 ) -> (Address, TaprootSpendInfo) {
-    let n = scripts.len();
-    assert!(n > 1, "more than one script is required");
-    let m: u8 = ((n - 1).ilog2() + 1) as u8; // m = ceil(log(n))
-    let k = 2_usize.pow(m.into()) - n;
-    let taproot = (0..n).fold(TaprootBuilder::new(), |acc, i| {
-        acc.add_leaf(m - ((i >= n - k) as u8), scripts[i].clone())
-            .unwrap()
-    });
-    let internal_key = XOnlyPublicKey::from_str(
-        "93c7378d96518a75448821c4f7c8f4bae7ce60f804d03d1f0628dd5dd0f5de51",
-    )
-    .unwrap();
-    let tree_info = taproot.finalize(secp, internal_key).unwrap();
+    taproot_address_from_script_leaves_with_internal_key(secp, unspendable_internal_key(), scripts)
+}
+
+// Used for outputs that the prover and the verifier may want to close cooperatively, where the
+// internal key is their aggregate key instead of the unspendable one.
+pub fn taproot_address_from_script_leaves_with_internal_key(
+    secp: &Secp256k1<All>,
+    internal_key: XOnlyPublicKey,
+    scripts: Vec<ScriptBuf>,
+) -> (Address, TaprootSpendInfo) {
+    assert!(!scripts.is_empty(), "at least one script is required");
+    let root = balanced_tree_root(scripts);
+    let tree_info = TaprootSpendInfo::from_node_info(secp, internal_key, root);
     let address = Address::p2tr(
         secp,
         internal_key,
@@ -41,11 +70,94 @@ pub fn taproot_address_from_script_leaves(
     (address, tree_info)
 }
 
-pub fn generate_response_address_and_info(
+// Same tree as adding the leaves to a TaprootBuilder one by one, the first 2n - 2^m leaves at
+// depth m = ceil(log(n)) and the rest at depth m - 1, but every level is hashed in parallel. A
+// single leaf is the root itself, e.g. in the challenge tree of a circuit in one chunk.
+fn balanced_tree_root(mut scripts: Vec<ScriptBuf>) -> NodeInfo {
+    let n = scripts.len();
+    if n == 1 {
+        return NodeInfo::new_leaf_with_ver(scripts.pop().unwrap(), LeafVersion::TapScript);
+    }
+    let m = (n - 1).ilog2() + 1; // m = ceil(log(n))
+    let deepest = 2 * n - 2_usize.pow(m);
+    let mut leaves = scripts
+        .into_par_iter()
+        .map(|script| NodeInfo::new_leaf_with_ver(script, LeafVersion::TapScript))
+        .collect::<Vec<NodeInfo>>();
+    let shallow_leaves = leaves.split_off(deepest);
+    let mut level = combine_pairs(leaves);
+    level.extend(shallow_leaves);
+    while level.len() > 1 {
+        level = combine_pairs(level);
+    }
+    level.pop().unwrap()
+}
+
+fn combine_pairs(nodes: Vec<NodeInfo>) -> Vec<NodeInfo> {
+    let mut nodes = nodes.into_iter();
+    let pairs = std::iter::from_fn(|| Some((nodes.next()?, nodes.next()?)))
+        .collect::<Vec<(NodeInfo, NodeInfo)>>();
+    pairs
+        .into_par_iter()
+        .map(|(a, b)| NodeInfo::combine(a, b).unwrap())
+        .collect()
+}
+
+// Builds a Huffman tree from the given (weight, script) pairs, so that likely spend paths end up
+// close to the root and get short control blocks. Also returns the expected size in bytes of the
+// leaf dependent part of the witness (script and control block), weighted by the leaf weights.
+pub fn taproot_address_from_weighted_script_leaves(
     secp: &Secp256k1<All>,
-    circuit: &Circuit,
+    internal_key: XOnlyPublicKey,
+    weighted_scripts: Vec<(u32, ScriptBuf)>,
+) -> (Address, TaprootSpendInfo, f64) {
+    assert!(
+        !weighted_scripts.is_empty(),
+        "at least one script is required"
+    );
+    let tree_info =
+        TaprootSpendInfo::with_huffman_tree(secp, internal_key, weighted_scripts.clone()).unwrap();
+    let address = Address::p2tr(
+        secp,
+        internal_key,
+        tree_info.merkle_root(),
+        bitcoin::Network::Regtest,
+    );
+    let expected_cost = expected_script_path_witness_cost(&tree_info, &weighted_scripts);
+    (address, tree_info, expected_cost)
+}
+
+pub fn expected_script_path_witness_cost(
+    tree_info: &TaprootSpendInfo,
+    weighted_scripts: &[(u32, ScriptBuf)],
+) -> f64 {
+    let total_weight = weighted_scripts
+        .iter()
+        .map(|(weight, _)| *weight as u64)
+        .sum::<u64>();
+    assert!(total_weight > 0, "total weight should be positive");
+    let weighted_cost = weighted_scripts
+        .iter()
+        .map(|(weight, script)| {
+            let control_block_size = tree_info
+                .control_block(&(script.clone(), LeafVersion::TapScript))
+                .expect("script should be in the tree")
+                .size();
+            let cost = VarInt(script.len() as u64).size()
+                + script.len()
+                + VarInt(control_block_size as u64).size()
+                + control_block_size;
+            *weight as u64 * cost as u64
+        })
+        .sum::<u64>();
+    weighted_cost as f64 / total_weight as f64
+}
+
+pub fn generate_response_address_and_info<C: BitCommitment>(
+    secp: &Secp256k1<All>,
+    circuit: &Circuit<C>,
     prover_pk: XOnlyPublicKey,
-    challenge_hashes: &Vec<HashValue>,
+    challenge_hashes: &[HashValue],
 ) -> (Address, TaprootSpendInfo) {
     assert_eq!(
         challenge_hashes.len(),
@@ -54,16 +166,16 @@ pub fn generate_response_address_and_info(
     );
     let scripts = circuit
         .gates
-        .iter()
-        .zip(challenge_hashes.iter())
+        .par_iter()
+        .zip(challenge_hashes.par_iter())
         .map(|(gate, hash)| generate_gate_response_script(gate, hash, prover_pk))
         .collect::<Vec<ScriptBuf>>();
     taproot_address_from_script_leaves(secp, scripts)
 }
 
 #[allow(clippy::borrowed_box)]
-pub fn generate_gate_response_script(
-    gate: &Box<dyn GateTrait + std::marker::Send>,
+pub fn generate_gate_response_script<C: BitCommitment>(
+    gate: &Box<dyn GateTrait<C> + std::marker::Send + std::marker::Sync>,
     challenge_hash: &HashValue,
     prover_pk: XOnlyPublicKey,
 ) -> ScriptBuf {
@@ -82,43 +194,128 @@ pub fn generate_response_second_address_and_info(
     prover_pk: XOnlyPublicKey,
     verifier_pk: XOnlyPublicKey,
 ) -> (Address, TaprootSpendInfo) {
-    taproot_address_from_script_leaves(
+    taproot_address_from_script_leaves_with_internal_key(
         secp,
+        cooperative_internal_key(secp, prover_pk, verifier_pk),
         vec![
-            generate_timelock_script(verifier_pk, 10),
+            generate_timelock_script(verifier_pk, TIMELOCK_BLOCKS),
             generate_2_of_2_script(prover_pk, verifier_pk),
         ],
     )
 }
 
-pub fn generate_equivoation_address_and_info(
+pub fn generate_equivoation_address_and_info<C: BitCommitment>(
     secp: &Secp256k1<All>,
-    circuit: &Circuit,
+    circuit: &Circuit<C>,
     prover_pk: XOnlyPublicKey,
     verifier_pk: XOnlyPublicKey,
 ) -> (Address, TaprootSpendInfo) {
-    // let mut reveal_challenge_scripts =
-    let mut scripts = circuit
-        .wires
-        .iter()
+    equivocation_address_and_info_for_wires(secp, circuit, &circuit.wires, prover_pk, verifier_pk)
+}
+
+// Only has anti-contradiction leaves for the wires some response reveals, which for a chunked
+// circuit are the ones crossing chunk boundaries. The tree then grows with the number of chunks
+// rather than the size of the circuit.
+pub fn generate_compact_equivocation_address_and_info<C: BitCommitment>(
+    secp: &Secp256k1<All>,
+    circuit: &Circuit<C>,
+    prover_pk: XOnlyPublicKey,
+    verifier_pk: XOnlyPublicKey,
+) -> (Address, TaprootSpendInfo) {
+    equivocation_address_and_info_for_wires(
+        secp,
+        circuit,
+        &circuit.get_revealed_wires(),
+        prover_pk,
+        verifier_pk,
+    )
+}
+
+fn equivocation_address_and_info_for_wires<C: BitCommitment>(
+    secp: &Secp256k1<All>,
+    circuit: &Circuit<C>,
+    wires: &Wires<C>,
+    prover_pk: XOnlyPublicKey,
+    verifier_pk: XOnlyPublicKey,
+) -> (Address, TaprootSpendInfo) {
+    // The 2-of-2 leaf is spent every round and the timelock leaf on every timeout, while a single
+    // anti-contradiction or wrong output leaf is only spent if the prover cheats on that particular
+    // wire or gate. Weighting the common leaves as heavily as all the others together keeps them
+    // near the root.
+    let num_fraud_leaves = (wires.len() + circuit.gates.len()) as u32;
+    let mut scripts = wires
+        .par_iter()
         .map(|wire_rcref| {
-            generate_anti_contradiction_script(
-                wire_rcref.lock().unwrap().get_hash_pair(),
-                verifier_pk,
+            (
+                1,
+                generate_anti_contradiction_script::<C>(
+                    &wire_rcref.lock().unwrap().commitment,
+                    verifier_pk,
+                ),
             )
         })
-        .collect::<Vec<ScriptBuf>>();
-    scripts.push(generate_timelock_script(prover_pk, 10));
-    scripts.push(generate_2_of_2_script(prover_pk, verifier_pk));
-    taproot_address_from_script_leaves(secp, scripts)
+        .collect::<Vec<(u32, ScriptBuf)>>();
+    scripts.par_extend(
+        circuit
+            .gates
+            .par_iter()
+            .map(|gate| (1, generate_wrong_output_script(gate, verifier_pk))),
+    );
+    scripts.push((
+        num_fraud_leaves,
+        generate_timelock_script(prover_pk, TIMELOCK_BLOCKS),
+    ));
+    scripts.push((
+        num_fraud_leaves,
+        generate_2_of_2_script(prover_pk, verifier_pk),
+    ));
+    let (address, tree_info, _) = taproot_address_from_weighted_script_leaves(
+        secp,
+        cooperative_internal_key(secp, prover_pk, verifier_pk),
+        scripts,
+    );
+    (address, tree_info)
+}
+
+pub fn cooperative_internal_key(
+    secp: &Secp256k1<All>,
+    prover_pk: XOnlyPublicKey,
+    verifier_pk: XOnlyPublicKey,
+) -> XOnlyPublicKey {
+    KeyAggContext::new(secp, &[prover_pk, verifier_pk], None).internal_key()
+}
+
+pub fn create_cooperative_close_tx(previous_output: OutPoint, payouts: Vec<TxOut>) -> Transaction {
+    Transaction {
+        version: bitcoin::transaction::Version::TWO,
+        lock_time: LockTime::from(Height::MIN),
+        input: vec![TxIn {
+            previous_output,
+            script_sig: ScriptBuf::new(),
+            sequence: bitcoin::transaction::Sequence::ENABLE_RBF_NO_LOCKTIME,
+            witness: Witness::new(),
+        }],
+        output: payouts,
+    }
 }
 
-pub fn generate_challenge_address_and_info(
+pub fn cooperative_close_sighash(
+    close_tx: &Transaction,
+    prevout: &TxOut,
+) -> Result<TapSighash, bitcoin::sighash::Error> {
+    SighashCache::new(close_tx).taproot_key_spend_signature_hash(
+        0,
+        &bitcoin::sighash::Prevouts::All(&[prevout]),
+        bitcoin::sighash::TapSighashType::Default,
+    )
+}
+
+pub fn generate_challenge_address_and_info<C: BitCommitment>(
     secp: &Secp256k1<All>,
-    circuit: &Circuit,
+    circuit: &Circuit<C>,
     prover_pk: XOnlyPublicKey,
     verifier_pk: XOnlyPublicKey,
-    challenge_hashes: &Vec<HashValue>,
+    challenge_hashes: &[HashValue],
 ) -> (Address, TaprootSpendInfo) {
     assert_eq!(
         challenge_hashes.len(),
@@ -126,23 +323,60 @@ pub fn generate_challenge_address_and_info(
         "wrong number of challenge hashes"
     );
     let scripts = challenge_hashes
-        .iter()
+        .par_iter()
         .map(|x| generate_challenge_script(prover_pk, verifier_pk, x))
         .collect::<Vec<ScriptBuf>>();
     taproot_address_from_script_leaves(secp, scripts)
 }
 
-pub fn generate_anti_contradiction_script(
-    wire_bit_hashes: HashTuple,
+pub fn generate_anti_contradiction_script<C: BitCommitment>(
+    wire_commitment: &C::Public,
     verifier_pk: XOnlyPublicKey,
 ) -> ScriptBuf {
-    Builder::new()
-        .push_opcode(OP_SHA256)
-        .push_slice(wire_bit_hashes.zero)
-        .push_opcode(OP_EQUALVERIFY)
-        .push_opcode(OP_SHA256)
-        .push_slice(wire_bit_hashes.one)
-        .push_opcode(OP_EQUALVERIFY)
+    C::add_equivocation_script(wire_commitment, Builder::new())
+        .push_x_only_key(&verifier_pk)
+        .push_opcode(OP_CHECKSIG)
+        .into_script()
+}
+
+// Lets the verifier claim the funds with reveals of all the wires of a gate whose outputs don't
+// match the gate evaluated on its inputs. Takes the reveals in the same order as the response
+// script, and checks that at least one computed output differs from the committed one.
+#[allow(clippy::borrowed_box)]
+pub fn generate_wrong_output_script<C: BitCommitment>(
+    gate: &Box<dyn GateTrait<C> + std::marker::Send + std::marker::Sync>,
+    verifier_pk: XOnlyPublicKey,
+) -> ScriptBuf {
+    let (input_wires, output_wires) = gate.get_wires();
+    let (input_commitments, output_commitments) =
+        (commitments_of(input_wires), commitments_of(output_wires));
+    let mut builder = Builder::new();
+    for commitment in output_commitments.iter().rev() {
+        builder = C::add_commitment_script(commitment, builder).push_opcode(OP_TOALTSTACK);
+    }
+    for commitment in input_commitments.iter().rev() {
+        builder = C::add_commitment_script(commitment, builder).push_opcode(OP_TOALTSTACK);
+    }
+    for _ in 0..input_commitments.len() {
+        builder = builder.push_opcode(OP_FROMALTSTACK);
+    }
+    builder = gate.add_compute_script(builder);
+    // Same order as in the chunk response script, but with the mismatches or'ed together on top
+    let num_outputs = output_commitments.len();
+    for i in 0..num_outputs {
+        let depth = num_outputs - 1 - i + usize::from(i > 0);
+        if depth > 0 {
+            builder = builder.push_int(depth as i64).push_opcode(OP_ROLL);
+        }
+        builder = builder
+            .push_opcode(OP_FROMALTSTACK)
+            .push_opcode(OP_NUMNOTEQUAL);
+        if i > 0 {
+            builder = builder.push_opcode(OP_BOOLOR);
+        }
+    }
+    builder
+        .push_opcode(OP_VERIFY)
         .push_x_only_key(&verifier_pk)
         .push_opcode(OP_CHECKSIG)
         .into_script()
@@ -162,6 +396,90 @@ pub fn add_bit_commitment_script(wire_bit_hashes: HashTuple, builder: Builder) -
         .push_opcode(OP_VERIFY)
 }
 
+// Checks a Winternitz signature given as (hash, digit) pairs with the first digit on top, and
+// leaves the message digits on the stack, least significant digit on top.
+pub fn add_winternitz_commitment_script(
+    public_key: &WinternitzPublicKey,
+    builder: Builder,
+) -> Builder {
+    let params = public_key.params;
+    let mut builder = builder;
+    for digit_hash in public_key.hashes.iter() {
+        builder = builder
+            .push_int(MAX_DIGIT as i64)
+            .push_opcode(OP_MIN)
+            .push_opcode(OP_DUP)
+            .push_opcode(OP_TOALTSTACK)
+            .push_opcode(OP_TOALTSTACK);
+        for _ in 0..MAX_DIGIT {
+            builder = builder.push_opcode(OP_DUP).push_opcode(OP_HASH160);
+        }
+        builder = builder
+            .push_opcode(OP_FROMALTSTACK)
+            .push_opcode(OP_PICK)
+            .push_slice(digit_hash)
+            .push_opcode(OP_EQUALVERIFY);
+        // Drop the MAX_DIGIT + 1 hashes of the chain
+        for _ in 0..=MAX_DIGIT / 2 {
+            builder = builder.push_opcode(OP_2DROP);
+        }
+    }
+    // The checksum digits come out of the altstack first, most significant first
+    builder = builder.push_opcode(OP_FROMALTSTACK);
+    for _ in 1..params.checksum_digits() {
+        for _ in 0..DIGIT_BITS {
+            builder = builder.push_opcode(OP_DUP).push_opcode(OP_ADD);
+        }
+        builder = builder.push_opcode(OP_FROMALTSTACK).push_opcode(OP_ADD);
+    }
+    // Adding up the message digits on top of the checksum has to give the maximum checksum
+    for i in 0..params.message_digits() {
+        builder = builder.push_opcode(OP_FROMALTSTACK);
+        // The most significant digit comes first, and when it only holds a few message bits the
+        // signer must not be able to claim a larger value
+        let top_bits = params.message_bits % DIGIT_BITS;
+        if i == 0 && top_bits != 0 {
+            builder = builder
+                .push_opcode(OP_DUP)
+                .push_int(1 << top_bits)
+                .push_opcode(OP_LESSTHAN)
+                .push_opcode(OP_VERIFY);
+        }
+        builder = builder.push_opcode(OP_TUCK).push_opcode(OP_ADD);
+    }
+    builder
+        .push_int(params.max_checksum() as i64)
+        .push_opcode(OP_EQUALVERIFY)
+}
+
+// Consumes two signatures for the same key, the first one on top, and fails unless both are valid
+// and sign different messages.
+pub fn add_winternitz_equivocation_script(
+    public_key: &WinternitzPublicKey,
+    builder: Builder,
+) -> Builder {
+    let message_digits = public_key.params.message_digits();
+    let mut builder = add_winternitz_commitment_script(public_key, builder);
+    // Reverse the first message so that its least significant digit comes out of the altstack first
+    for i in 1..message_digits {
+        builder = builder.push_int(i as i64).push_opcode(OP_ROLL);
+    }
+    for _ in 0..message_digits {
+        builder = builder.push_opcode(OP_TOALTSTACK);
+    }
+    builder = add_winternitz_commitment_script(public_key, builder)
+        .push_opcode(OP_FROMALTSTACK)
+        .push_opcode(OP_NUMNOTEQUAL);
+    for _ in 1..message_digits {
+        builder = builder
+            .push_opcode(OP_SWAP)
+            .push_opcode(OP_FROMALTSTACK)
+            .push_opcode(OP_NUMNOTEQUAL)
+            .push_opcode(OP_BOOLOR);
+    }
+    builder.push_opcode(OP_VERIFY)
+}
+
 pub fn generate_challenge_script(
     _prover_pk: XOnlyPublicKey,
     verifier_pk: XOnlyPublicKey,
@@ -189,30 +507,757 @@ pub fn generate_timelock_script(actor_pk: XOnlyPublicKey, block_count: u32) -> S
     Builder::new()
         .push_int(block_count as i64)
         .push_opcode(OP_CSV)
+        .push_opcode(OP_DROP)
         .push_x_only_key(&actor_pk)
         .push_opcode(OP_CHECKSIG)
         .into_script()
 }
 
-pub fn watch_transaction(
-    rpc: &Client,
+// Script path witnesses for each kind of leaf, with the stack items in the order the leaf
+// consumes them followed by the script and the control block.
+fn script_path_witness(
+    stack: Vec<Vec<u8>>,
+    script: &ScriptBuf,
+    control_block: &ControlBlock,
+) -> Witness {
+    let mut witness = Witness::from_slice(&stack);
+    witness.push(script.as_bytes());
+    witness.push(control_block.serialize());
+    witness
+}
+
+pub fn generate_challenge_witness(
+    verifier_sig: &Signature,
+    challenge_preimage: &PreimageValue,
+    challenge_script: &ScriptBuf,
+    control_block: &ControlBlock,
+) -> Witness {
+    script_path_witness(
+        vec![verifier_sig.as_ref().to_vec(), challenge_preimage.to_vec()],
+        challenge_script,
+        control_block,
+    )
+}
+
+// The prover's key is checked first, so its signature goes on top
+pub fn generate_2_of_2_witness(
+    prover_sig: &Signature,
+    verifier_sig: &Signature,
+    script: &ScriptBuf,
+    control_block: &ControlBlock,
+) -> Witness {
+    script_path_witness(
+        vec![verifier_sig.as_ref().to_vec(), prover_sig.as_ref().to_vec()],
+        script,
+        control_block,
+    )
+}
+
+pub fn generate_timelock_witness(
+    sig: &Signature,
+    script: &ScriptBuf,
+    control_block: &ControlBlock,
+) -> Witness {
+    script_path_witness(vec![sig.as_ref().to_vec()], script, control_block)
+}
+
+#[allow(clippy::borrowed_box)]
+pub fn generate_gate_response_witness<C: BitCommitment>(
+    prover_sig: &Signature,
+    gate: &mut Box<dyn GateTrait<C> + std::marker::Send + std::marker::Sync>,
+    challenge_preimage: PreimageValue,
+    response_script: &ScriptBuf,
+    control_block: &ControlBlock,
+) -> Witness {
+    let mut stack = vec![prover_sig.as_ref().to_vec()];
+    stack.extend(gate.create_response_witness(challenge_preimage));
+    script_path_witness(stack, response_script, control_block)
+}
+
+// Only possible once both values of the wire have been revealed
+pub fn generate_anti_contradiction_witness<C: BitCommitment>(
+    verifier_sig: &Signature,
+    wire: &Wire<C>,
+    equivocation_script: &ScriptBuf,
+    control_block: &ControlBlock,
+) -> Option<Witness> {
+    let mut stack = vec![verifier_sig.as_ref().to_vec()];
+    stack.extend(wire.get_equivocation_witness()?);
+    Some(script_path_witness(
+        stack,
+        equivocation_script,
+        control_block,
+    ))
+}
+
+pub fn generate_wrong_output_witness<C: BitCommitment>(
+    verifier_sig: &Signature,
+    proof: &WrongOutputProof<C>,
+    wrong_output_script: &ScriptBuf,
+    control_block: &ControlBlock,
+) -> Witness {
+    let mut stack = vec![verifier_sig.as_ref().to_vec()];
+    for reveal in proof.input_reveals.iter().chain(&proof.output_reveals) {
+        stack.extend(C::reveal_to_witness(reveal));
+    }
+    script_path_witness(stack, wrong_output_script, control_block)
+}
+
+// Checks that the transaction would be relayed and sends it, kind is the role of the transaction in
+// the game, e.g. "kickoff" or "slash"
+pub async fn broadcast_transaction(rpc: &Arc<Client>, tx: &Transaction, kind: &str) -> Txid {
+    let violations = resources::check_transaction(tx);
+    assert!(
+        violations.is_empty(),
+        "{} transaction would not be relayed: {:?}",
+        kind,
+        violations
+    );
+    let tx = tx.clone();
+    let txid = call_blocking(rpc, move |rpc| {
+        rpc.send_raw_transaction(&tx).map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap_or_else(|e| panic!("Failed to send the {} transaction: {}", kind, e));
+    info!(kind, %txid, "broadcast transaction");
+    txid
+}
+
+pub async fn watch_transaction(
+    rpc: &Arc<Client>,
     txid: &Txid,
     interval: time::Duration,
 ) -> Result<Transaction, Box<dyn Error>> {
     loop {
         // wait for block to be mined
-        match rpc.get_raw_transaction(
-            txid,
-            Some(
-                &rpc.get_block_hash(rpc.get_block_count().unwrap() - 1)
-                    .unwrap(),
-            ),
-        ) {
-            Ok(tx) => return Ok(tx),
-            Err(_e) => {
-                // println!("Transaction {:?} not found yet: {}", txid, e);
-                thread::sleep(interval);
+        let lookup = *txid;
+        let found = call_blocking(rpc, move |rpc| {
+            rpc.get_raw_transaction(
+                &lookup,
+                Some(
+                    &rpc.get_block_hash(rpc.get_block_count().unwrap() - 1)
+                        .unwrap(),
+                ),
+            )
+        })
+        .await;
+        match found {
+            Ok(tx) => {
+                info!(%txid, "detected spend");
+                return Ok(tx);
+            }
+            Err(e) => {
+                trace!(%txid, error = %e, "transaction not found yet");
+                tokio::time::sleep(interval).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::chunk::ChunkLimits;
+    use crate::circuit::winternitz::{WinternitzCommitment, WinternitzParams, WinternitzSecretKey};
+    use crate::circuit::wire::HashCommitment;
+    use crate::test_utils::{run_script, run_spend};
+    use crate::utils::number_to_bool_array;
+    use bitcoin::hashes::{hash160, sha256, Hash};
+    use bitcoin::secp256k1::rand::{self, Rng};
+    use bitcoin::secp256k1::{Keypair, Message};
+    use bitcoin::sighash::{Prevouts, TapSighashType};
+    use bitcoin::taproot::TaprootBuilder;
+    use bitcoin::transaction::Sequence;
+    use bitcoin::{Amount, TapLeafHash, TapNodeHash};
+
+    fn random_pk(secp: &Secp256k1<All>) -> XOnlyPublicKey {
+        secp.generate_keypair(&mut rand::thread_rng())
+            .1
+            .x_only_public_key()
+            .0
+    }
+
+    #[test]
+    fn test_winternitz_commitment_script() {
+        let secret_key = WinternitzSecretKey::new(WinternitzParams::new(32));
+        let public_key = secret_key.public_key();
+        let message = number_to_bool_array(0x1234abcd, 32);
+        let signature = secret_key.sign(&message);
+        let script = add_winternitz_commitment_script(&public_key, Builder::new())
+            .push_int(0xd)
+            .push_opcode(OP_EQUALVERIFY)
+            .push_int(0xc)
+            .push_opcode(OP_EQUALVERIFY)
+            .push_opcode(OP_2DROP)
+            .push_opcode(OP_2DROP)
+            .push_opcode(OP_2DROP)
+            .push_int(1)
+            .into_script();
+        let res = run_script(&script, signature.to_witness());
+        assert_eq!(res.error, None);
+        assert!(res.success);
+
+        // Claiming a larger digit than the one that was signed is possible for the message
+        // digits, but then the checksum no longer adds up
+        let mut forged = signature.clone();
+        forged.digits[0] += 1;
+        forged.hashes[0] = hash160::Hash::hash(&forged.hashes[0]).to_byte_array();
+        let res = run_script(&script, forged.to_witness());
+        assert!(res.error.is_some());
+    }
+
+    #[test]
+    fn test_winternitz_commitment_script_bounds_top_digit() {
+        // A 10 bit value leaves 2 bits to its top digit. A 12 bit key has as many digits, so its
+        // holder could otherwise sign a value of up to 12 bits for the same public key.
+        let secret_key = WinternitzSecretKey::new(WinternitzParams::new(12));
+        let public_key = WinternitzPublicKey {
+            params: WinternitzParams::new(10),
+            hashes: secret_key.public_key().hashes,
+        };
+        let script = add_winternitz_commitment_script(&public_key, Builder::new())
+            .push_opcode(OP_2DROP)
+            .push_opcode(OP_DROP)
+            .push_int(1)
+            .into_script();
+
+        let in_range = secret_key.sign(&number_to_bool_array(0x3ff, 12));
+        let res = run_script(&script, in_range.to_witness());
+        assert_eq!(res.error, None);
+        assert!(res.success);
+
+        let out_of_range = secret_key.sign(&number_to_bool_array(0x400, 12));
+        let res = run_script(&script, out_of_range.to_witness());
+        assert!(res.error.is_some());
+    }
+
+    #[test]
+    fn test_winternitz_anti_contradiction_script() {
+        let secp = Secp256k1::new();
+        let secret_key = WinternitzSecretKey::new(WinternitzParams::new(32));
+        let public_key = secret_key.public_key();
+        let script = generate_anti_contradiction_script::<WinternitzCommitment>(
+            &public_key,
+            random_pk(&secp),
+        );
+        let first = secret_key.sign(&number_to_bool_array(1000, 32));
+        let second = secret_key.sign(&number_to_bool_array(1001, 32));
+
+        // Without a signature the final CHECKSIG fails, but everything before it has to pass
+        let mut witness = vec![vec![]];
+        witness.extend(second.to_witness());
+        witness.extend(first.to_witness());
+        let res = run_script(&script, witness);
+        assert_eq!(res.error, None);
+
+        let mut witness = vec![vec![]];
+        witness.extend(first.to_witness());
+        witness.extend(first.to_witness());
+        let res = run_script(&script, witness);
+        assert!(res.error.is_some());
+    }
+
+    #[test]
+    fn test_balanced_tree_matches_taproot_builder() {
+        let secp = Secp256k1::new();
+        let pk = random_pk(&secp);
+        for n in [2, 3, 5, 8, 13, 64, 100] {
+            let scripts = (0..n)
+                .map(|i| generate_timelock_script(pk, i + 1))
+                .collect::<Vec<ScriptBuf>>();
+            let m: u8 = ((n - 1).ilog2() + 1) as u8;
+            let k = 2_u32.pow(m.into()) - n;
+            let builder = (0..n).fold(TaprootBuilder::new(), |acc, i| {
+                acc.add_leaf(m - ((i >= n - k) as u8), scripts[i as usize].clone())
+                    .unwrap()
+            });
+            let expected = builder.finalize(&secp, unspendable_internal_key()).unwrap();
+            let (_, tree_info) = taproot_address_from_script_leaves(&secp, scripts);
+            assert_eq!(tree_info.merkle_root(), expected.merkle_root());
+        }
+    }
+
+    #[test]
+    fn test_weighted_tree_shortens_heavy_leaves() {
+        let secp = Secp256k1::new();
+        let pk = random_pk(&secp);
+        let light_scripts = (0..62)
+            .map(|i| generate_timelock_script(pk, i + 20))
+            .collect::<Vec<ScriptBuf>>();
+        let heavy_scripts = vec![
+            generate_timelock_script(pk, 1),
+            generate_2_of_2_script(pk, pk),
+        ];
+        let mut weighted_scripts = light_scripts
+            .iter()
+            .map(|script| (1, script.clone()))
+            .collect::<Vec<(u32, ScriptBuf)>>();
+        weighted_scripts.extend(heavy_scripts.iter().map(|script| (62, script.clone())));
+
+        let (_, weighted_info, weighted_cost) = taproot_address_from_weighted_script_leaves(
+            &secp,
+            unspendable_internal_key(),
+            weighted_scripts.clone(),
+        );
+        let mut all_scripts = light_scripts.clone();
+        all_scripts.extend(heavy_scripts.clone());
+        let (_, balanced_info) = taproot_address_from_script_leaves(&secp, all_scripts);
+
+        for script in heavy_scripts.iter() {
+            let leaf = (script.clone(), LeafVersion::TapScript);
+            assert!(
+                weighted_info.control_block(&leaf).unwrap().size()
+                    < balanced_info.control_block(&leaf).unwrap().size()
+            );
+        }
+        assert!(
+            weighted_cost < expected_script_path_witness_cost(&balanced_info, &weighted_scripts)
+        );
+    }
+
+    // A transaction spending a single output of the given address, used to sign and run script
+    // path spends of its leaves the same way a node would
+    struct LeafSpend {
+        tx: Transaction,
+        prevout: TxOut,
+    }
+
+    impl LeafSpend {
+        fn new(address: &Address, sequence: Sequence) -> Self {
+            let prevout = TxOut {
+                value: Amount::from_sat(100_000),
+                script_pubkey: address.script_pubkey(),
+            };
+            let tx = Transaction {
+                version: bitcoin::transaction::Version::TWO,
+                lock_time: LockTime::ZERO,
+                input: vec![TxIn {
+                    previous_output: OutPoint::null(),
+                    script_sig: ScriptBuf::new(),
+                    sequence,
+                    witness: Witness::new(),
+                }],
+                output: vec![TxOut {
+                    value: Amount::from_sat(99_000),
+                    script_pubkey: address.script_pubkey(),
+                }],
+            };
+            LeafSpend { tx, prevout }
+        }
+
+        fn sign(&self, keypair: &Keypair, script: &ScriptBuf) -> Signature {
+            let sighash = SighashCache::new(&self.tx)
+                .taproot_script_spend_signature_hash(
+                    0,
+                    &Prevouts::All(&[&self.prevout]),
+                    TapLeafHash::from_script(script, LeafVersion::TapScript),
+                    TapSighashType::Default,
+                )
+                .unwrap();
+            Secp256k1::new()
+                .sign_schnorr_no_aux_rand(&Message::from_digest(sighash.to_byte_array()), keypair)
+        }
+
+        // Checks the control block against the output key, then runs the leaf script on the
+        // witness stack. Valid spends also have to pass the resource checker.
+        fn execute(&mut self, tree_info: &TaprootSpendInfo, witness: Witness) -> bool {
+            let elements = witness.to_vec();
+            let n = elements.len();
+            let script = ScriptBuf::from_bytes(elements[n - 2].clone());
+            let control_block = ControlBlock::decode(&elements[n - 1]).unwrap();
+            if !control_block.verify_taproot_commitment(
+                &Secp256k1::new(),
+                tree_info.output_key().to_inner(),
+                &script,
+            ) {
+                return false;
+            }
+            self.tx.input[0].witness = witness;
+            let res = run_spend(
+                &self.tx,
+                vec![self.prevout.clone()],
+                script,
+                elements[..n - 2].to_vec(),
+            );
+            res.success && resources::check_transaction(&self.tx).is_empty()
+        }
+    }
+
+    fn control_block_of(tree_info: &TaprootSpendInfo, script: &ScriptBuf) -> ControlBlock {
+        tree_info
+            .control_block(&(script.clone(), LeafVersion::TapScript))
+            .unwrap()
+    }
+
+    fn random_challenges(num_gates: usize) -> (Vec<PreimageValue>, Vec<HashValue>) {
+        let preimages = (0..num_gates)
+            .map(|_| rand::thread_rng().gen())
+            .collect::<Vec<PreimageValue>>();
+        let hashes = preimages
+            .iter()
+            .map(|preimage| sha256::Hash::hash(preimage).to_byte_array())
+            .collect();
+        (preimages, hashes)
+    }
+
+    #[test]
+    fn test_challenge_leaf_spend() {
+        let secp = Secp256k1::new();
+        let prover = Keypair::new(&secp, &mut rand::thread_rng());
+        let verifier = Keypair::new(&secp, &mut rand::thread_rng());
+        let (prover_pk, verifier_pk) =
+            (prover.x_only_public_key().0, verifier.x_only_public_key().0);
+        let circuit: Circuit = Circuit::default();
+        let (preimages, hashes) = random_challenges(circuit.num_gates());
+        let (address, tree_info) =
+            generate_challenge_address_and_info(&secp, &circuit, prover_pk, verifier_pk, &hashes);
+        let script = generate_challenge_script(prover_pk, verifier_pk, &hashes[1]);
+        let control_block = control_block_of(&tree_info, &script);
+
+        let mut spend = LeafSpend::new(&address, Sequence::ENABLE_RBF_NO_LOCKTIME);
+        let sig = spend.sign(&verifier, &script);
+        let witness = generate_challenge_witness(&sig, &preimages[1], &script, &control_block);
+        assert!(spend.execute(&tree_info, witness));
+
+        let witness = generate_challenge_witness(&sig, &preimages[0], &script, &control_block);
+        assert!(!spend.execute(&tree_info, witness));
+        let prover_sig = spend.sign(&prover, &script);
+        let witness =
+            generate_challenge_witness(&prover_sig, &preimages[1], &script, &control_block);
+        assert!(!spend.execute(&tree_info, witness));
+        let other_script = generate_challenge_script(prover_pk, verifier_pk, &hashes[0]);
+        let witness = generate_challenge_witness(
+            &sig,
+            &preimages[1],
+            &script,
+            &control_block_of(&tree_info, &other_script),
+        );
+        assert!(!spend.execute(&tree_info, witness));
+    }
+
+    #[test]
+    fn test_one_chunk_trees() {
+        let secp = Secp256k1::new();
+        let prover = Keypair::new(&secp, &mut rand::thread_rng());
+        let verifier = Keypair::new(&secp, &mut rand::thread_rng());
+        let (prover_pk, verifier_pk) =
+            (prover.x_only_public_key().0, verifier.x_only_public_key().0);
+        let circuit: Circuit = Circuit::default().into_chunks(&ChunkLimits::default());
+        assert_eq!(circuit.num_gates(), 1);
+        let (preimages, hashes) = random_challenges(circuit.num_gates());
+
+        // Each tree is a single leaf, spent with a control block of just the internal key
+        let (address, tree_info) =
+            generate_challenge_address_and_info(&secp, &circuit, prover_pk, verifier_pk, &hashes);
+        let script = generate_challenge_script(prover_pk, verifier_pk, &hashes[0]);
+        let control_block = control_block_of(&tree_info, &script);
+        assert!(control_block.merkle_branch.is_empty());
+        let mut spend = LeafSpend::new(&address, Sequence::ENABLE_RBF_NO_LOCKTIME);
+        let sig = spend.sign(&verifier, &script);
+        let witness = generate_challenge_witness(&sig, &preimages[0], &script, &control_block);
+        assert!(spend.execute(&tree_info, witness));
+
+        let (_, tree_info) =
+            generate_response_address_and_info(&secp, &circuit, prover_pk, &hashes);
+        assert_eq!(
+            tree_info.merkle_root(),
+            Some(TapNodeHash::from(TapLeafHash::from_script(
+                &generate_gate_response_script(&circuit.gates[0], &hashes[0], prover_pk),
+                LeafVersion::TapScript
+            )))
+        );
+    }
+
+    #[test]
+    fn test_2_of_2_leaf_spend() {
+        let secp = Secp256k1::new();
+        let prover = Keypair::new(&secp, &mut rand::thread_rng());
+        let verifier = Keypair::new(&secp, &mut rand::thread_rng());
+        let (prover_pk, verifier_pk) =
+            (prover.x_only_public_key().0, verifier.x_only_public_key().0);
+        let (address, tree_info) =
+            generate_response_second_address_and_info(&secp, prover_pk, verifier_pk);
+        let script = generate_2_of_2_script(prover_pk, verifier_pk);
+        let control_block = control_block_of(&tree_info, &script);
+
+        let mut spend = LeafSpend::new(&address, Sequence::ENABLE_RBF_NO_LOCKTIME);
+        let prover_sig = spend.sign(&prover, &script);
+        let verifier_sig = spend.sign(&verifier, &script);
+        let witness = generate_2_of_2_witness(&prover_sig, &verifier_sig, &script, &control_block);
+        assert!(spend.execute(&tree_info, witness));
+
+        let witness = generate_2_of_2_witness(&verifier_sig, &prover_sig, &script, &control_block);
+        assert!(!spend.execute(&tree_info, witness));
+        let witness = generate_2_of_2_witness(&prover_sig, &prover_sig, &script, &control_block);
+        assert!(!spend.execute(&tree_info, witness));
+    }
+
+    #[test]
+    fn test_timelock_leaf_spend() {
+        let secp = Secp256k1::new();
+        let prover = Keypair::new(&secp, &mut rand::thread_rng());
+        let verifier = Keypair::new(&secp, &mut rand::thread_rng());
+        let (prover_pk, verifier_pk) =
+            (prover.x_only_public_key().0, verifier.x_only_public_key().0);
+        let (address, tree_info) =
+            generate_response_second_address_and_info(&secp, prover_pk, verifier_pk);
+        let script = generate_timelock_script(verifier_pk, TIMELOCK_BLOCKS);
+        let control_block = control_block_of(&tree_info, &script);
+
+        let mut spend = LeafSpend::new(&address, Sequence::from_height(10));
+        let sig = spend.sign(&verifier, &script);
+        let witness = generate_timelock_witness(&sig, &script, &control_block);
+        assert!(spend.execute(&tree_info, witness));
+
+        let prover_sig = spend.sign(&prover, &script);
+        let witness = generate_timelock_witness(&prover_sig, &script, &control_block);
+        assert!(!spend.execute(&tree_info, witness));
+
+        let mut early_spend = LeafSpend::new(&address, Sequence::from_height(9));
+        let sig = early_spend.sign(&verifier, &script);
+        let witness = generate_timelock_witness(&sig, &script, &control_block);
+        assert!(!early_spend.execute(&tree_info, witness));
+    }
+
+    fn check_anti_contradiction_leaf_spend<C: BitCommitment>() {
+        let secp = Secp256k1::new();
+        let prover = Keypair::new(&secp, &mut rand::thread_rng());
+        let verifier = Keypair::new(&secp, &mut rand::thread_rng());
+        let (prover_pk, verifier_pk) =
+            (prover.x_only_public_key().0, verifier.x_only_public_key().0);
+        let circuit: Circuit<C> = Circuit::default();
+        let (address, tree_info) =
+            generate_equivoation_address_and_info(&secp, &circuit, prover_pk, verifier_pk);
+        let mut prover_wire = circuit.wires[2].lock().unwrap().clone();
+        let mut verifier_wire: Wire<C> = Wire::new_with_commitment(2, prover_wire.get_commitment());
+        let script =
+            generate_anti_contradiction_script::<C>(&verifier_wire.commitment, verifier_pk);
+        let control_block = control_block_of(&tree_info, &script);
+
+        let mut spend = LeafSpend::new(&address, Sequence::ENABLE_RBF_NO_LOCKTIME);
+        let sig = spend.sign(&verifier, &script);
+        prover_wire.selector = Some(false);
+        let zero = prover_wire.get_reveal_of_selector();
+        verifier_wire.add_reveal(zero.clone()).unwrap();
+        assert!(
+            generate_anti_contradiction_witness(&sig, &verifier_wire, &script, &control_block)
+                .is_none()
+        );
+
+        // Revealing the same value twice is not an equivocation
+        let mut stack = vec![sig.as_ref().to_vec()];
+        stack.extend(C::equivocation_witness(&zero, &zero));
+        let witness = script_path_witness(stack, &script, &control_block);
+        assert!(!spend.execute(&tree_info, witness));
+
+        prover_wire.selector = Some(true);
+        verifier_wire
+            .add_reveal(prover_wire.get_reveal_of_selector())
+            .unwrap();
+        let witness =
+            generate_anti_contradiction_witness(&sig, &verifier_wire, &script, &control_block)
+                .unwrap();
+        assert!(spend.execute(&tree_info, witness));
+
+        let prover_sig = spend.sign(&prover, &script);
+        let witness = generate_anti_contradiction_witness(
+            &prover_sig,
+            &verifier_wire,
+            &script,
+            &control_block,
+        )
+        .unwrap();
+        assert!(!spend.execute(&tree_info, witness));
+    }
+
+    // Reveals every wire of the challenged gate as the prover evaluated it, except that the first
+    // output is flipped if lie is set, and tries to claim the funds with the wrong output leaf
+    fn check_wrong_output_leaf_spend<C: BitCommitment>(
+        mut prover_circuit: Circuit<C>,
+        mut verifier_circuit: Circuit<C>,
+        inputs: Vec<Vec<bool>>,
+        gate_num: usize,
+        lie: bool,
+    ) -> bool {
+        let secp = Secp256k1::new();
+        let prover = Keypair::new(&secp, &mut rand::thread_rng());
+        let verifier = Keypair::new(&secp, &mut rand::thread_rng());
+        let (prover_pk, verifier_pk) =
+            (prover.x_only_public_key().0, verifier.x_only_public_key().0);
+        let (address, tree_info) =
+            generate_equivoation_address_and_info(&secp, &verifier_circuit, prover_pk, verifier_pk);
+        let script = generate_wrong_output_script(&verifier_circuit.gates[gate_num], verifier_pk);
+        let control_block = control_block_of(&tree_info, &script);
+
+        prover_circuit.evaluate(inputs);
+        let prover_gate = &mut prover_circuit.gates[gate_num];
+        if lie {
+            let mut output = prover_gate.get_output_bits();
+            output[0] = !output[0];
+            prover_gate.set_output_bits(output);
+        }
+        let mut response = prover_gate.create_response_witness([0; 32]);
+        response.pop();
+        let verifier_gate = &mut verifier_circuit.gates[gate_num];
+        verifier_gate.add_preimages_from_witness(&response).unwrap();
+
+        let mut spend = LeafSpend::new(&address, Sequence::ENABLE_RBF_NO_LOCKTIME);
+        let sig = spend.sign(&verifier, &script);
+        let proof = match verifier_gate.find_wrong_output() {
+            Some(proof) => {
+                assert!(lie);
+                proof
+            }
+            None => {
+                assert!(!lie);
+                // Try to claim with the honest reveals anyway
+                let mut reveals = response
+                    .chunks(C::REVEAL_WITNESS_LEN)
+                    .map(|chunk| C::reveal_from_witness(chunk).unwrap())
+                    .collect::<Vec<C::Reveal>>();
+                let output_reveals = reveals.split_off(verifier_gate.get_input_size());
+                WrongOutputProof {
+                    input_reveals: reveals,
+                    output_reveals,
+                }
+            }
+        };
+        let witness = generate_wrong_output_witness(&sig, &proof, &script, &control_block);
+        spend.execute(&tree_info, witness)
+    }
+
+    fn circuit_pair<C: BitCommitment>(file: &str) -> (Circuit<C>, Circuit<C>) {
+        let prover_circuit: Circuit<C> = Circuit::from_bristol(file, None);
+        let verifier_circuit =
+            Circuit::from_bristol(file, Some(prover_circuit.get_wire_commitments()));
+        (prover_circuit, verifier_circuit)
+    }
+
+    #[test]
+    fn test_wrong_output_leaf_spend() {
+        for lie in [false, true] {
+            for gate_num in 0..2 {
+                let (prover_circuit, verifier_circuit) =
+                    circuit_pair::<HashCommitment>("bristol/test.txt");
+                let inputs = vec![vec![true], vec![true, false]];
+                assert_eq!(
+                    check_wrong_output_leaf_spend(
+                        prover_circuit,
+                        verifier_circuit,
+                        inputs.clone(),
+                        gate_num,
+                        lie
+                    ),
+                    lie
+                );
+                let (prover_circuit, verifier_circuit) =
+                    circuit_pair::<WinternitzCommitment>("bristol/test.txt");
+                assert_eq!(
+                    check_wrong_output_leaf_spend(
+                        prover_circuit,
+                        verifier_circuit,
+                        inputs,
+                        gate_num,
+                        lie
+                    ),
+                    lie
+                );
             }
         }
     }
+
+    #[test]
+    fn test_wrong_output_leaf_spend_for_chunk() {
+        for lie in [false, true] {
+            let limits = ChunkLimits::default();
+            let (prover_circuit, verifier_circuit) =
+                circuit_pair::<HashCommitment>("bristol/add.txt");
+            let (prover_circuit, verifier_circuit) = (
+                prover_circuit.into_chunks(&limits),
+                verifier_circuit.into_chunks(&limits),
+            );
+            let inputs = vec![number_to_bool_array(633, 64), number_to_bool_array(15, 64)];
+            assert_eq!(
+                check_wrong_output_leaf_spend(prover_circuit, verifier_circuit, inputs, 1, lie),
+                lie
+            );
+        }
+    }
+
+    #[test]
+    fn test_compact_equivocation_tree() {
+        let secp = Secp256k1::new();
+        let prover_pk = random_pk(&secp);
+        let verifier_pk = random_pk(&secp);
+        let circuit: Circuit =
+            Circuit::from_bristol("bristol/add.txt", None).into_chunks(&ChunkLimits::default());
+        let (_, tree_info) =
+            generate_compact_equivocation_address_and_info(&secp, &circuit, prover_pk, verifier_pk);
+        let revealed = circuit
+            .get_revealed_wires()
+            .iter()
+            .map(|wire_arcm| wire_arcm.lock().unwrap().index.unwrap())
+            .collect::<Vec<usize>>();
+        for wire_arcm in circuit.wires.iter() {
+            let wire = wire_arcm.lock().unwrap();
+            let script =
+                generate_anti_contradiction_script::<HashCommitment>(&wire.commitment, verifier_pk);
+            let has_leaf = tree_info
+                .control_block(&(script, LeafVersion::TapScript))
+                .is_some();
+            assert_eq!(has_leaf, revealed.contains(&wire.index.unwrap()));
+        }
+    }
+
+    #[test]
+    fn test_anti_contradiction_leaf_spend() {
+        check_anti_contradiction_leaf_spend::<HashCommitment>();
+        check_anti_contradiction_leaf_spend::<WinternitzCommitment>();
+    }
+
+    fn check_response_leaf_spend<C: BitCommitment>() {
+        let secp = Secp256k1::new();
+        let prover = Keypair::new(&secp, &mut rand::thread_rng());
+        let verifier = Keypair::new(&secp, &mut rand::thread_rng());
+        let prover_pk = prover.x_only_public_key().0;
+        let mut circuit: Circuit<C> = Circuit::default();
+        circuit.evaluate(vec![vec![true], vec![true, false]]);
+        let (preimages, hashes) = random_challenges(circuit.num_gates());
+        let (address, tree_info) =
+            generate_response_address_and_info(&secp, &circuit, prover_pk, &hashes);
+        let script = generate_gate_response_script(&circuit.gates[1], &hashes[1], prover_pk);
+        let control_block = control_block_of(&tree_info, &script);
+
+        let mut spend = LeafSpend::new(&address, Sequence::ENABLE_RBF_NO_LOCKTIME);
+        let sig = spend.sign(&prover, &script);
+        let gate = &mut circuit.gates[1];
+        let witness =
+            generate_gate_response_witness(&sig, gate, preimages[1], &script, &control_block);
+        assert!(spend.execute(&tree_info, witness));
+
+        let witness =
+            generate_gate_response_witness(&sig, gate, preimages[0], &script, &control_block);
+        assert!(!spend.execute(&tree_info, witness));
+        let verifier_sig = spend.sign(&verifier, &script);
+        let witness = generate_gate_response_witness(
+            &verifier_sig,
+            gate,
+            preimages[1],
+            &script,
+            &control_block,
+        );
+        assert!(!spend.execute(&tree_info, witness));
+
+        let output = gate.get_output_bits();
+        gate.set_output_bits(output.iter().map(|b| !b).collect());
+        let witness =
+            generate_gate_response_witness(&sig, gate, preimages[1], &script, &control_block);
+        assert!(!spend.execute(&tree_info, witness));
+    }
+
+    #[test]
+    fn test_response_leaf_spend() {
+        check_response_leaf_spend::<HashCommitment>();
+        check_response_leaf_spend::<WinternitzCommitment>();
+    }
 }
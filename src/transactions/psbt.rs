@@ -0,0 +1,180 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use bitcoin::psbt::Psbt;
+use bitcoin::secp256k1::schnorr::Signature;
+use bitcoin::taproot::{self, LeafVersion, TapLeafHash, TaprootSpendInfo};
+use bitcoin::{ScriptBuf, Transaction, TxOut, Witness, XOnlyPublicKey};
+use tracing::debug;
+
+// Directory the binaries write the PSBT of every transaction of the game to, if set
+pub const PSBT_DIR_ENV_VAR: &str = "TOY_BITVM_PSBT_DIR";
+
+// What a PSBT needs to know about an input of a transaction of the game: the output it spends,
+// the tree of that output, the leaves it may be spent with and the signatures collected so far
+pub struct InputSpend<'a> {
+    pub prevout: TxOut,
+    pub tree_info: &'a TaprootSpendInfo,
+    pub leaves: Vec<ScriptBuf>,
+    pub signatures: Vec<(XOnlyPublicKey, ScriptBuf, Signature)>,
+}
+
+impl<'a> InputSpend<'a> {
+    pub fn new(prevout: TxOut, tree_info: &'a TaprootSpendInfo) -> Self {
+        InputSpend {
+            prevout,
+            tree_info,
+            leaves: Vec::new(),
+            signatures: Vec::new(),
+        }
+    }
+
+    pub fn with_leaf(mut self, leaf: ScriptBuf) -> Self {
+        self.leaves.push(leaf);
+        self
+    }
+
+    pub fn with_signature(mut self, pk: XOnlyPublicKey, leaf: ScriptBuf, sig: Signature) -> Self {
+        self.signatures.push((pk, leaf, sig));
+        self
+    }
+}
+
+// PSBT of a transaction with the taproot fields of BIP 371 for each input. Inputs that already
+// have a witness are exported as finalized, so a fully signed transaction can be extracted again.
+pub fn create_psbt(tx: &Transaction, inputs: Vec<InputSpend>) -> Psbt {
+    assert_eq!(tx.input.len(), inputs.len(), "Every input needs its spend");
+    let mut unsigned_tx = tx.clone();
+    for input in unsigned_tx.input.iter_mut() {
+        input.witness = Witness::new();
+    }
+    let mut psbt = Psbt::from_unsigned_tx(unsigned_tx).unwrap();
+
+    for ((psbt_input, tx_input), spend) in psbt.inputs.iter_mut().zip(&tx.input).zip(inputs) {
+        psbt_input.witness_utxo = Some(spend.prevout);
+        psbt_input.tap_internal_key = Some(spend.tree_info.internal_key());
+        psbt_input.tap_merkle_root = spend.tree_info.merkle_root();
+        for leaf in spend.leaves {
+            let control_block = spend
+                .tree_info
+                .control_block(&(leaf.clone(), LeafVersion::TapScript))
+                .expect("Leaf should be in the tree");
+            psbt_input
+                .tap_scripts
+                .insert(control_block, (leaf, LeafVersion::TapScript));
+        }
+        for (pk, leaf, sig) in spend.signatures {
+            psbt_input.tap_script_sigs.insert(
+                (pk, TapLeafHash::from_script(&leaf, LeafVersion::TapScript)),
+                taproot::Signature {
+                    sig,
+                    hash_ty: bitcoin::sighash::TapSighashType::Default,
+                },
+            );
+        }
+        if !tx_input.witness.is_empty() {
+            psbt_input.final_script_witness = Some(tx_input.witness.clone());
+        }
+    }
+    psbt
+}
+
+// Writes the PSBT in base64 to the export directory, if there is one
+pub fn export_psbt(name: &str, psbt: &Psbt) {
+    if let Ok(dir) = env::var(PSBT_DIR_ENV_VAR) {
+        let dir = PathBuf::from(dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!("{}.psbt", name));
+        fs::write(&path, psbt.to_string()).unwrap();
+        debug!(path = %path.display(), "exported PSBT");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transactions::{
+        generate_2_of_2_script, generate_2_of_2_witness, generate_response_second_address_and_info,
+    };
+    use bitcoin::absolute::{Height, LockTime};
+    use bitcoin::hashes::Hash;
+    use bitcoin::secp256k1::{rand, Message, Secp256k1};
+    use bitcoin::{Amount, OutPoint, Sequence, TxIn, Txid};
+
+    #[test]
+    fn test_create_psbt() {
+        let secp = Secp256k1::new();
+        let keypair = |_| {
+            secp.generate_keypair(&mut rand::thread_rng())
+                .0
+                .keypair(&secp)
+        };
+        let (prover, verifier) = (keypair(0), keypair(1));
+        let (prover_pk, verifier_pk) =
+            (prover.x_only_public_key().0, verifier.x_only_public_key().0);
+        let (address, tree_info) =
+            generate_response_second_address_and_info(&secp, prover_pk, verifier_pk);
+        let prevout = TxOut {
+            value: Amount::from_sat(10_000),
+            script_pubkey: address.script_pubkey(),
+        };
+        let mut tx = Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: LockTime::from(Height::MIN),
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::all_zeros(), 1),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(9_000),
+                script_pubkey: address.script_pubkey(),
+            }],
+        };
+        let musig_script = generate_2_of_2_script(prover_pk, verifier_pk);
+        let message = Message::from_digest([7; 32]);
+        let prover_sig = secp.sign_schnorr(&message, &prover);
+
+        // Presigned by one side only: the leaf, its control block and the partial signature
+        let psbt = create_psbt(
+            &tx,
+            vec![InputSpend::new(prevout.clone(), &tree_info)
+                .with_leaf(musig_script.clone())
+                .with_signature(prover_pk, musig_script.clone(), prover_sig)],
+        );
+        let input = &psbt.inputs[0];
+        assert_eq!(input.witness_utxo, Some(prevout.clone()));
+        assert_eq!(input.tap_internal_key, Some(tree_info.internal_key()));
+        assert_eq!(input.tap_merkle_root, tree_info.merkle_root());
+        let (control_block, (script, _)) = input.tap_scripts.iter().next().unwrap();
+        assert_eq!(script, &musig_script);
+        assert_eq!(
+            Some(control_block),
+            tree_info
+                .control_block(&(musig_script.clone(), LeafVersion::TapScript))
+                .as_ref()
+        );
+        let leaf_hash = TapLeafHash::from_script(&musig_script, LeafVersion::TapScript);
+        assert_eq!(
+            input.tap_script_sigs[&(prover_pk, leaf_hash)].sig,
+            prover_sig
+        );
+        assert!(input.final_script_witness.is_none());
+        // Only the leaf it will be spent with, not the whole tree
+        assert_eq!(input.tap_scripts.len(), 1);
+
+        // Survives the base64 encoding used for exports
+        let decoded: Psbt = psbt.to_string().parse().unwrap();
+        assert_eq!(decoded, psbt);
+
+        // Fully signed, it gives back the same transaction
+        let verifier_sig = secp.sign_schnorr(&message, &verifier);
+        tx.input[0].witness =
+            generate_2_of_2_witness(&prover_sig, &verifier_sig, &musig_script, control_block);
+        let psbt = create_psbt(&tx, vec![InputSpend::new(prevout, &tree_info)]);
+        assert_eq!(psbt.unsigned_tx.txid(), tx.txid());
+        assert_eq!(psbt.extract_tx_unchecked_fee_rate(), tx);
+    }
+}
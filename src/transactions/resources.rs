@@ -0,0 +1,381 @@
+use bitcoin::blockdata::constants::MAX_SCRIPT_ELEMENT_SIZE;
+use bitcoin::opcodes::all::*;
+use bitcoin::opcodes::{Class, ClassifyContext, Opcode};
+use bitcoin::policy::MAX_STANDARD_TX_WEIGHT;
+use bitcoin::script::Instruction;
+use bitcoin::{ScriptBuf, Transaction, Witness};
+
+// Consensus limit on the number of elements in the stack and altstack together
+pub const MAX_STACK_SIZE: usize = 1000;
+// Policy limit on the size of the witness stack items of a tapscript spend, not counting the
+// script and the control block
+pub const MAX_STANDARD_TAPSCRIPT_STACK_ITEM_SIZE: usize = 80;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    StackOverflow(usize),
+    StackUnderflow { position: usize },
+    // Tapscript requires exactly one element to be left on the stack
+    UnbalancedStack(usize),
+    UnbalancedConditional,
+    OversizedPush { position: usize, size: usize },
+    DisallowedOpcode { position: usize, opcode: u8 },
+    NonStandardStackItem { index: usize, size: usize },
+    NonStandardWeight(u64),
+}
+
+impl Violation {
+    // Whether the spend is invalid, rather than just not relayed by default nodes
+    pub fn is_consensus(&self) -> bool {
+        !matches!(
+            self,
+            Violation::NonStandardStackItem { .. } | Violation::NonStandardWeight(_)
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ScriptReport {
+    pub script_size: usize,
+    pub stack_items: usize,
+    // Largest number of elements in the stack and altstack together
+    pub max_stack_depth: usize,
+    pub final_stack_depth: usize,
+    // Weight units taken by the witness, which are the same as its serialized size. Only counts
+    // the control block when the whole witness is analyzed.
+    pub witness_weight: usize,
+    pub violations: Vec<Violation>,
+}
+
+impl ScriptReport {
+    pub fn is_valid(&self) -> bool {
+        !self.violations.iter().any(|v| v.is_consensus())
+    }
+
+    pub fn is_standard(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+// Number of elements an opcode takes from the main stack and puts back, or None for opcodes
+// that are handled separately or not allowed.
+fn stack_effect(opcode: Opcode) -> Option<(usize, usize)> {
+    let effect = match opcode {
+        OP_CLTV | OP_CSV => (1, 1),
+        // Only duplicates a non-zero top item, but the worst case is what bounds the depth
+        OP_IFDUP => (1, 2),
+        OP_VERIFY | OP_DROP => (1, 0),
+        OP_2DROP => (2, 0),
+        OP_DUP | OP_SIZE => (1, 2),
+        OP_2DUP => (2, 4),
+        OP_3DUP => (3, 6),
+        OP_2OVER => (4, 6),
+        OP_2ROT => (6, 6),
+        OP_2SWAP => (4, 4),
+        OP_DEPTH => (0, 1),
+        OP_NIP => (2, 1),
+        OP_OVER => (2, 3),
+        OP_ROT => (3, 3),
+        OP_SWAP => (2, 2),
+        OP_TUCK => (2, 3),
+        OP_PICK => (1, 1),
+        OP_ROLL => (1, 0),
+        OP_EQUAL => (2, 1),
+        OP_EQUALVERIFY | OP_NUMEQUALVERIFY | OP_CHECKSIGVERIFY => (2, 0),
+        OP_1ADD | OP_1SUB | OP_NEGATE | OP_ABS | OP_NOT | OP_0NOTEQUAL => (1, 1),
+        OP_ADD
+        | OP_SUB
+        | OP_BOOLAND
+        | OP_BOOLOR
+        | OP_NUMEQUAL
+        | OP_NUMNOTEQUAL
+        | OP_LESSTHAN
+        | OP_GREATERTHAN
+        | OP_LESSTHANOREQUAL
+        | OP_GREATERTHANOREQUAL
+        | OP_MIN
+        | OP_MAX => (2, 1),
+        OP_WITHIN => (3, 1),
+        OP_RIPEMD160 | OP_SHA1 | OP_SHA256 | OP_HASH160 | OP_HASH256 => (1, 1),
+        OP_CHECKSIG => (2, 1),
+        OP_CHECKSIGADD => (3, 1),
+        OP_CODESEPARATOR => (0, 0),
+        _ => match opcode.classify(ClassifyContext::TapScript) {
+            Class::NoOp => (0, 0),
+            Class::PushNum(_) => (0, 1),
+            _ => return None,
+        },
+    };
+    Some(effect)
+}
+
+fn pop(main: &mut usize, count: usize, position: usize, violations: &mut Vec<Violation>) {
+    if *main < count {
+        violations.push(Violation::StackUnderflow { position });
+        *main = 0;
+    } else {
+        *main -= count;
+    }
+}
+
+struct Conditional {
+    main: usize,
+    alt: usize,
+    then_branch: Option<(usize, usize)>,
+}
+
+// Follows the number of stack elements through the script without executing it. The depth of
+// every script generated here does not depend on the values in the witness, only on its length.
+// Both branches of a conditional are assumed to be possible, keeping the larger depth.
+pub fn analyze_script(script: &ScriptBuf, stack_items: &[Vec<u8>]) -> ScriptReport {
+    let mut violations = Vec::new();
+    for (index, item) in stack_items.iter().enumerate() {
+        if item.len() > MAX_STANDARD_TAPSCRIPT_STACK_ITEM_SIZE {
+            violations.push(Violation::NonStandardStackItem {
+                index,
+                size: item.len(),
+            });
+        }
+    }
+
+    let mut main = stack_items.len();
+    let mut alt = 0;
+    let mut max_depth = main;
+    let mut last_number: Option<i64> = None;
+    let mut conditionals: Vec<Conditional> = Vec::new();
+
+    for (position, instruction) in script.instructions().enumerate() {
+        let instruction = match instruction {
+            Ok(instruction) => instruction,
+            Err(_) => {
+                violations.push(Violation::DisallowedOpcode {
+                    position,
+                    opcode: OP_INVALIDOPCODE.to_u8(),
+                });
+                break;
+            }
+        };
+        let number = instruction.script_num();
+        match instruction {
+            Instruction::PushBytes(bytes) => {
+                if bytes.len() > MAX_SCRIPT_ELEMENT_SIZE {
+                    violations.push(Violation::OversizedPush {
+                        position,
+                        size: bytes.len(),
+                    });
+                }
+                main += 1;
+            }
+            Instruction::Op(opcode) => match opcode {
+                OP_IF | OP_NOTIF => {
+                    pop(&mut main, 1, position, &mut violations);
+                    conditionals.push(Conditional {
+                        main,
+                        alt,
+                        then_branch: None,
+                    });
+                }
+                OP_ELSE => match conditionals.last_mut() {
+                    Some(conditional) if conditional.then_branch.is_none() => {
+                        conditional.then_branch = Some((main, alt));
+                        main = conditional.main;
+                        alt = conditional.alt;
+                    }
+                    _ => violations.push(Violation::UnbalancedConditional),
+                },
+                OP_ENDIF => match conditionals.pop() {
+                    Some(conditional) => {
+                        let (other_main, other_alt) = conditional
+                            .then_branch
+                            .unwrap_or((conditional.main, conditional.alt));
+                        main = main.max(other_main);
+                        alt = alt.max(other_alt);
+                    }
+                    None => violations.push(Violation::UnbalancedConditional),
+                },
+                OP_TOALTSTACK => {
+                    pop(&mut main, 1, position, &mut violations);
+                    alt += 1;
+                }
+                OP_FROMALTSTACK => {
+                    if alt == 0 {
+                        violations.push(Violation::StackUnderflow { position });
+                    } else {
+                        alt -= 1;
+                        main += 1;
+                    }
+                }
+                _ => match stack_effect(opcode) {
+                    Some((pops, pushes)) => {
+                        pop(&mut main, pops, position, &mut violations);
+                        // PICK and ROLL reach as deep as the number pushed before them
+                        if let (OP_PICK | OP_ROLL, Some(n)) = (opcode, last_number) {
+                            if n < 0 || main < n as usize + 1 {
+                                violations.push(Violation::StackUnderflow { position });
+                            }
+                        }
+                        main += pushes;
+                    }
+                    None => violations.push(Violation::DisallowedOpcode {
+                        position,
+                        opcode: opcode.to_u8(),
+                    }),
+                },
+            },
+        }
+        last_number = number;
+        max_depth = max_depth.max(main + alt);
+    }
+
+    if !conditionals.is_empty() {
+        violations.push(Violation::UnbalancedConditional);
+    }
+    if max_depth > MAX_STACK_SIZE {
+        violations.push(Violation::StackOverflow(max_depth));
+    }
+    if main != 1 {
+        violations.push(Violation::UnbalancedStack(main));
+    }
+
+    let mut witness = Witness::from_slice(stack_items);
+    witness.push(script.as_bytes());
+    ScriptReport {
+        script_size: script.len(),
+        stack_items: stack_items.len(),
+        max_stack_depth: max_depth,
+        final_stack_depth: main,
+        witness_weight: witness.size(),
+        violations,
+    }
+}
+
+// Splits a script path witness into its stack items, script and control block. Key path spends
+// and witnesses with an annex, which nothing here produces, return None.
+pub fn analyze_witness(witness: &Witness) -> Option<ScriptReport> {
+    let elements = witness.to_vec();
+    if elements.len() < 2 || elements.last()?.first() == Some(&0x50) {
+        return None;
+    }
+    let (stack_items, rest) = elements.split_at(elements.len() - 2);
+    let script = ScriptBuf::from_bytes(rest[0].clone());
+    let mut report = analyze_script(&script, stack_items);
+    report.witness_weight = witness.size();
+    Some(report)
+}
+
+// Everything that would keep a transaction out of the mempool of a default node
+pub fn check_transaction(tx: &Transaction) -> Vec<Violation> {
+    let mut violations = tx
+        .input
+        .iter()
+        .filter_map(|input| analyze_witness(&input.witness))
+        .flat_map(|report| report.violations)
+        .collect::<Vec<Violation>>();
+    let weight = tx.weight().to_wu();
+    if weight > MAX_STANDARD_TX_WEIGHT as u64 {
+        violations.push(Violation::NonStandardWeight(weight));
+    }
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::chunk::ChunkLimits;
+    use crate::circuit::gates::create_gate;
+    use crate::circuit::winternitz::WinternitzCommitment;
+    use crate::circuit::wire::HashCommitment;
+    use crate::circuit::Circuit;
+    use crate::traits::bit_commitment::BitCommitment;
+    use crate::traits::gate::GateTrait;
+    use crate::utils::number_to_bool_array;
+    use bitcoin::script::Builder;
+
+    fn response_stack<C: BitCommitment>(
+        gate: &mut Box<dyn GateTrait<C> + Send + Sync>,
+    ) -> Vec<Vec<u8>> {
+        // The signature is only checked by the final OP_CHECKSIG, any 64 bytes do here
+        let mut stack = vec![vec![1; 64]];
+        stack.extend(gate.create_response_witness([0; 32]));
+        stack
+    }
+
+    #[test]
+    fn test_gate_response_resources() {
+        for gate_name in [
+            "not",
+            "and",
+            "xor",
+            "or",
+            "mux",
+            "lut:00,10,10,01,10,01,01,11",
+        ] {
+            let mut gate: Box<dyn GateTrait + Send + Sync> = create_gate(gate_name, None, None);
+            gate.set_input_bits(vec![false; gate.get_input_size()]);
+            gate.set_output_bits(vec![false; gate.get_output_size()]);
+            let script = Builder::from(gate.create_response_script([0; 32]).to_bytes())
+                .push_slice([2; 32])
+                .push_opcode(OP_CHECKSIG)
+                .into_script();
+            let report = analyze_script(&script, &response_stack(&mut gate));
+            assert!(report.is_standard(), "{}: {:?}", gate_name, report);
+            assert_eq!(report.final_stack_depth, 1);
+
+            // A missing reveal leaves the script short of stack elements
+            let mut stack = response_stack(&mut gate);
+            stack.remove(1);
+            assert!(!analyze_script(&script, &stack).is_valid());
+        }
+    }
+
+    fn check_chunk_resources<C: BitCommitment>() {
+        let mut circuit: Circuit<C> =
+            Circuit::from_bristol("bristol/add.txt", None).into_chunks(&ChunkLimits::default());
+        circuit.evaluate(vec![
+            number_to_bool_array(1, 64),
+            number_to_bool_array(2, 64),
+        ]);
+        for gate in circuit.gates.iter_mut() {
+            let script = Builder::from(gate.create_response_script([0; 32]).to_bytes())
+                .push_slice([2; 32])
+                .push_opcode(OP_CHECKSIG)
+                .into_script();
+            let report = analyze_script(&script, &response_stack(gate));
+            assert!(report.is_valid(), "{:?}", report.violations);
+            assert!(report.max_stack_depth <= MAX_STACK_SIZE);
+        }
+    }
+
+    #[test]
+    fn test_chunk_response_resources() {
+        check_chunk_resources::<HashCommitment>();
+        check_chunk_resources::<WinternitzCommitment>();
+    }
+
+    #[test]
+    fn test_ifdup_worst_case() {
+        let script = Builder::new()
+            .push_int(1)
+            .push_opcode(OP_IFDUP)
+            .push_opcode(OP_DROP)
+            .into_script();
+        assert_eq!(analyze_script(&script, &[]).max_stack_depth, 2);
+    }
+
+    #[test]
+    fn test_nonstandard_witness() {
+        let script = Builder::new().push_opcode(OP_DROP).into_script();
+        let report = analyze_script(&script, &[vec![0; 81], vec![1]]);
+        assert!(report.is_valid());
+        assert!(!report.is_standard());
+
+        let script = Builder::new()
+            .push_int(5)
+            .push_opcode(OP_PICK)
+            .into_script();
+        let report = analyze_script(&script, &[vec![1], vec![1]]);
+        assert!(report
+            .violations
+            .contains(&Violation::StackUnderflow { position: 1 }));
+    }
+}
@@ -0,0 +1,48 @@
+use std::env;
+use std::sync::Arc;
+
+use bitcoincore_rpc::{Auth, Client};
+use tracing::Span;
+
+// Node to connect to, and the wallet of it to use. In the wallet of the verifier, `{session}`
+// stands for the id of the session, so that every session can fund its games from its own wallet.
+pub const RPC_URL_ENV_VAR: &str = "TOY_BITVM_RPC_URL";
+pub const RPC_WALLET_ENV_VAR: &str = "TOY_BITVM_RPC_WALLET";
+
+pub fn rpc_wallet() -> String {
+    env::var(RPC_WALLET_ENV_VAR).unwrap_or_else(|_| "admin".to_string())
+}
+
+pub fn rpc_url(wallet: &str) -> String {
+    let node = env::var(RPC_URL_ENV_VAR).unwrap_or_else(|_| "http://localhost:18443".to_string());
+    format!("{}/wallet/{}", node.trim_end_matches('/'), wallet)
+}
+
+pub fn rpc_client() -> Client {
+    rpc_client_for_wallet(&rpc_wallet())
+}
+
+pub fn rpc_client_for_wallet(wallet: &str) -> Client {
+    Client::new(
+        &rpc_url(wallet),
+        Auth::UserPass("admin".to_string(), "admin".to_string()),
+    )
+    .unwrap_or_else(|e| panic!("Failed to connect to Bitcoin RPC: {}", e))
+}
+
+// The client blocks, so a game makes its calls on a thread of their own to leave the runtime to
+// the other games
+pub async fn call_blocking<T, F>(rpc: &Arc<Client>, call: F) -> T
+where
+    T: Send + 'static,
+    F: FnOnce(&Client) -> T + Send + 'static,
+{
+    let rpc = rpc.clone();
+    let span = Span::current();
+    tokio::task::spawn_blocking(move || {
+        let _span = span.enter();
+        call(&rpc)
+    })
+    .await
+    .unwrap()
+}
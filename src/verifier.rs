@@ -1,4 +1,7 @@
 use std::borrow::BorrowMut;
+use std::collections::HashSet;
+use std::env;
+use std::sync::Arc;
 use std::time::Duration;
 
 use bitcoin::absolute::{Height, LockTime};
@@ -7,91 +10,182 @@ use bitcoin::secp256k1::schnorr::Signature;
 use bitcoin::secp256k1::Message;
 use bitcoin::sighash::SighashCache;
 use bitcoin::taproot::LeafVersion;
-use bitcoin::{secp256k1::Secp256k1, Transaction, Txid, XOnlyPublicKey};
-use bitcoin::{Amount, OutPoint, ScriptBuf, TapLeafHash, TxIn, TxOut, Witness};
-use bitcoincore_rpc::{Auth, Client, RpcApi};
+use bitcoin::{secp256k1::Secp256k1, Address, Transaction, Txid};
+use bitcoin::{FeeRate, OutPoint, ScriptBuf, TapLeafHash, TxIn, TxOut, Witness};
+use bitcoincore_rpc::RpcApi;
 use tokio::net::{TcpListener, TcpStream};
 use tokio_tungstenite::accept_async;
 use toy_bitvm::{
-    actor::Actor,
-    circuit::wire::{HashTuple, HashValue, PreimageValue, Wire},
-    circuit::Circuit,
-    communication::{receive_message, send_message},
+    actor::{Actor, ActorPublicKeys},
+    circuit::wire::{HashCommitment, HashTuple, HashValue, RevealOutcome, Wire},
+    circuit::{chunk::ChunkLimits, Circuit},
+    communication::{receive_message, send_message, verifier_address, RoundDecision},
+    logging::init_logging,
+    musig::{generate_nonce, KeyAggContext, PublicNonce},
+    report::{response_reveals, FraudProof, GameReport, Outcome, Role},
+    session::{circuit_path, Challenge, ChallengeStrategy, Session, SessionManager},
+    tower::{
+        presign_slashes, register_with_watchtower, watched_wires, WatchtowerPackage,
+        WATCHTOWER_ENV_VAR,
+    },
+    traits::bit_commitment::BitCommitment,
+    traits::gate::WrongOutputProof,
+    transactions::cpfp::{with_anchor, FeeBumper},
+    transactions::fees::{check_proposed_fee_rate, choose_fee_rate, FeeSchedule, DUST_LIMIT},
+    transactions::psbt::{create_psbt, export_psbt, InputSpend},
+    transactions::rpc::{call_blocking, rpc_client_for_wallet},
     transactions::{
-        generate_2_of_2_script, generate_anti_contradiction_script,
-        generate_challenge_address_and_info, generate_challenge_script,
-        generate_equivoation_address_and_info, generate_response_address_and_info,
-        generate_response_second_address_and_info, watch_transaction,
+        broadcast_transaction,
+        cache::{LeafSet, TaprootCache},
+        cooperative_close_sighash, create_cooperative_close_tx, generate_2_of_2_script,
+        generate_2_of_2_witness, generate_anti_contradiction_script,
+        generate_anti_contradiction_witness, generate_challenge_script, generate_challenge_witness,
+        generate_compact_equivocation_address_and_info, generate_response_second_address_and_info,
+        generate_timelock_script, generate_timelock_witness, generate_wrong_output_script,
+        generate_wrong_output_witness, watch_transaction, TIMELOCK_BLOCKS,
     },
-    utils::take_stdin,
 };
+use tracing::{debug, field, info, info_span, warn, Instrument, Span};
+
+// Ways the prover can be caught cheating, each with its own leaf in the equivocation output
+enum Fraud {
+    Equivocation(Wire),
+    WrongOutput(usize, WrongOutputProof),
+}
 
 #[tokio::main]
 async fn main() {
-    let listener = TcpListener::bind("127.0.0.1:9000").await.unwrap();
-    println!("Listening on: 127.0.0.1:9000");
-
-    while let Ok((stream, _)) = listener.accept().await {
-        tokio::spawn(handle_connection(stream));
+    init_logging();
+    let address = verifier_address();
+    let listener = TcpListener::bind(&address).await.unwrap();
+    info!(address, "listening");
+
+    // Every connection gets a session of its own, with its own challenge strategy
+    let manager = SessionManager::new();
+    while let Ok((stream, peer)) = listener.accept().await {
+        let session = manager.start(peer.to_string(), ChallengeStrategy::from_env());
+        let span = info_span!(
+            "session",
+            role = "verifier",
+            id = session.id,
+            %peer,
+            game = field::Empty
+        );
+        info!(id = session.id, %peer, active = manager.sessions().len(), "session started");
+        tokio::spawn(handle_connection(stream, session).instrument(span));
     }
 }
 
-async fn handle_connection(stream: TcpStream) {
+async fn handle_connection(stream: TcpStream, mut session: Session) {
     let mut ws_stream = accept_async(stream)
         .await
         .expect("Error during the websocket handshake occurred");
 
     let circuit_bristol_path: String = receive_message(&mut ws_stream).await.unwrap();
-    println!("Received: {}", circuit_bristol_path);
+    info!(circuit = circuit_bristol_path, "received the circuit");
+    let circuit_file = match circuit_path(&circuit_bristol_path) {
+        Ok(path) => path,
+        Err(e) => {
+            warn!(error = e, "rejected the circuit");
+            return;
+        }
+    };
 
-    let mut verifier = Actor::new();
+    // Every session gets keys of its own, even when they are derived from a single seed
+    let mut verifier = Actor::from_env_for_game(session.id as u32);
     let verifier_public_key = verifier.public_key;
-    println!("Verifier public key: {}", verifier_public_key);
-    // send our public key to the prover
-    send_message(&mut ws_stream, &verifier.public_key.to_string())
+    info!(keys = ?verifier.public_keys(), "verifier public keys");
+    // send our public keys to the prover
+    send_message(&mut ws_stream, &verifier.public_keys())
         .await
         .unwrap();
-    let prover_public_key_str: String = receive_message(&mut ws_stream).await.unwrap();
-    let prover_public_key: XOnlyPublicKey = prover_public_key_str.parse().unwrap();
-    println!("Prover public key: {}", prover_public_key);
+    let prover_keys: ActorPublicKeys = receive_message(&mut ws_stream).await.unwrap();
+    // A cooperative close needs a MuSig partial signature from both sides, which signers holding
+    // the key outside the process cannot give
+    let prover_can_close: bool = receive_message(&mut ws_stream).await.unwrap();
+    let can_close = prover_can_close && verifier.can_close();
+    if !can_close {
+        warn!("a signer cannot take part in MuSig, so the game will not close cooperatively");
+    }
+    let prover_public_key = prover_keys.presign;
+    let prover_response_key = prover_keys.response;
+    info!(keys = ?prover_keys, "received the prover public keys");
 
     // NOW PUBLIC KEY EXCHANGE IS COMPLETE
 
     let wire_hashes: Vec<HashTuple> = receive_message(&mut ws_stream).await.unwrap();
 
-    let mut circuit = Circuit::from_bristol("bristol/add.txt", Some(wire_hashes));
+    // Must be chunked with the same limits as the prover
+    let mut circuit: Circuit =
+        Circuit::from_bristol(circuit_file.to_str().unwrap(), Some(wire_hashes))
+            .into_chunks(&ChunkLimits::default());
     let secp = Secp256k1::new();
 
     let bisection_length = 10;
+    let mut taproot_cache = TaprootCache::new();
 
-    let (equivocation_address, equivocation_taproot_info) = generate_equivoation_address_and_info(
-        &secp,
-        &circuit,
-        prover_public_key,
-        verifier_public_key,
-    );
+    let (equivocation_address, equivocation_taproot_info) =
+        generate_compact_equivocation_address_and_info(
+            &secp,
+            &circuit,
+            prover_public_key,
+            verifier_public_key,
+        );
 
-    let (response_second_address, _) =
+    let (response_second_address, response_second_taproot_info) =
         generate_response_second_address_and_info(&secp, prover_public_key, verifier_public_key);
 
-    let rpc = Client::new(
-        "http://localhost:18443/wallet/admin",
-        Auth::UserPass("admin".to_string(), "admin".to_string()),
-    )
-    .unwrap_or_else(|e| panic!("Failed to connect to Bitcoin RPC: {}", e));
+    let rpc = Arc::new(rpc_client_for_wallet(&session.wallet));
 
-    let amt: u64 = 100_000;
-    let fee: u64 = 500;
-    let dust_limit: u64 = 546;
     let watch_interval = Duration::from_secs(1);
 
-    let initial_fund_txid: Txid = receive_message(&mut ws_stream).await.unwrap();
-    let initial_fund_tx = rpc
-        .get_transaction(&initial_fund_txid, None)
-        .unwrap_or_else(|e| panic!("Failed to get transaction: {}", e));
+    // The prover proposes the fee rate, the fees of each transaction follow from it
+    let fee_rate = FeeRate::from_sat_per_kwu(receive_message(&mut ws_stream).await.unwrap());
+    if let Err(e) = check_proposed_fee_rate(fee_rate, call_blocking(&rpc, choose_fee_rate).await) {
+        warn!(error = e, "rejected the fee rate proposed by the prover");
+        return;
+    }
+    let anchors: bool = receive_message(&mut ws_stream).await.unwrap();
+    let fee_schedule = FeeSchedule::new(
+        fee_rate,
+        &circuit,
+        prover_public_key,
+        verifier_public_key,
+        &equivocation_taproot_info,
+        &response_second_taproot_info,
+        anchors,
+    );
+    let funding = fee_schedule.required_funding(bisection_length as u64);
+
+    // The funding output must pay the whole funding to the prover's key, whichever wallet made it
+    let funding_outpoint: OutPoint = receive_message(&mut ws_stream).await.unwrap();
+    Span::current().record("game", field::display(funding_outpoint));
+    session.set_game(funding_outpoint);
+    let funding_output = call_blocking(&rpc, move |rpc| {
+        rpc.get_tx_out(&funding_outpoint.txid, funding_outpoint.vout, Some(true))
+            .map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap_or_else(|e| panic!("Failed to get the funding output: {}", e))
+    .expect("Funding output should be unspent");
+    assert_eq!(funding_output.value, funding, "Game is not fully funded");
+    assert_eq!(
+        funding_output.script_pub_key.script().unwrap(),
+        Address::p2tr(&secp, prover_keys.funding, None, bitcoin::Network::Regtest).script_pubkey(),
+        "Funding output does not pay to the prover"
+    );
+
+    let mut report = GameReport::new(
+        Role::Verifier,
+        &circuit_bristol_path,
+        circuit.get_wire_commitments(),
+        prover_keys,
+        verifier.public_keys(),
+        funding_outpoint,
+    );
+    let mut fee_bumper = FeeBumper::new(&verifier, &session.wallet, &fee_schedule, watch_interval);
 
-    let mut last_txid = initial_fund_txid;
-    let _last_vout = initial_fund_tx.details[0].vout;
+    let mut last_txid = funding_outpoint.txid;
     let mut last_output: Vec<TxOut> = Vec::new();
     let mut kickoff_tx: Transaction = Transaction {
         version: bitcoin::transaction::Version::TWO,
@@ -99,100 +193,174 @@ async fn handle_connection(stream: TcpStream) {
         input: vec![],
         output: vec![],
     };
+    // A watchtower gets a slash of every round presigned for each wire it may catch equivocating
+    let watchtower = env::var(WATCHTOWER_ENV_VAR).ok();
+    let watched = if watchtower.is_some() {
+        watched_wires(&circuit, &equivocation_taproot_info, verifier_public_key)
+    } else {
+        Vec::new()
+    };
+    let mut watched_rounds = Vec::new();
 
     for i in 0..bisection_length as u64 {
-        println!("Bisection iteration: {}", i);
-        let challenge_hashes: Vec<HashValue> =
-            verifier.generate_challenge_hashes(circuit.num_gates());
-        send_message(&mut ws_stream, &challenge_hashes)
-            .await
-            .unwrap();
+        async {
+            let challenge_hashes: Vec<HashValue> =
+                verifier.generate_challenge_hashes(circuit.num_gates());
+            send_message(&mut ws_stream, &challenge_hashes)
+                .await
+                .unwrap();
 
-        let (challenge_address, _) = generate_challenge_address_and_info(
-            &secp,
-            &circuit,
-            prover_public_key,
-            verifier_public_key,
-            &challenge_hashes,
-        );
+            let (challenge_address, challenge_taproot_info) = taproot_cache
+                .challenge_address_and_info(
+                    &secp,
+                    i as usize,
+                    &circuit,
+                    prover_public_key,
+                    verifier_public_key,
+                    &challenge_hashes,
+                );
 
-        let (response_address, _) = generate_response_address_and_info(
-            &secp,
-            &circuit,
-            prover_public_key,
-            &challenge_hashes,
-        );
+            let (response_address, _) = taproot_cache.response_address_and_info(
+                &secp,
+                i as usize,
+                &circuit,
+                prover_response_key,
+                &challenge_hashes,
+            );
 
-        let outputs1 = vec![
-            TxOut {
-                script_pubkey: challenge_address.script_pubkey(),
-                value: Amount::from_sat(dust_limit),
-            },
-            TxOut {
-                script_pubkey: equivocation_address.script_pubkey(),
-                value: Amount::from_sat(amt - (2 * i + 1) * (fee + dust_limit)),
-            },
-        ];
-
-        let outputs2 = vec![
-            TxOut {
-                script_pubkey: response_address.script_pubkey(),
-                value: Amount::from_sat(dust_limit),
-            },
-            TxOut {
-                script_pubkey: response_second_address.script_pubkey(),
-                value: Amount::from_sat(amt - (2 * i + 2) * (fee + dust_limit)),
-            },
-        ];
-
-        let inputs = if i == 0 {
-            vec![TxIn {
-                previous_output: OutPoint {
-                    txid: initial_fund_txid,
-                    vout: initial_fund_tx.details[0].vout,
+            let outputs1 = vec![
+                TxOut {
+                    script_pubkey: challenge_address.script_pubkey(),
+                    value: DUST_LIMIT,
                 },
-                script_sig: ScriptBuf::new(),
-                sequence: bitcoin::transaction::Sequence::ENABLE_RBF_NO_LOCKTIME,
-                witness: Witness::new(),
-            }]
-        } else {
-            vec![
-                TxIn {
-                    previous_output: OutPoint {
-                        txid: last_txid,
-                        vout: 0,
-                    },
-                    script_sig: ScriptBuf::new(),
-                    sequence: bitcoin::transaction::Sequence::ENABLE_RBF_NO_LOCKTIME,
-                    witness: Witness::new(),
+                TxOut {
+                    script_pubkey: equivocation_address.script_pubkey(),
+                    value: fee_schedule.equivocation_value(funding, i),
                 },
-                TxIn {
-                    previous_output: OutPoint {
-                        txid: last_txid,
-                        vout: 1,
-                    },
+            ];
+
+            let outputs2 = vec![
+                TxOut {
+                    script_pubkey: response_address.script_pubkey(),
+                    value: DUST_LIMIT,
+                },
+                TxOut {
+                    script_pubkey: response_second_address.script_pubkey(),
+                    value: fee_schedule.response_second_value(funding, i),
+                },
+            ];
+
+            let inputs = if i == 0 {
+                vec![TxIn {
+                    previous_output: funding_outpoint,
                     script_sig: ScriptBuf::new(),
                     sequence: bitcoin::transaction::Sequence::ENABLE_RBF_NO_LOCKTIME,
                     witness: Witness::new(),
-                },
-            ]
-        };
+                }]
+            } else {
+                vec![
+                    TxIn {
+                        previous_output: OutPoint {
+                            txid: last_txid,
+                            vout: 0,
+                        },
+                        script_sig: ScriptBuf::new(),
+                        sequence: bitcoin::transaction::Sequence::ENABLE_RBF_NO_LOCKTIME,
+                        witness: Witness::new(),
+                    },
+                    TxIn {
+                        previous_output: OutPoint {
+                            txid: last_txid,
+                            vout: 1,
+                        },
+                        script_sig: ScriptBuf::new(),
+                        sequence: bitcoin::transaction::Sequence::ENABLE_RBF_NO_LOCKTIME,
+                        witness: Witness::new(),
+                    },
+                ]
+            };
 
-        let mut challenge_tx = Transaction {
-            version: bitcoin::transaction::Version::TWO,
-            lock_time: LockTime::from(Height::MIN),
-            input: inputs,
-            output: outputs1.clone(),
-        };
+            let mut challenge_tx = Transaction {
+                version: bitcoin::transaction::Version::TWO,
+                lock_time: LockTime::from(Height::MIN),
+                input: inputs,
+                output: with_anchor(&secp, &fee_schedule, &outputs1, prover_keys.funding),
+            };
+
+            if i != 0 {
+                // Verifier needs needs to give signature to prover so that prover can give a response
+                let mut sighash_cache = SighashCache::new(challenge_tx.borrow_mut());
+
+                let sig_hash = sighash_cache
+                    .taproot_script_spend_signature_hash(
+                        1_usize,
+                        &bitcoin::sighash::Prevouts::All(&last_output),
+                        TapLeafHash::from_script(
+                            &generate_2_of_2_script(prover_public_key, verifier_public_key),
+                            LeafVersion::TapScript,
+                        ),
+                        bitcoin::sighash::TapSighashType::Default,
+                    )
+                    .unwrap();
+                let sig = verifier.sign(sig_hash).unwrap();
+                let musig_2of2_script =
+                    generate_2_of_2_script(prover_public_key, verifier_public_key);
+                let (_, last_response_taproot_info) = taproot_cache
+                    .get(i as usize - 1, LeafSet::Response)
+                    .expect("Response tree of the previous round should be cached");
+                export_psbt(
+                    &format!("verifier-response-{}-presigned", i),
+                    &create_psbt(
+                        &challenge_tx,
+                        vec![
+                            InputSpend::new(last_output[0].clone(), &last_response_taproot_info),
+                            InputSpend::new(last_output[1].clone(), &response_second_taproot_info)
+                                .with_leaf(musig_2of2_script.clone())
+                                .with_signature(verifier_public_key, musig_2of2_script, sig),
+                        ],
+                    ),
+                );
+                send_message(&mut ws_stream, &sig).await.unwrap();
+            } else {
+                kickoff_tx = challenge_tx.clone();
+            }
 
-        if i != 0 {
-            // Verifier needs needs to give signature to prover so that prover can give a response
-            let mut sighash_cache = SighashCache::new(challenge_tx.borrow_mut());
+            let mut response_tx = Transaction {
+                version: bitcoin::transaction::Version::TWO,
+                lock_time: LockTime::from(Height::MIN),
+                input: vec![
+                    TxIn {
+                        previous_output: OutPoint {
+                            txid: challenge_tx.txid(),
+                            vout: 0,
+                        },
+                        script_sig: ScriptBuf::new(),
+                        sequence: bitcoin::transaction::Sequence::ENABLE_RBF_NO_LOCKTIME,
+                        witness: Witness::new(),
+                    },
+                    TxIn {
+                        previous_output: OutPoint {
+                            txid: challenge_tx.txid(),
+                            vout: 1,
+                        },
+                        script_sig: ScriptBuf::new(),
+                        sequence: bitcoin::transaction::Sequence::ENABLE_RBF_NO_LOCKTIME,
+                        witness: Witness::new(),
+                    },
+                ],
+                output: with_anchor(&secp, &fee_schedule, &outputs2, verifier.funding_public_key),
+            };
+            // Prover needs to give signature to verifier so that verifier can start a challenge
+            let challenge_sig: Signature = receive_message(&mut ws_stream).await.unwrap();
+            verifier.add_signature(challenge_sig);
+            debug!(signature = %challenge_sig, "received the presigned challenge");
+            // Verify needs to verify the signature
+            let mut sighash_cache = SighashCache::new(response_tx.borrow_mut());
 
             let sig_hash = sighash_cache
                 .taproot_script_spend_signature_hash(
                     1_usize,
-                    &bitcoin::sighash::Prevouts::All(&last_output),
+                    &bitcoin::sighash::Prevouts::All(&outputs1),
                     TapLeafHash::from_script(
                         &generate_2_of_2_script(prover_public_key, verifier_public_key),
                         LeafVersion::TapScript,
@@ -200,323 +368,550 @@ async fn handle_connection(stream: TcpStream) {
                     bitcoin::sighash::TapSighashType::Default,
                 )
                 .unwrap();
-            let sig = verifier.sign(sig_hash);
-            send_message(&mut ws_stream, &sig).await.unwrap();
-        } else {
-            kickoff_tx = challenge_tx.clone();
-        }
-
-        let mut response_tx = Transaction {
-            version: bitcoin::transaction::Version::TWO,
-            lock_time: LockTime::from(Height::MIN),
-            input: vec![
-                TxIn {
-                    previous_output: OutPoint {
-                        txid: challenge_tx.txid(),
-                        vout: 0,
-                    },
-                    script_sig: ScriptBuf::new(),
-                    sequence: bitcoin::transaction::Sequence::ENABLE_RBF_NO_LOCKTIME,
-                    witness: Witness::new(),
-                },
-                TxIn {
-                    previous_output: OutPoint {
-                        txid: challenge_tx.txid(),
-                        vout: 1,
-                    },
-                    script_sig: ScriptBuf::new(),
-                    sequence: bitcoin::transaction::Sequence::ENABLE_RBF_NO_LOCKTIME,
-                    witness: Witness::new(),
-                },
-            ],
-            output: outputs2.clone(),
-        };
-        // Prover needs to give signature to verifier so that verifier can start a challenge
-        let challenge_sig: Signature = receive_message(&mut ws_stream).await.unwrap();
-        verifier.add_signature(challenge_sig);
-        println!("Challenge Sig: {:?}", challenge_sig);
-        // Verify needs to verify the signature
-        let mut sighash_cache = SighashCache::new(response_tx.borrow_mut());
-
-        let sig_hash = sighash_cache
-            .taproot_script_spend_signature_hash(
-                1_usize,
-                &bitcoin::sighash::Prevouts::All(&outputs1),
-                TapLeafHash::from_script(
-                    &generate_2_of_2_script(prover_public_key, verifier_public_key),
-                    LeafVersion::TapScript,
-                ),
-                bitcoin::sighash::TapSighashType::Default,
+            secp.verify_schnorr(
+                &challenge_sig,
+                &Message::from_digest_slice(sig_hash.as_byte_array()).expect("should be hash"),
+                &prover_public_key,
             )
             .unwrap();
-        secp.verify_schnorr(
-            &challenge_sig,
-            &Message::from_digest_slice(sig_hash.as_byte_array()).expect("should be hash"),
-            &prover_public_key,
-        )
-        .unwrap();
+            let musig_2of2_script = generate_2_of_2_script(prover_public_key, verifier_public_key);
+            export_psbt(
+                &format!("verifier-challenge-{}-presigned", i),
+                &create_psbt(
+                    &response_tx,
+                    vec![
+                        InputSpend::new(outputs1[0].clone(), &challenge_taproot_info),
+                        InputSpend::new(outputs1[1].clone(), &equivocation_taproot_info)
+                            .with_leaf(musig_2of2_script.clone())
+                            .with_signature(prover_public_key, musig_2of2_script, challenge_sig),
+                    ],
+                ),
+            );
+
+            if watchtower.is_some() {
+                watched_rounds.push(
+                    presign_slashes(
+                        &verifier,
+                        i,
+                        challenge_tx.txid(),
+                        response_tx.txid(),
+                        outputs1[1].clone(),
+                        TxOut {
+                            script_pubkey: verifier.address.script_pubkey(),
+                            value: outputs1[1].value - fee_schedule.slash,
+                        },
+                        &watched,
+                    )
+                    .unwrap(),
+                );
+            }
 
-        last_output = outputs2;
-        last_txid = response_tx.txid();
+            last_output = outputs2;
+            last_txid = response_tx.txid();
+        }
+        .instrument(info_span!("round", round = i, phase = "presign"))
+        .await;
+    }
+    info!("presigning completed");
+    if let Some(url) = &watchtower {
+        let package = WatchtowerPackage {
+            funding: funding_outpoint,
+            verifier_public_key,
+            wires: watched,
+            rounds: watched_rounds,
+        };
+        register_with_watchtower(url, &package)
+            .await
+            .unwrap_or_else(|e| panic!("Failed to register with the watchtower: {}", e));
     }
-    println!("Bisection completed");
     let kickoff_txid: Txid = receive_message(&mut ws_stream).await.unwrap();
     if kickoff_tx.txid() != kickoff_txid {
         panic!("Kickoff txid mismatch!");
     }
+    report.add_transaction("kickoff", None, kickoff_txid);
 
     let mut challenge_gate_num: usize = 0;
-    last_txid = initial_fund_txid;
+    last_txid = funding_outpoint.txid;
     for i in 0..bisection_length as u64 {
-        let challenge_hashes: Vec<HashValue> = verifier.get_challenge_hashes(i as usize);
+        let ended = async {
+            let challenge_hashes: Vec<HashValue> = verifier.get_challenge_hashes(i as usize);
+
+            let (challenge_address, challenge_taproot_info) = taproot_cache
+                .challenge_address_and_info(
+                    &secp,
+                    i as usize,
+                    &circuit,
+                    prover_public_key,
+                    verifier_public_key,
+                    &challenge_hashes,
+                );
 
-        let (challenge_address, challenge_taproot_info) = generate_challenge_address_and_info(
-            &secp,
-            &circuit,
-            prover_public_key,
-            verifier_public_key,
-            &challenge_hashes,
-        );
+            let (response_address, _) = taproot_cache.response_address_and_info(
+                &secp,
+                i as usize,
+                &circuit,
+                prover_response_key,
+                &challenge_hashes,
+            );
 
-        let (response_address, _) = generate_response_address_and_info(
-            &secp,
-            &circuit,
-            prover_public_key,
-            &challenge_hashes,
-        );
+            let outputs1 = vec![
+                TxOut {
+                    script_pubkey: challenge_address.script_pubkey(),
+                    value: DUST_LIMIT,
+                },
+                TxOut {
+                    script_pubkey: equivocation_address.script_pubkey(),
+                    value: fee_schedule.equivocation_value(funding, i),
+                },
+            ];
 
-        let outputs1 = vec![
-            TxOut {
-                script_pubkey: challenge_address.script_pubkey(),
-                value: Amount::from_sat(dust_limit),
-            },
-            TxOut {
-                script_pubkey: equivocation_address.script_pubkey(),
-                value: Amount::from_sat(amt - (2 * i + 1) * (fee + dust_limit)),
-            },
-        ];
-
-        let outputs2 = vec![
-            TxOut {
-                script_pubkey: response_address.script_pubkey(),
-                value: Amount::from_sat(dust_limit),
-            },
-            TxOut {
-                script_pubkey: response_second_address.script_pubkey(),
-                value: Amount::from_sat(amt - (2 * i + 2) * (fee + dust_limit)),
-            },
-        ];
-
-        let inputs = if i == 0 {
-            vec![TxIn {
-                previous_output: OutPoint {
-                    txid: initial_fund_txid,
-                    vout: initial_fund_tx.details[0].vout,
+            let outputs2 = vec![
+                TxOut {
+                    script_pubkey: response_address.script_pubkey(),
+                    value: DUST_LIMIT,
                 },
-                script_sig: ScriptBuf::new(),
-                sequence: bitcoin::transaction::Sequence::ENABLE_RBF_NO_LOCKTIME,
-                witness: Witness::new(),
-            }]
-        } else {
-            vec![
-                TxIn {
-                    previous_output: OutPoint {
-                        txid: last_txid,
-                        vout: 0,
-                    },
-                    script_sig: ScriptBuf::new(),
-                    sequence: bitcoin::transaction::Sequence::ENABLE_RBF_NO_LOCKTIME,
-                    witness: Witness::new(),
+                TxOut {
+                    script_pubkey: response_second_address.script_pubkey(),
+                    value: fee_schedule.response_second_value(funding, i),
                 },
-                TxIn {
-                    previous_output: OutPoint {
-                        txid: last_txid,
-                        vout: 1,
-                    },
+            ];
+
+            let inputs = if i == 0 {
+                vec![TxIn {
+                    previous_output: funding_outpoint,
                     script_sig: ScriptBuf::new(),
                     sequence: bitcoin::transaction::Sequence::ENABLE_RBF_NO_LOCKTIME,
                     witness: Witness::new(),
-                },
-            ]
-        };
+                }]
+            } else {
+                vec![
+                    TxIn {
+                        previous_output: OutPoint {
+                            txid: last_txid,
+                            vout: 0,
+                        },
+                        script_sig: ScriptBuf::new(),
+                        sequence: bitcoin::transaction::Sequence::ENABLE_RBF_NO_LOCKTIME,
+                        witness: Witness::new(),
+                    },
+                    TxIn {
+                        previous_output: OutPoint {
+                            txid: last_txid,
+                            vout: 1,
+                        },
+                        script_sig: ScriptBuf::new(),
+                        sequence: bitcoin::transaction::Sequence::ENABLE_RBF_NO_LOCKTIME,
+                        witness: Witness::new(),
+                    },
+                ]
+            };
 
-        let challenge_tx = Transaction {
-            version: bitcoin::transaction::Version::TWO,
-            lock_time: LockTime::from(Height::MIN),
-            input: inputs,
-            output: outputs1.clone(),
-        };
+            let challenge_tx = Transaction {
+                version: bitcoin::transaction::Version::TWO,
+                lock_time: LockTime::from(Height::MIN),
+                input: inputs,
+                output: with_anchor(&secp, &fee_schedule, &outputs1, prover_keys.funding),
+            };
 
-        let mut found_contradiction: Option<Wire> = None;
-
-        if i != 0 {
-            // Verifier needs needs to give signature to prover so that prover can give a response
-            println!("Waiting for prover's response...");
-            let provers_response =
-                watch_transaction(&rpc, &challenge_tx.txid(), watch_interval).unwrap();
-            let num_wires = circuit.gates[challenge_gate_num].get_input_size()
-                + circuit.gates[challenge_gate_num].get_output_size();
-            let mut witness: Vec<PreimageValue> = Vec::new();
-            for i in 0..num_wires {
-                witness.push(
-                    provers_response.input[0]
-                        .witness
-                        .nth(i + 1)
-                        .unwrap()
-                        .try_into()
-                        .expect("Slice with incorrect length"),
+            let mut found_fraud: Option<Fraud> = None;
+
+            if i != 0 {
+                // Verifier needs needs to give signature to prover so that prover can give a response
+                info!("waiting for the prover's response");
+                let provers_response =
+                    watch_transaction(&rpc, &challenge_tx.txid(), watch_interval)
+                        .await
+                        .unwrap();
+                report.add_response(
+                    provers_response.txid(),
+                    response_reveals(
+                        circuit.gates[challenge_gate_num].as_ref(),
+                        &provers_response.input[0].witness,
+                    ),
                 );
+                let num_wires = circuit.gates[challenge_gate_num].get_input_size()
+                    + circuit.gates[challenge_gate_num].get_output_size();
+                // Skip the signature, the reveals are checked before being recorded
+                let witness = provers_response.input[0]
+                    .witness
+                    .iter()
+                    .skip(1)
+                    .take(num_wires * HashCommitment::REVEAL_WITNESS_LEN)
+                    .map(|element| element.to_vec())
+                    .collect::<Vec<Vec<u8>>>();
+
+                let mut revealed = HashSet::new();
+                match circuit.gates[challenge_gate_num].add_preimages_from_witness(&witness) {
+                    Ok(outcomes) => {
+                        let (inputs, outputs) = circuit.gates[challenge_gate_num].get_wires();
+                        for (wire, outcome) in inputs.iter().chain(outputs).zip(&outcomes) {
+                            if let RevealOutcome::NewBit(_) = outcome {
+                                revealed.insert(wire.lock().unwrap().index.unwrap());
+                            }
+                        }
+                        found_fraud = outcomes.into_iter().find_map(|outcome| match outcome {
+                            RevealOutcome::Equivocation(wire) => Some(Fraud::Equivocation(wire)),
+                            _ => None,
+                        })
+                    }
+                    Err(e) => {
+                        warn!(error = ?e, "ignoring invalid reveals in the prover's response")
+                    }
+                }
+                // The new reveals may complete the wires of a gate whose outputs are wrong, which
+                // can only be a gate with one of those wires
+                if found_fraud.is_none() {
+                    found_fraud = circuit
+                        .gates
+                        .iter_mut()
+                        .enumerate()
+                        .filter(|(_, gate)| {
+                            let (inputs, outputs) = gate.get_wires();
+                            inputs
+                                .iter()
+                                .chain(outputs)
+                                .any(|wire| revealed.contains(&wire.lock().unwrap().index.unwrap()))
+                        })
+                        .find_map(|(gate_num, gate)| {
+                            gate.find_wrong_output()
+                                .map(|proof| Fraud::WrongOutput(gate_num, proof))
+                        });
+                }
             }
 
-            found_contradiction =
-                circuit.gates[challenge_gate_num].add_preimages_from_witness(witness);
-        }
-
-        let mut response_tx = Transaction {
-            version: bitcoin::transaction::Version::TWO,
-            lock_time: LockTime::from(Height::MIN),
-            input: vec![
-                TxIn {
-                    previous_output: OutPoint {
-                        txid: challenge_tx.txid(),
-                        vout: 0,
+            let mut response_tx = Transaction {
+                version: bitcoin::transaction::Version::TWO,
+                lock_time: LockTime::from(Height::MIN),
+                input: vec![
+                    TxIn {
+                        previous_output: OutPoint {
+                            txid: challenge_tx.txid(),
+                            vout: 0,
+                        },
+                        script_sig: ScriptBuf::new(),
+                        sequence: bitcoin::transaction::Sequence::ENABLE_RBF_NO_LOCKTIME,
+                        witness: Witness::new(),
                     },
-                    script_sig: ScriptBuf::new(),
-                    sequence: bitcoin::transaction::Sequence::ENABLE_RBF_NO_LOCKTIME,
-                    witness: Witness::new(),
-                },
-                TxIn {
-                    previous_output: OutPoint {
-                        txid: challenge_tx.txid(),
-                        vout: 1,
+                    TxIn {
+                        previous_output: OutPoint {
+                            txid: challenge_tx.txid(),
+                            vout: 1,
+                        },
+                        script_sig: ScriptBuf::new(),
+                        sequence: bitcoin::transaction::Sequence::ENABLE_RBF_NO_LOCKTIME,
+                        witness: Witness::new(),
                     },
-                    script_sig: ScriptBuf::new(),
-                    sequence: bitcoin::transaction::Sequence::ENABLE_RBF_NO_LOCKTIME,
-                    witness: Witness::new(),
-                },
-            ],
-            output: outputs2.clone(),
-        };
+                ],
+                output: with_anchor(&secp, &fee_schedule, &outputs2, verifier.funding_public_key),
+            };
 
-        if let Some(value) = found_contradiction {
-            let mut steal_tx = Transaction {
-                version: bitcoin::transaction::Version::TWO,
-                lock_time: LockTime::from(Height::MIN),
-                input: vec![TxIn {
-                    previous_output: OutPoint {
+            if let Some(fraud) = found_fraud {
+                let mut steal_tx = Transaction {
+                    version: bitcoin::transaction::Version::TWO,
+                    lock_time: LockTime::from(Height::MIN),
+                    input: vec![TxIn {
+                        previous_output: OutPoint {
+                            txid: challenge_tx.txid(),
+                            vout: 1,
+                        },
+                        script_sig: ScriptBuf::new(),
+                        sequence: bitcoin::transaction::Sequence::ENABLE_RBF_NO_LOCKTIME,
+                        witness: Witness::new(),
+                    }],
+                    output: vec![TxOut {
+                        script_pubkey: verifier.address.script_pubkey(),
+                        value: outputs1[1].value - fee_schedule.slash,
+                    }],
+                };
+                let fraud_script = match &fraud {
+                    Fraud::Equivocation(wire) => {
+                        warn!(wire = wire.index, "prover equivocated, slashing");
+                        report.fraud = Some(FraudProof::Equivocation {
+                            wire: wire.index.unwrap(),
+                            zero: wire.reveals.zero.unwrap(),
+                            one: wire.reveals.one.unwrap(),
+                        });
+                        generate_anti_contradiction_script::<HashCommitment>(
+                            &wire.get_commitment(),
+                            verifier_public_key,
+                        )
+                    }
+                    Fraud::WrongOutput(gate_num, _) => {
+                        warn!(chunk = gate_num, "prover gave a wrong output, slashing");
+                        report.fraud = Some(FraudProof::WrongOutput { chunk: *gate_num });
+                        generate_wrong_output_script(&circuit.gates[*gate_num], verifier_public_key)
+                    }
+                };
+                let mut sighash_cache = SighashCache::new(steal_tx.borrow_mut());
+
+                let sig_hash = sighash_cache
+                    .taproot_script_spend_signature_hash(
+                        0,
+                        &bitcoin::sighash::Prevouts::All(&[outputs1[1].clone()]),
+                        TapLeafHash::from_script(&fraud_script, LeafVersion::TapScript),
+                        bitcoin::sighash::TapSighashType::Default,
+                    )
+                    .unwrap();
+
+                let fraud_sig = verifier.sign(sig_hash).unwrap();
+
+                let fraud_control_block = equivocation_taproot_info
+                    .control_block(&(fraud_script.clone(), LeafVersion::TapScript))
+                    .expect("Cannot create control block");
+
+                *sighash_cache.witness_mut(0).unwrap() = match &fraud {
+                    Fraud::Equivocation(wire) => generate_anti_contradiction_witness(
+                        &fraud_sig,
+                        wire,
+                        &fraud_script,
+                        &fraud_control_block,
+                    )
+                    .unwrap(),
+                    Fraud::WrongOutput(_, proof) => generate_wrong_output_witness(
+                        &fraud_sig,
+                        proof,
+                        &fraud_script,
+                        &fraud_control_block,
+                    ),
+                };
+
+                export_psbt(
+                    "verifier-slash",
+                    &create_psbt(
+                        &steal_tx,
+                        vec![
+                            InputSpend::new(outputs1[1].clone(), &equivocation_taproot_info)
+                                .with_leaf(fraud_script.clone())
+                                .with_signature(verifier_public_key, fraud_script, fraud_sig),
+                        ],
+                    ),
+                );
+
+                let slash_txid = broadcast_transaction(&rpc, &steal_tx, "slash").await;
+                report.add_transaction("slash", Some(i), slash_txid);
+                for (round, txid) in fee_bumper.finish().await {
+                    report.add_transaction("cpfp", round, txid);
+                }
+                report.write(Outcome::Slashed { txid: slash_txid });
+                return true;
+            }
+            // Prover needs to give signature to verifier so that verifier can start a challenge
+            let decision = session.next_challenge(circuit.num_gates()).await;
+            if decision == Challenge::Close && !can_close {
+                send_message(&mut ws_stream, &RoundDecision::Finish)
+                    .await
+                    .unwrap();
+                info!("stopped challenging without a cooperative close");
+                for (round, txid) in fee_bumper.finish().await {
+                    report.add_transaction("cpfp", round, txid);
+                }
+                report.write(Outcome::Finished);
+                return true;
+            }
+            if decision == Challenge::Close {
+                send_message(&mut ws_stream, &RoundDecision::Close)
+                    .await
+                    .unwrap();
+                // The game is undisputed, so everything left in the equivocation output goes back to the prover
+                let prover_address =
+                    Address::p2tr(&secp, prover_keys.funding, None, bitcoin::Network::Regtest);
+                let close_tx = create_cooperative_close_tx(
+                    OutPoint {
                         txid: challenge_tx.txid(),
                         vout: 1,
                     },
-                    script_sig: ScriptBuf::new(),
-                    sequence: bitcoin::transaction::Sequence::ENABLE_RBF_NO_LOCKTIME,
-                    witness: Witness::new(),
-                }],
-                output: vec![TxOut {
-                    script_pubkey: verifier.address.script_pubkey(),
-                    value: Amount::from_sat(amt - (2 * i + 2) * (fee + dust_limit)),
-                }],
-            };
-            println!("Contraditcion found! Slashing the prover!");
-            let mut sighash_cache = SighashCache::new(steal_tx.borrow_mut());
+                    vec![TxOut {
+                        script_pubkey: prover_address.script_pubkey(),
+                        value: outputs1[1].value - fee_schedule.close,
+                    }],
+                );
+                let sig_hash = cooperative_close_sighash(&close_tx, &outputs1[1]).unwrap();
+                let key_agg_ctx = KeyAggContext::new(
+                    &secp,
+                    &[prover_public_key, verifier_public_key],
+                    equivocation_taproot_info.merkle_root(),
+                );
+                let (secret_nonce, public_nonce) = generate_nonce(&secp);
+                send_message(&mut ws_stream, &public_nonce).await.unwrap();
+                let prover_nonce: PublicNonce = receive_message(&mut ws_stream).await.unwrap();
+                let session = key_agg_ctx.session(
+                    &secp,
+                    &[prover_nonce, public_nonce],
+                    sig_hash.to_byte_array(),
+                );
+                let partial_sig = verifier.musig_partial_sign(&session, secret_nonce);
+                send_message(&mut ws_stream, &partial_sig).await.unwrap();
+                let close_txid: Txid = receive_message(&mut ws_stream).await.unwrap();
+                info!(%close_txid, "game closed cooperatively");
+                report.add_transaction("close", Some(i), close_txid);
+                for (round, txid) in fee_bumper.finish().await {
+                    report.add_transaction("cpfp", round, txid);
+                }
+                report.write(Outcome::Closed { txid: close_txid });
+                return true;
+            }
+            send_message(&mut ws_stream, &RoundDecision::Challenge)
+                .await
+                .unwrap();
+            if let Challenge::Chunk(chunk) = decision {
+                challenge_gate_num = chunk;
+            }
+            let musig_presigned_by_prover = verifier.get_signature(i as usize);
 
-            let equivocation_script = generate_anti_contradiction_script(
-                value.clone().get_hash_pair(),
+            // println!("challenge sig: {:?}", challenge_sig);
+            // Verify needs to verify the signature
+            let mut sighash_cache = SighashCache::new(response_tx.borrow_mut());
+
+            // Now we need to sign with the challenge_gate_num preimage to reveal a challenge
+            let challenge_script = generate_challenge_script(
+                prover_public_key,
                 verifier_public_key,
+                &challenge_hashes[challenge_gate_num],
             );
+            let musig_2of2_script = generate_2_of_2_script(prover_public_key, verifier_public_key);
+
             let sig_hash = sighash_cache
                 .taproot_script_spend_signature_hash(
                     0,
-                    &bitcoin::sighash::Prevouts::All(&[outputs1[1].clone()]),
-                    TapLeafHash::from_script(&equivocation_script, LeafVersion::TapScript),
+                    &bitcoin::sighash::Prevouts::All(&outputs1),
+                    TapLeafHash::from_script(&challenge_script, LeafVersion::TapScript),
                     bitcoin::sighash::TapSighashType::Default,
                 )
                 .unwrap();
+            let verifier_challenge_sig = verifier.sign(sig_hash).unwrap();
 
-            let equivocation_sig = verifier.sign(sig_hash);
+            let sig_hash = sighash_cache
+                .taproot_script_spend_signature_hash(
+                    1,
+                    &bitcoin::sighash::Prevouts::All(&outputs1),
+                    TapLeafHash::from_script(&musig_2of2_script, LeafVersion::TapScript),
+                    bitcoin::sighash::TapSighashType::Default,
+                )
+                .unwrap();
+            let verifier_2of2_sig = verifier.sign(sig_hash).unwrap();
+            let challenge_preimage =
+                verifier.get_challenge_preimage(i as usize, challenge_gate_num);
 
-            let equivocation_control_block = equivocation_taproot_info
-                .control_block(&(equivocation_script.clone(), LeafVersion::TapScript))
+            let challenge_control_block = challenge_taproot_info
+                .control_block(&(challenge_script.clone(), LeafVersion::TapScript))
                 .expect("Cannot create control block");
 
-            let witness = sighash_cache.witness_mut(0).unwrap();
-            witness.push(equivocation_sig.as_ref());
-            witness.push(value.clone().preimages.unwrap().one.unwrap());
-            witness.push(value.preimages.unwrap().zero.unwrap());
-            witness.push(equivocation_script);
-            witness.push(&equivocation_control_block.serialize());
-
-            let steal_txid = rpc
-                .send_raw_transaction(&steal_tx)
-                .unwrap_or_else(|e| panic!("Failed to send raw transaction: {}", e));
-            println!("Verifier slashed the prover: {:?}", steal_txid);
-            std::process::exit(0);
-        }
-        // Prover needs to give signature to verifier so that verifier can start a challenge
-        challenge_gate_num =
-            take_stdin("Enter your challenge gate if you want to challenge the prover\n").unwrap();
-        let musig_presigned_by_prover = verifier.get_signature(i as usize);
-
-        // println!("challenge sig: {:?}", challenge_sig);
-        // Verify needs to verify the signature
-        let mut sighash_cache = SighashCache::new(response_tx.borrow_mut());
-
-        // Now we need to sign with the challenge_gate_num preimage to reveal a challenge
-        let challenge_script = generate_challenge_script(
-            prover_public_key,
-            verifier_public_key,
-            &challenge_hashes[challenge_gate_num],
-        );
-        let musig_2of2_script = generate_2_of_2_script(prover_public_key, verifier_public_key);
-
-        let sig_hash = sighash_cache
-            .taproot_script_spend_signature_hash(
-                0,
-                &bitcoin::sighash::Prevouts::All(&outputs1),
-                TapLeafHash::from_script(&challenge_script, LeafVersion::TapScript),
-                bitcoin::sighash::TapSighashType::Default,
-            )
-            .unwrap();
-        let verifier_challenge_sig = verifier.sign(sig_hash);
-
-        let sig_hash = sighash_cache
-            .taproot_script_spend_signature_hash(
-                1,
-                &bitcoin::sighash::Prevouts::All(&outputs1),
-                TapLeafHash::from_script(&musig_2of2_script, LeafVersion::TapScript),
-                bitcoin::sighash::TapSighashType::Default,
-            )
-            .unwrap();
-        let verifier_2of2_sig = verifier.sign(sig_hash);
-        let challenge_preimage = verifier.get_challenge_preimage(i as usize, challenge_gate_num);
-
-        let challenge_control_block = challenge_taproot_info
-            .control_block(&(challenge_script.clone(), LeafVersion::TapScript))
-            .expect("Cannot create control block");
+            let musig_control_block = equivocation_taproot_info
+                .control_block(&(musig_2of2_script.clone(), LeafVersion::TapScript))
+                .expect("Cannot create control block");
 
-        let musig_control_block = equivocation_taproot_info
-            .control_block(&(musig_2of2_script.clone(), LeafVersion::TapScript))
-            .expect("Cannot create control block");
+            *sighash_cache.witness_mut(0).unwrap() = generate_challenge_witness(
+                &verifier_challenge_sig,
+                &challenge_preimage,
+                &challenge_script,
+                &challenge_control_block,
+            );
+            *sighash_cache.witness_mut(1).unwrap() = generate_2_of_2_witness(
+                &musig_presigned_by_prover,
+                &verifier_2of2_sig,
+                &musig_2of2_script,
+                &musig_control_block,
+            );
 
-        let witness0 = sighash_cache.witness_mut(0).unwrap();
-        witness0.push(verifier_challenge_sig.as_ref());
-        witness0.push(challenge_preimage);
-        witness0.push(challenge_script);
-        witness0.push(&challenge_control_block.serialize());
+            export_psbt(
+                &format!("verifier-challenge-{}", i),
+                &create_psbt(
+                    &response_tx,
+                    vec![
+                        InputSpend::new(outputs1[0].clone(), &challenge_taproot_info)
+                            .with_leaf(challenge_script.clone())
+                            .with_signature(
+                                verifier_public_key,
+                                challenge_script.clone(),
+                                verifier_challenge_sig,
+                            ),
+                        InputSpend::new(outputs1[1].clone(), &equivocation_taproot_info)
+                            .with_leaf(musig_2of2_script.clone())
+                            .with_signature(
+                                prover_public_key,
+                                musig_2of2_script.clone(),
+                                musig_presigned_by_prover,
+                            )
+                            .with_signature(
+                                verifier_public_key,
+                                musig_2of2_script.clone(),
+                                verifier_2of2_sig,
+                            ),
+                    ],
+                ),
+            );
 
-        let witness1 = sighash_cache.witness_mut(1).unwrap();
-        witness1.push(verifier_2of2_sig.as_ref());
-        witness1.push(musig_presigned_by_prover.as_ref());
-        witness1.push(musig_2of2_script);
-        witness1.push(&musig_control_block.serialize());
+            let response_txid = broadcast_transaction(&rpc, &response_tx, "challenge").await;
+            report.add_challenge(
+                i,
+                challenge_gate_num,
+                challenge_hashes[challenge_gate_num],
+                response_txid,
+            );
 
-        let response_txid = rpc
-            .send_raw_transaction(&response_tx)
-            .unwrap_or_else(|e| panic!("Failed to send raw transaction: {}", e));
+            fee_bumper.watch(&response_tx, fee_schedule.challenge, Some(i));
 
-        println!("Challenge transaction sent! txid: {:?}", response_txid);
+            // If the prover never responds, the second output goes to the verifier once the timelock
+            // of the response second tree expires. The slash fee covers its smaller witness.
+            let timeout_script = generate_timelock_script(verifier_public_key, TIMELOCK_BLOCKS);
+            let mut timeout_tx = Transaction {
+                version: bitcoin::transaction::Version::TWO,
+                lock_time: LockTime::from(Height::MIN),
+                input: vec![TxIn {
+                    previous_output: OutPoint {
+                        txid: response_txid,
+                        vout: 1,
+                    },
+                    script_sig: ScriptBuf::new(),
+                    sequence: bitcoin::transaction::Sequence::from_height(TIMELOCK_BLOCKS as u16),
+                    witness: Witness::new(),
+                }],
+                output: vec![TxOut {
+                    script_pubkey: verifier.address.script_pubkey(),
+                    value: outputs2[1].value - fee_schedule.slash,
+                }],
+            };
+            let sig_hash = SighashCache::new(&timeout_tx)
+                .taproot_script_spend_signature_hash(
+                    0,
+                    &bitcoin::sighash::Prevouts::All(&[outputs2[1].clone()]),
+                    TapLeafHash::from_script(&timeout_script, LeafVersion::TapScript),
+                    bitcoin::sighash::TapSighashType::Default,
+                )
+                .unwrap();
+            let timeout_sig = verifier.sign(sig_hash).unwrap();
+            timeout_tx.input[0].witness = generate_timelock_witness(
+                &timeout_sig,
+                &timeout_script,
+                &response_second_taproot_info
+                    .control_block(&(timeout_script.clone(), LeafVersion::TapScript))
+                    .expect("Cannot create control block"),
+            );
+            export_psbt(
+                &format!("verifier-timeout-{}", i),
+                &create_psbt(
+                    &timeout_tx,
+                    vec![
+                        InputSpend::new(outputs2[1].clone(), &response_second_taproot_info)
+                            .with_leaf(timeout_script.clone())
+                            .with_signature(verifier_public_key, timeout_script, timeout_sig),
+                    ],
+                ),
+            );
 
-        last_output = outputs2;
-        last_txid = response_tx.txid();
+            last_output = outputs2;
+            last_txid = response_tx.txid();
+            false
+        }
+        .instrument(info_span!("round", round = i, phase = "dispute"))
+        .await;
+        if ended {
+            return;
+        }
+    }
+    info!("game finished without a fraud");
+    for (round, txid) in fee_bumper.finish().await {
+        report.add_transaction("cpfp", round, txid);
     }
-    println!("Last output: {:?}", last_output);
+    report.write(Outcome::Finished);
 }
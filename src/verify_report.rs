@@ -0,0 +1,31 @@
+use std::env;
+use std::process;
+
+use toy_bitvm::report::GameReport;
+use toy_bitvm::transactions::rpc::rpc_client;
+
+// Checks a game report written by the prover or the verifier against the chain of the node
+fn main() {
+    let path = env::args()
+        .nth(1)
+        .unwrap_or_else(|| panic!("Usage: verify-report <report.json>"));
+    let report = GameReport::load(&path)
+        .unwrap_or_else(|e| panic!("Failed to read the report {}: {}", path, e));
+
+    let rpc = rpc_client();
+
+    let problems = report.verify(&rpc);
+    if problems.is_empty() {
+        println!(
+            "Report verified: {} transactions, {} challenges, outcome {:?}",
+            report.transactions.len(),
+            report.challenges.len(),
+            report.outcome
+        );
+    } else {
+        for problem in &problems {
+            println!("{}", problem);
+        }
+        process::exit(1);
+    }
+}
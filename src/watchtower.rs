@@ -0,0 +1,58 @@
+use std::time::Duration;
+
+use bitcoin::secp256k1::Secp256k1;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::accept_async;
+use toy_bitvm::{
+    communication::{receive_message, send_message},
+    logging::init_logging,
+    tower::{Watchtower, WatchtowerPackage},
+    transactions::rpc::rpc_client,
+};
+use tracing::{field, info, info_span, warn, Instrument, Span};
+
+// Watches games on behalf of verifiers, slashing the prover of any game it sees equivocating
+#[tokio::main]
+async fn main() {
+    init_logging();
+    let listener = TcpListener::bind("127.0.0.1:9001").await.unwrap();
+    info!(address = "127.0.0.1:9001", "listening");
+
+    while let Ok((stream, peer)) = listener.accept().await {
+        tokio::spawn(handle_connection(stream).instrument(info_span!(
+            "session",
+            role = "watchtower",
+            %peer,
+            game = field::Empty
+        )));
+    }
+}
+
+async fn handle_connection(stream: TcpStream) {
+    let mut ws_stream = accept_async(stream)
+        .await
+        .expect("Error during the websocket handshake occurred");
+
+    let package: WatchtowerPackage = receive_message(&mut ws_stream).await.unwrap();
+    Span::current().record("game", field::display(package.funding));
+    let mut tower = match Watchtower::new(&Secp256k1::verification_only(), package) {
+        Ok(tower) => tower,
+        Err(e) => {
+            warn!(error = e, "rejected the package");
+            send_message(&mut ws_stream, &false).await.unwrap();
+            return;
+        }
+    };
+    send_message(&mut ws_stream, &true).await.unwrap();
+    info!("watching the game");
+
+    // Polling the node blocks, so it gets a thread of its own
+    let span = Span::current();
+    tokio::task::spawn_blocking(move || {
+        let _span = span.enter();
+        let rpc = rpc_client();
+        tower.watch(&rpc, Duration::from_secs(1))
+    })
+    .await
+    .unwrap();
+}